@@ -17,7 +17,29 @@ pub fn kernel_stack_position(app_id: usize) -> (usize, usize) {
 }
 
 pub const CLOCK_FREQ: usize = 12500000;
+/// Time slice of the round-robin scheduler in ticks
+pub const RR_TIME_SLICE: usize = 1;
+/// Number of levels of the multi-level feedback queue
+pub const MLFQ_LEVELS: usize = 3;
+/// Ticks between two priority boosts of the multi-level feedback queue
+pub const MLFQ_BOOST_PERIOD: usize = 100;
+/// Period in timer cycles in which every runnable task of the CFS scheduler runs once
+pub const CFS_SCHED_LATENCY: usize = CLOCK_FREQ / 50;
+/// Minimum time in timer cycles a task of the CFS scheduler runs before being preempted
+pub const CFS_MIN_GRANULARITY: usize = CLOCK_FREQ / 250;
+/// Time slice of `SCHED_RR` real-time tasks in ticks
+pub const RT_RR_TIME_SLICE: usize = 10;
+/// Throttling period of real-time tasks in ticks
+pub const RT_PERIOD: usize = 100;
+/// Ticks of every `RT_PERIOD` real-time tasks may use while normal tasks wait
+pub const RT_RUNTIME: usize = 95;
 pub type BlockDeviceImpl = crate::drivers::block::VirtIOBlock;
+/// Number of 512-byte blocks of the disk image made by easy-fs-fuse
+pub const BLOCK_DEVICE_BLOCKS: usize = 16 * 2048;
+/// Hard limit of `RLIMIT_NOFILE`, the number of fds a process may have
+pub const MAX_FD_NUM: usize = 4096;
+/// Largest pipe buffer `F_SETPIPE_SZ` may set, in bytes
+pub const PIPE_MAX_SIZE: usize = 16 * PAGE_SIZE;
 pub const MMIO: &[(usize, usize)] = &[
     (0x0010_0000, 0x00_2000), // VIRT_TEST/RTC  in virt machine
     (0x10001000, 0x1000),
@@ -0,0 +1,250 @@
+//! Device filesystem, mounted on `/dev`
+//!
+//! - `null`: reads end of file, writes are discarded
+//! - `zero`: reads zeros, writes are discarded
+//! - `random`, `urandom`: pseudo-random bytes, both from the same generator
+//! - `console`, `tty`: the sbi console, reads block for one character
+//! - `vda`: the virtio block device as raw bytes, bypassing the block cache
+//!   of easy-fs. It is read-only while mounted, as it is on `/`, since a
+//!   write would race the write-back of that cache.
+use super::stdio::{console_read_char, console_ready};
+use super::vfs::{is_mounted_from, InodeAttr, InodeType, SuperBlock, VfsInode};
+use crate::config::BLOCK_DEVICE_BLOCKS;
+use crate::drivers::BLOCK_DEVICE;
+use crate::sbi::console_putchar;
+use crate::sync::UPSafeCell;
+use crate::syscall::errno::EPERM;
+use crate::timer::get_time;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+const BLOCK_SZ: usize = 512;
+
+/// Device behind a node
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum Device {
+    Null,
+    Zero,
+    Random,
+    Console,
+    Vda,
+}
+
+impl Device {
+    pub fn itype(&self) -> InodeType {
+        match self {
+            Device::Vda => InodeType::BlockDevice,
+            _ => InodeType::CharDevice,
+        }
+    }
+}
+
+/// Encode a device number as `makedev` of Linux does
+pub fn makedev(major: u64, minor: u64) -> u64 {
+    ((major & 0xfff) << 8) | (minor & 0xff) | ((minor & !0xff) << 12) | ((major & !0xfff) << 32)
+}
+
+/// Device number of the console, also reported for stdin and stdout
+pub const CONSOLE_RDEV: (u64, u64) = (5, 1);
+
+/// Nodes of the device directory with their (major, minor) numbers,
+/// the inode number is the index plus 2
+const NODES: &[(&str, Device, (u64, u64))] = &[
+    ("null", Device::Null, (1, 3)),
+    ("zero", Device::Zero, (1, 5)),
+    ("random", Device::Random, (1, 8)),
+    ("urandom", Device::Random, (1, 9)),
+    ("console", Device::Console, CONSOLE_RDEV),
+    ("tty", Device::Console, (5, 0)),
+    ("vda", Device::Vda, (254, 0)),
+];
+
+lazy_static! {
+    /// State of the xorshift generator, seeded lazily from the timer
+    static ref RANDOM_STATE: UPSafeCell<u64> = unsafe { UPSafeCell::new(0) };
+}
+
+fn next_random() -> u64 {
+    let mut state = RANDOM_STATE.exclusive_access();
+    if *state == 0 {
+        *state = get_time() as u64 | 1;
+    }
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+/// The device filesystem
+pub struct DevFs;
+
+impl SuperBlock for DevFs {
+    fn root(&self) -> Arc<dyn VfsInode> {
+        Arc::new(DevRoot)
+    }
+    fn fs_type(&self) -> &'static str {
+        "devfs"
+    }
+}
+
+/// The `/dev` directory
+pub struct DevRoot;
+
+impl VfsInode for DevRoot {
+    fn ino(&self) -> u64 {
+        1
+    }
+    fn itype(&self) -> InodeType {
+        InodeType::Dir
+    }
+    fn nlink(&self) -> u32 {
+        2
+    }
+    fn size(&self) -> usize {
+        0
+    }
+    fn read_at(&self, _offset: usize, _buf: &mut [u8]) -> usize {
+        0
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    fn clear(&self) {}
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let index = NODES.iter().position(|(node, _, _)| *node == name)?;
+        let (_, device, (major, minor)) = NODES[index];
+        Some(Arc::new(DevNode {
+            ino: index as u64 + 2,
+            device,
+            rdev: makedev(major, minor),
+        }))
+    }
+    fn create(&self, _name: &str, _itype: InodeType) -> Result<Arc<dyn VfsInode>, isize> {
+        Err(EPERM)
+    }
+    fn link(&self, _name: &str, _target: &Arc<dyn VfsInode>) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn unlink(&self, _name: &str) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn list(&self) -> Vec<String> {
+        NODES.iter().map(|(name, _, _)| String::from(*name)).collect()
+    }
+}
+
+/// A device node
+pub struct DevNode {
+    ino: u64,
+    device: Device,
+    rdev: u64,
+}
+
+impl VfsInode for DevNode {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+    fn itype(&self) -> InodeType {
+        self.device.itype()
+    }
+    fn nlink(&self) -> u32 {
+        1
+    }
+    fn size(&self) -> usize {
+        match self.device {
+            Device::Vda => BLOCK_DEVICE_BLOCKS * BLOCK_SZ,
+            _ => 0,
+        }
+    }
+    fn attr(&self) -> InodeAttr {
+        let mut attr = InodeAttr::default_for(self.itype());
+        attr.perm = match self.device {
+            Device::Vda if vda_mounted() => 0o440,
+            Device::Vda => 0o660,
+            _ => 0o666,
+        };
+        attr.rdev = self.rdev;
+        attr
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        match self.device {
+            Device::Null => 0,
+            Device::Zero => {
+                buf.fill(0);
+                buf.len()
+            }
+            Device::Random => {
+                for chunk in buf.chunks_mut(8) {
+                    let bytes = next_random().to_le_bytes();
+                    chunk.copy_from_slice(&bytes[..chunk.len()]);
+                }
+                buf.len()
+            }
+            Device::Console => {
+                if buf.is_empty() {
+                    return 0;
+                }
+                buf[0] = console_read_char();
+                1
+            }
+            Device::Vda => block_io(offset, buf.len(), |block, range, pos| {
+                let len = range.len();
+                buf[pos..pos + len].copy_from_slice(&block[range]);
+                false
+            }),
+        }
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        match self.device {
+            Device::Null | Device::Zero | Device::Random => buf.len(),
+            Device::Console => {
+                for c in buf {
+                    console_putchar(*c as usize);
+                }
+                buf.len()
+            }
+            Device::Vda if vda_mounted() => 0,
+            Device::Vda => block_io(offset, buf.len(), |block, range, pos| {
+                let len = range.len();
+                block[range].copy_from_slice(&buf[pos..pos + len]);
+                true
+            }),
+        }
+    }
+    fn clear(&self) {}
+    fn read_ready(&self) -> bool {
+        self.device != Device::Console || console_ready()
+    }
+}
+
+/// Whether a filesystem, easy-fs on `/` at least, is mounted from `vda`
+fn vda_mounted() -> bool {
+    is_mounted_from("/dev/vda")
+}
+
+/// Access `len` bytes of the block device at `offset` block by block.
+/// `f` gets the block, the range of it covered and the position in the
+/// caller's buffer, and returns whether the block must be written back.
+fn block_io(
+    offset: usize,
+    len: usize,
+    mut f: impl FnMut(&mut [u8; BLOCK_SZ], core::ops::Range<usize>, usize) -> bool,
+) -> usize {
+    let end = (offset + len).min(BLOCK_DEVICE_BLOCKS * BLOCK_SZ);
+    let mut block = [0u8; BLOCK_SZ];
+    let mut pos = offset;
+    while pos < end {
+        let block_id = pos / BLOCK_SZ;
+        let start = pos % BLOCK_SZ;
+        let range = start..(BLOCK_SZ.min(start + end - pos));
+        let range_len = range.len();
+        BLOCK_DEVICE.read_block(block_id, &mut block);
+        if f(&mut block, range, pos - offset) {
+            BLOCK_DEVICE.write_block(block_id, &block);
+        }
+        pos += range_len;
+    }
+    end.saturating_sub(offset)
+}
@@ -0,0 +1,224 @@
+//! `easy-fs` on the virtio block device, mounted on `/`
+use super::vfs::{InodeType, SuperBlock, VfsInode};
+use crate::drivers::BLOCK_DEVICE;
+use crate::sync::UPSafeCell;
+use crate::syscall::errno::{EINVAL, EISDIR, ENOENT, ENOSPC, EPERM, EXDEV};
+use alloc::collections::BTreeMap;
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use easy_fs::{EasyFileSystem, Inode};
+use lazy_static::*;
+
+lazy_static! {
+    pub static ref ROOT_INODE: Arc<Inode> = {
+        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
+        Arc::new(EasyFileSystem::root_inode(&efs))
+    };
+    /// Sizes of the inodes by inode number, found once by `file_size` and
+    /// kept up to date by the writes through this adapter
+    static ref SIZES: UPSafeCell<BTreeMap<u64, usize>> = unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// The disk filesystem
+pub struct EasyFsSuperBlock;
+
+impl SuperBlock for EasyFsSuperBlock {
+    fn root(&self) -> Arc<dyn VfsInode> {
+        Arc::new(EasyFsInode::new(ROOT_INODE.clone(), None))
+    }
+    fn fs_type(&self) -> &'static str {
+        "easyfs"
+    }
+}
+
+/// An `easy-fs` inode
+pub struct EasyFsInode {
+    inode: Arc<Inode>,
+    /// the directory it was found in, `None` for the root
+    parent: Option<Arc<Inode>>,
+}
+
+impl EasyFsInode {
+    fn new(inode: Arc<Inode>, parent: Option<Arc<Inode>>) -> Self {
+        Self { inode, parent }
+    }
+    /// Give the file `old_name` of this directory the name `new_name` too
+    fn hard_link(&self, old_name: &str, new_name: &str) -> Result<(), isize> {
+        self.forget_size();
+        if self.inode.create_hard_link(old_name, new_name) < 0 {
+            return Err(EINVAL);
+        }
+        Ok(())
+    }
+    /// Drop the cached size, for a directory whose entries change
+    fn forget_size(&self) {
+        SIZES.exclusive_access().remove(&self.ino());
+    }
+}
+
+/// Size of the file of `inode`, which easy-fs does not tell: reads stop
+/// at the end of the file, so the last readable byte is searched for.
+/// This takes O(log n) reads, hence the results are kept in `SIZES`.
+fn file_size(inode: &Inode) -> usize {
+    let readable = |offset: usize| inode.read_at(offset, &mut [0u8; 1]) == 1;
+    if !readable(0) {
+        return 0;
+    }
+    // the size is in (low, high]
+    let mut low = 0;
+    let mut high = 1;
+    while readable(high) {
+        low = high;
+        high *= 2;
+    }
+    while high - low > 1 {
+        let mid = low + (high - low) / 2;
+        if readable(mid) {
+            low = mid;
+        } else {
+            high = mid;
+        }
+    }
+    high
+}
+
+impl VfsInode for EasyFsInode {
+    fn ino(&self) -> u64 {
+        self.inode.inode_id() as u64
+    }
+    fn itype(&self) -> InodeType {
+        if self.inode.is_dir() {
+            InodeType::Dir
+        } else {
+            InodeType::File
+        }
+    }
+    fn nlink(&self) -> u32 {
+        // easy-fs links names within one directory, so all the names of
+        // the inode are in the directory it was found in
+        match &self.parent {
+            Some(dir) => dir.hard_link_cnt(&self.inode),
+            None => 1,
+        }
+    }
+    fn size(&self) -> usize {
+        let cached = SIZES.exclusive_access().get(&self.ino()).copied();
+        cached.unwrap_or_else(|| {
+            let size = file_size(&self.inode);
+            SIZES.exclusive_access().insert(self.ino(), size);
+            size
+        })
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        self.inode.read_at(offset, buf)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let written = self.inode.write_at(offset, buf);
+        if let Some(size) = SIZES.exclusive_access().get_mut(&self.ino()) {
+            *size = (*size).max(offset + written);
+        }
+        written
+    }
+    fn clear(&self) {
+        self.inode.clear();
+        SIZES.exclusive_access().insert(self.ino(), 0);
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let inode = self.inode.find(name)?;
+        Some(Arc::new(EasyFsInode::new(inode, Some(self.inode.clone()))))
+    }
+    fn create(&self, name: &str, itype: InodeType) -> Result<Arc<dyn VfsInode>, isize> {
+        // easy-fs only creates files, directories and device nodes live
+        // on other filesystems
+        if itype != InodeType::File {
+            return Err(EPERM);
+        }
+        self.forget_size();
+        let inode = self.inode.create(name).ok_or(ENOSPC)?;
+        // the number may be reused from a file deleted before
+        SIZES.exclusive_access().insert(inode.inode_id() as u64, 0);
+        Ok(Arc::new(EasyFsInode::new(inode, Some(self.inode.clone()))))
+    }
+    fn link(&self, name: &str, target: &Arc<dyn VfsInode>) -> Result<(), isize> {
+        if target.itype() == InodeType::Dir {
+            return Err(EPERM);
+        }
+        // easy-fs links names within one directory, so the target needs a
+        // name in this directory already
+        let old_name = self
+            .inode
+            .ls()
+            .into_iter()
+            .find(|entry| {
+                self.inode
+                    .find(entry)
+                    .is_some_and(|inode| inode.inode_id() as u64 == target.ino())
+            })
+            .ok_or(EXDEV)?;
+        self.hard_link(&old_name, name)
+    }
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        self.forget_size();
+        if self.inode.delete_hard_link(name) < 0 {
+            return Err(ENOENT);
+        }
+        Ok(())
+    }
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        if self.inode.is_dir() {
+            return Err(EISDIR);
+        }
+        let old_size = self.size();
+        if size >= old_size {
+            let zeros = vec![0u8; size - old_size];
+            self.inode.write_at(old_size, &zeros);
+            return Ok(());
+        }
+        // easy-fs can only free all blocks of a file, keep the head and rewrite it
+        let mut head = vec![0u8; size];
+        self.inode.read_at(0, &mut head);
+        self.inode.clear();
+        self.inode.write_at(0, &head);
+        Ok(())
+    }
+    fn symlink(&self, _name: &str, _target: &str) -> Result<(), isize> {
+        // easy-fs has no inode type for links, they can be made on tmpfs
+        Err(EPERM)
+    }
+    fn list(&self) -> Vec<String> {
+        self.inode.ls()
+    }
+    /// easy-fs cannot swap the inode of a name in place, so a rename is a
+    /// series of link and unlink steps and is not atomic: a crash midway
+    /// leaves both names or a temporary one. A new name is always linked
+    /// before anything is unlinked, so a failing link leaves the source
+    /// where it was.
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn VfsInode>, new_name: &str) -> Result<(), isize> {
+        // names can only be linked within one directory
+        if new_dir.ino() != self.ino() {
+            return Err(EXDEV);
+        }
+        let source = self.inode.find(old_name).ok_or(ENOENT)?;
+        let Some(target) = self.inode.find(new_name) else {
+            self.hard_link(old_name, new_name)?;
+            return self.unlink(old_name);
+        };
+        // two names of one file stay as they are
+        if target.inode_id() == source.inode_id() {
+            return Ok(());
+        }
+        // the file is kept under a temporary name while the target goes
+        let temp = format!(".rename-{}", source.inode_id());
+        self.hard_link(old_name, &temp)?;
+        self.unlink(new_name)?;
+        if let Err(errno) = self.hard_link(&temp, new_name) {
+            self.unlink(&temp)?;
+            return Err(errno);
+        }
+        self.unlink(&temp)?;
+        self.unlink(old_name)
+    }
+}
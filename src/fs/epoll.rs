@@ -0,0 +1,205 @@
+//! epoll instances
+//!
+//! An instance watches the open files of fds and reports those ready. It
+//! only holds weak references, so a watched file leaves the instance when
+//! its last fd is closed. Reporting is level-triggered: `EPOLLET` is
+//! accepted and reported like level-triggered events, which is a superset
+//! of what a program reading until `EAGAIN` expects.
+use super::{File, PollEvents, Stat, StatMode};
+use crate::mm::UserBuffer;
+use crate::sync::UPSafeCell;
+use crate::syscall::errno::{EEXIST, ELOOP, ENOENT};
+use crate::task::TaskControlBlock;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+/// `struct epoll_event`, which is not packed on riscv64
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct EpollEvent {
+    pub events: u32,
+    pub data: u64,
+}
+
+/// Report the events once, until the interest is modified
+pub const EPOLLONESHOT: u32 = 1 << 30;
+/// Edge-triggered, reported as level-triggered here
+pub const EPOLLET: u32 = 1 << 31;
+
+/// A watched fd
+struct Interest {
+    file: Weak<dyn File + Send + Sync>,
+    /// events asked with the `EPOLL*` flags
+    events: u32,
+    data: u64,
+    /// reported once with `EPOLLONESHOT`
+    disabled: bool,
+}
+
+/// An epoll instance
+pub struct EpollFile {
+    interests: UPSafeCell<BTreeMap<usize, Interest>>,
+}
+
+impl EpollFile {
+    pub fn new() -> Self {
+        Self {
+            interests: unsafe { UPSafeCell::new(BTreeMap::new()) },
+        }
+    }
+    /// Watch `file` open on `fd`, `ELOOP` if `file` is an instance which
+    /// watches this one, directly or through other instances
+    pub fn add(&self, fd: usize, file: &Arc<dyn File + Send + Sync>, event: EpollEvent) -> Result<(), isize> {
+        if file.as_epoll().map_or(false, |epoll| epoll.reaches(self)) {
+            return Err(ELOOP);
+        }
+        let mut interests = self.interests.exclusive_access();
+        // the fd may be open on another file since the one watched was closed
+        interests.retain(|_, interest| interest.file.strong_count() > 0);
+        if interests.contains_key(&fd) {
+            return Err(EEXIST);
+        }
+        interests.insert(
+            fd,
+            Interest {
+                file: Arc::downgrade(file),
+                events: event.events,
+                data: event.data,
+                disabled: false,
+            },
+        );
+        Ok(())
+    }
+    /// Change the events and data of `fd`, enabling it again
+    pub fn modify(&self, fd: usize, event: EpollEvent) -> Result<(), isize> {
+        let mut interests = self.interests.exclusive_access();
+        let interest = interests.get_mut(&fd).ok_or(ENOENT)?;
+        interest.events = event.events;
+        interest.data = event.data;
+        interest.disabled = false;
+        Ok(())
+    }
+    /// Stop watching `fd`
+    pub fn delete(&self, fd: usize) -> Result<(), isize> {
+        self.interests.exclusive_access().remove(&fd).map(|_| ()).ok_or(ENOENT)
+    }
+    /// Whether `target` is this instance or watched by it through nested
+    /// instances, which never form a cycle as `add` refuses one
+    fn reaches(&self, target: &EpollFile) -> bool {
+        core::ptr::eq(self, target)
+            || self
+                .files()
+                .iter()
+                .any(|file| file.as_epoll().map_or(false, |epoll| epoll.reaches(target)))
+    }
+    /// Watched files still open
+    pub fn files(&self) -> Vec<Arc<dyn File + Send + Sync>> {
+        let interests = self.interests.exclusive_access();
+        interests.values().filter_map(|interest| interest.file.upgrade()).collect()
+    }
+    /// Up to `max` events ready, a one-shot interest is disabled once
+    /// reported if `consume`
+    pub fn scan(&self, max: usize, consume: bool) -> Vec<EpollEvent> {
+        let watched: Vec<(usize, Arc<dyn File + Send + Sync>, u32, u64)> = self
+            .interests
+            .exclusive_access()
+            .iter()
+            .filter(|(_, interest)| !interest.disabled)
+            .filter_map(|(fd, interest)| {
+                let file = interest.file.upgrade()?;
+                Some((*fd, file, interest.events, interest.data))
+            })
+            .collect();
+        let mut ready = Vec::new();
+        // polled out of the borrow, a watched file may be an instance too
+        for (fd, file, events, data) in watched {
+            if ready.len() == max {
+                break;
+            }
+            let mask = events | (PollEvents::ERR | PollEvents::HUP).bits() as u32;
+            let revents = file.poll().bits() as u32 & mask;
+            if revents == 0 {
+                continue;
+            }
+            ready.push(EpollEvent { events: revents, data });
+            if consume && events & EPOLLONESHOT != 0 {
+                if let Some(interest) = self.interests.exclusive_access().get_mut(&fd) {
+                    interest.disabled = true;
+                }
+            }
+        }
+        ready
+    }
+}
+
+impl File for EpollFile {
+    fn readable(&self) -> bool {
+        false
+    }
+    fn writable(&self) -> bool {
+        false
+    }
+    fn read(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn write(&self, _buf: UserBuffer) -> usize {
+        0
+    }
+    fn stat(&self) -> Stat {
+        let mut st = Stat::empty();
+        st.mode = StatMode::new(StatMode::NULL, 0o600);
+        st.nlink = 1;
+        st
+    }
+    fn path(&self) -> Option<String> {
+        None
+    }
+    fn seekable(&self) -> bool {
+        false
+    }
+    fn poll(&self) -> PollEvents {
+        if self.scan(1, false).is_empty() {
+            PollEvents::empty()
+        } else {
+            PollEvents::IN
+        }
+    }
+    fn add_waiter(&self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut notified = true;
+        for file in self.files() {
+            if !file.add_waiter(task) {
+                notified = false;
+            }
+        }
+        notified
+    }
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        for file in self.files() {
+            file.remove_waiter(task);
+        }
+    }
+    fn as_epoll(&self) -> Option<&EpollFile> {
+        Some(self)
+    }
+}
+
+#[allow(unused)]
+/// Check that nested instances cannot watch each other in a cycle
+pub fn epoll_test() {
+    use crate::println;
+    let event = EpollEvent { events: 0, data: 0 };
+    let a: Arc<dyn File + Send + Sync> = Arc::new(EpollFile::new());
+    let b: Arc<dyn File + Send + Sync> = Arc::new(EpollFile::new());
+    let c: Arc<dyn File + Send + Sync> = Arc::new(EpollFile::new());
+    let (ea, eb, ec) = (a.as_epoll().unwrap(), b.as_epoll().unwrap(), c.as_epoll().unwrap());
+    assert_eq!(ea.add(1, &b, event), Ok(()));
+    assert_eq!(eb.add(2, &c, event), Ok(()));
+    // a -> b -> c -> a and a -> a
+    assert_eq!(ec.add(0, &a, event).err(), Some(ELOOP));
+    assert_eq!(ea.add(0, &a, event).err(), Some(ELOOP));
+    // a second path to c is no cycle
+    assert_eq!(ea.add(2, &c, event), Ok(()));
+    println!("epoll_test passed!");
+}
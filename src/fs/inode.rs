@@ -1,40 +1,58 @@
-//! `Arc<Inode>` -> `OSInodeInner`: In order to open files concurrently
-//! we need to wrap `Inode` into `Arc`,but `Mutex` in `Inode` prevents
-//! file systems from being accessed simultaneously
+//! `Arc<dyn VfsInode>` -> `OSInodeInner`: In order to open files concurrently
+//! we need to wrap the inode into `Arc`, the filesystem behind it decides
+//! how it is accessed
 //!
-//! `UPSafeCell<OSInodeInner>` -> `OSInode`: for static `ROOT_INODE`,we
-//! need to wrap `OSInodeInner` into `UPSafeCell`
-use super::File;
-use crate::fs::{Stat, StatMode};
-use crate::{drivers::BLOCK_DEVICE, println};
+//! `UPSafeCell<OSInodeInner>` -> `OSInode`: the offset is shared by all the
+//! fds duplicated from one open, so it needs interior mutability
+use super::path::{join, split_parent};
+use super::pipe::open_fifo;
+use super::vfs::{is_mount_point, lookup, lookup_nofollow, lookup_parent, mount_points_in, stat_inode, Dentry, InodeType, VfsInode};
+use super::{seek_position, File};
+use crate::fs::Stat;
+use crate::syscall::errno::{EACCES, EBUSY, EEXIST, EINVAL, EISDIR, ELOOP, ENOENT, ENOTDIR, ENOTEMPTY, ENXIO, EPERM, EXDEV};
+use crate::println;
 use crate::mm::UserBuffer;
 use crate::sync::UPSafeCell;
-use crate::debug;
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 use alloc::vec::Vec;
 use bitflags::*;
-use easy_fs::{EasyFileSystem, Inode};
-use lazy_static::*;
 /// A wrapper around a filesystem inode
 /// to implement File trait atop
 pub struct OSInode {
     readable: bool,
     writable: bool,
+    /// normalized absolute path the inode was opened with
+    path: String,
+    /// device number of the filesystem of the inode
+    dev: u64,
     inner: UPSafeCell<OSInodeInner>,
 }
 /// The OS inode inner in 'UPSafeCell'
 pub struct OSInodeInner {
     offset: usize,
-    inode: Arc<Inode>,
+    /// `APPEND` and `NONBLOCK`, shared by the duplicated fds as the offset
+    status: OpenFlags,
+    inode: Arc<dyn VfsInode>,
 }
 
 impl OSInode {
-    /// Construct an OS inode from a inode
-    pub fn new(readable: bool, writable: bool, inode: Arc<Inode>) -> Self {
+    /// Construct an OS inode from a inode opened with `flags`
+    pub fn new(flags: OpenFlags, inode: Arc<dyn VfsInode>, path: &str, dev: u64) -> Self {
+        let (readable, writable) = flags.read_write();
         Self {
             readable,
             writable,
-            inner: unsafe { UPSafeCell::new(OSInodeInner { offset: 0, inode }) },
+            path: String::from(path),
+            dev,
+            inner: unsafe {
+                UPSafeCell::new(OSInodeInner {
+                    offset: 0,
+                    status: flags & OpenFlags::STATUS,
+                    inode,
+                })
+            },
         }
     }
     /// Read all data inside a inode into vector
@@ -54,16 +72,10 @@ impl OSInode {
     }
 }
 
-lazy_static! {
-    pub static ref ROOT_INODE: Arc<Inode> = {
-        let efs = EasyFileSystem::open(BLOCK_DEVICE.clone());
-        Arc::new(EasyFileSystem::root_inode(&efs))
-    };
-}
-/// List all files in the filesystems
+/// List all files in the root directory
 pub fn list_apps() {
     println!("/**** APPS ****");
-    for app in ROOT_INODE.ls() {
+    for app in lookup("/").unwrap().inode.list() {
         println!("{}", app);
     }
     println!("**************/");
@@ -71,6 +83,7 @@ pub fn list_apps() {
 
 bitflags! {
     ///Open file flags
+    #[derive(Copy, Clone, PartialEq, Debug)]
     pub struct OpenFlags: u32 {
         ///Read only
         const RDONLY = 0;
@@ -78,60 +91,321 @@ bitflags! {
         const WRONLY = 1 << 0;
         ///Read & Write
         const RDWR = 1 << 1;
+        ///Fail with `EEXIST` if `CREATE` is given and the file exists
+        const EXCL = 1 << 7;
         ///Allow create
         const CREATE = 1 << 9;
         ///Clear file and return an empty one
         const TRUNC = 1 << 10;
+        ///Return `EAGAIN` instead of blocking
+        const NONBLOCK = 1 << 11;
+        ///Write at the end of file, moving there and writing at once;
+        ///the bit of `O_APPEND` of Linux is taken by `TRUNC`
+        const APPEND = 1 << 12;
+        ///Fail with `ENOTDIR` if the path does not name a directory
+        const DIRECTORY = 1 << 16;
+        ///Fail with `ELOOP` if the path names a symbolic link
+        const NOFOLLOW = 1 << 17;
+        ///Close the fd on `exec`
+        const CLOEXEC = 1 << 19;
+        ///Flags kept by the open file after `open` returns
+        const STATUS = Self::APPEND.bits() | Self::NONBLOCK.bits();
     }
 }
 
 impl OpenFlags {
-    /// Do not check validity for simplicity
+    /// Flags passed from user space, `EINVAL` for unknown bits and for
+    /// `WRONLY` together with `RDWR`
+    pub fn from_user(bits: u32) -> Result<Self, isize> {
+        let flags = Self::from_bits(bits).ok_or(EINVAL)?;
+        if flags.contains(Self::WRONLY | Self::RDWR) {
+            return Err(EINVAL);
+        }
+        Ok(flags)
+    }
     /// Return (readable, writable)
     pub fn read_write(&self) -> (bool, bool) {
-        if self.is_empty() {
-            (true, false)
-        } else if self.contains(Self::WRONLY) {
+        if self.contains(Self::WRONLY) {
             (false, true)
-        } else {
+        } else if self.contains(Self::RDWR) {
             (true, true)
+        } else {
+            (true, false)
         }
     }
 }
-///Open file with flags
-pub fn open_file(name: &str, flags: OpenFlags) -> Option<Arc<OSInode>> {
+
+///Open file at the normalized absolute `path` with flags, a file created
+///by `CREATE` gets the permission bits of `mode`
+///
+///- `CREATE` makes the file if it does not exist and leaves an existing one
+///  as it is, `EISDIR` if it is a directory
+///- `CREATE` with `EXCL` fails with `EEXIST` if the name exists at all
+///- `TRUNC` empties an existing regular file, `EISDIR` for a directory
+///- an existing file is opened only as its owner permission bits allow,
+///  `EACCES` otherwise
+pub fn open_file(path: &str, flags: OpenFlags, mode: u32) -> Result<Arc<OSInode>, isize> {
+    let dentry = open_dentry(path, flags, mode)?;
+    Ok(Arc::new(OSInode::new(flags, dentry.inode, path, dentry.dev)))
+}
+
+/// Open the file at `path` as `open_file` does, a FIFO gives an end of
+/// its pipe
+pub fn open_path(path: &str, flags: OpenFlags, mode: u32) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let dentry = open_dentry(path, flags, mode)?;
+    if dentry.inode.itype() == InodeType::Fifo {
+        return Ok(open_fifo(dentry, flags)?);
+    }
+    Ok(Arc::new(OSInode::new(flags, dentry.inode, path, dentry.dev)))
+}
+
+/// Find or create the inode to open at `path` with `flags`
+fn open_dentry(path: &str, flags: OpenFlags, mode: u32) -> Result<Dentry, isize> {
     let (readable, writable) = flags.read_write();
-    if flags.contains(OpenFlags::CREATE) {
-        if let Some(inode) = ROOT_INODE.find(name) {
-            // clear size
-            inode.clear();
-            Some(Arc::new(OSInode::new(readable, writable, inode)))
-        } else {
-            // create file
-            ROOT_INODE
-                .create(name)
-                .map(|inode| Arc::new(OSInode::new(readable, writable, inode)))
-        }
+    let create = flags.contains(OpenFlags::CREATE);
+    let exclusive = flags.contains(OpenFlags::CREATE | OpenFlags::EXCL);
+    if create && flags.contains(OpenFlags::DIRECTORY) {
+        return Err(EINVAL);
+    }
+    // an exclusive create does not follow a link, even a dangling one
+    let found = if flags.contains(OpenFlags::NOFOLLOW) || exclusive {
+        lookup_nofollow(path)
     } else {
-        ROOT_INODE.find(name).map(|inode| {
-            if flags.contains(OpenFlags::TRUNC) {
-                inode.clear();
+        lookup(path)
+    };
+    let dentry = match found {
+        Ok(_) if exclusive => return Err(EEXIST),
+        Ok(dentry) => {
+            let itype = dentry.inode.itype();
+            if itype == InodeType::Symlink {
+                return Err(ELOOP);
+            }
+            // a socket is reached with `connect`
+            if itype == InodeType::Socket {
+                return Err(ENXIO);
+            }
+            if itype == InodeType::Dir && (create || writable || flags.contains(OpenFlags::TRUNC)) {
+                return Err(EISDIR);
             }
-            Arc::new(OSInode::new(readable, writable, inode))
-        })
+            if itype != InodeType::Dir && flags.contains(OpenFlags::DIRECTORY) {
+                return Err(ENOTDIR);
+            }
+            // every task owns every file, so the bits of the owner apply
+            let perm = dentry.inode.attr().perm;
+            let write = writable || flags.contains(OpenFlags::TRUNC);
+            if (readable && perm & 0o400 == 0) || (write && perm & 0o200 == 0) {
+                return Err(EACCES);
+            }
+            // only once nothing can fail any more
+            if itype == InodeType::File && flags.contains(OpenFlags::TRUNC) {
+                dentry.inode.clear();
+            }
+            dentry
+        }
+        Err(ENOENT) if create => {
+            // create file
+            let (dir, name) = lookup_parent(path)?;
+            let mut dentry = dir.clone();
+            dentry.inode = dir.inode.create(name, InodeType::File)?;
+            dentry.inode.set_perm(mode & 0o7777);
+            dentry.path = String::from(path);
+            dentry
+        }
+        Err(errno) => return Err(errno),
+    };
+    Ok(dentry)
+}
+
+/// Create a directory at `path`
+pub fn make_dir(path: &str) -> Result<(), isize> {
+    // the root always exists
+    let (dir, name) = lookup_parent(path).map_err(|errno| if errno == EBUSY { EEXIST } else { errno })?;
+    if dir.inode.lookup(name).is_some() || is_mount_point(path) {
+        return Err(EEXIST);
     }
+    dir.inode.create(name, InodeType::Dir)?;
+    Ok(())
 }
 
-pub fn create_hard_link(oldpath: &str, newpath: &str) -> isize {
-    ROOT_INODE.create_hard_link(oldpath, newpath)
+/// Create a regular file, a FIFO or a socket inode at `path` with the
+/// permission bits `perm`
+pub fn make_node(path: &str, itype: InodeType, perm: u32) -> Result<(), isize> {
+    let (dir, name) = lookup_parent(path).map_err(|errno| if errno == EBUSY { EEXIST } else { errno })?;
+    if dir.inode.lookup(name).is_some() || is_mount_point(path) {
+        return Err(EEXIST);
+    }
+    dir.inode.create(name, itype)?.set_perm(perm & 0o7777);
+    Ok(())
+}
+
+/// Remove the empty directory at `path`
+pub fn remove_dir(path: &str) -> Result<(), isize> {
+    if is_mount_point(path) {
+        return Err(EBUSY);
+    }
+    let (dir, name) = lookup_parent(path)?;
+    let inode = dir.inode.lookup(name).ok_or(ENOENT)?;
+    if inode.itype() != InodeType::Dir {
+        return Err(ENOTDIR);
+    }
+    if !inode.list().is_empty() {
+        return Err(ENOTEMPTY);
+    }
+    dir.inode.unlink(name)
 }
 
-pub fn delete_hard_link(path: &str) -> isize {
-    ROOT_INODE.delete_hard_link(path)
+/// Create `newpath` as a hard link to the file at `oldpath`
+pub fn create_hard_link(oldpath: &str, newpath: &str) -> Result<(), isize> {
+    let old = lookup_nofollow(oldpath)?;
+    if old.inode.itype() == InodeType::Dir {
+        return Err(EPERM);
+    }
+    let (dir, name) = lookup_parent(newpath)?;
+    if dir.dev != old.dev {
+        return Err(EXDEV);
+    }
+    if dir.inode.lookup(name).is_some() {
+        return Err(EEXIST);
+    }
+    dir.inode.link(name, &old.inode)
 }
 
-pub fn hard_link_cnt(node: &OSInode) -> u32 {
-    ROOT_INODE.hard_link_cnt(node.inner.exclusive_access().inode.deref())
+/// Create a symbolic link at `linkpath` pointing to `target`
+pub fn symlink(target: &str, linkpath: &str) -> Result<(), isize> {
+    if target.is_empty() {
+        return Err(ENOENT);
+    }
+    let (dir, name) = lookup_parent(linkpath).map_err(|errno| if errno == EBUSY { EEXIST } else { errno })?;
+    if dir.inode.lookup(name).is_some() || is_mount_point(linkpath) {
+        return Err(EEXIST);
+    }
+    dir.inode.symlink(name, target)
+}
+
+/// Target of the symbolic link at `path`
+pub fn readlink(path: &str) -> Result<String, isize> {
+    lookup_nofollow(path)?.inode.readlink()
+}
+
+/// Remove the name `path` of a file, the file is freed with its last link
+pub fn delete_hard_link(path: &str) -> Result<(), isize> {
+    let (dir, name) = lookup_parent(path)?;
+    let inode = dir.inode.lookup(name).ok_or(ENOENT)?;
+    if inode.itype() == InodeType::Dir {
+        return Err(EISDIR);
+    }
+    dir.inode.unlink(name)
+}
+
+/// A `linux_dirent64` record
+struct Dirent64<'a> {
+    ino: u64,
+    /// position of the next record
+    off: u64,
+    d_type: u8,
+    name: &'a str,
+}
+
+impl Dirent64<'_> {
+    /// Size of the fixed fields `d_ino`, `d_off`, `d_reclen` and `d_type`
+    const HEADER_SIZE: usize = 19;
+    /// Length of the record with the nul-terminated name, aligned to 8 bytes
+    fn reclen(&self) -> usize {
+        (Self::HEADER_SIZE + self.name.len() + 1).next_multiple_of(8)
+    }
+    /// Write the record to the start of `buf`, return false if it does not fit
+    fn write_to(&self, buf: &mut [u8]) -> bool {
+        let reclen = self.reclen();
+        if buf.len() < reclen {
+            return false;
+        }
+        let record = &mut buf[..reclen];
+        record.fill(0);
+        record[0..8].copy_from_slice(&self.ino.to_le_bytes());
+        record[8..16].copy_from_slice(&self.off.to_le_bytes());
+        record[16..18].copy_from_slice(&(reclen as u16).to_le_bytes());
+        record[18] = self.d_type;
+        record[Self::HEADER_SIZE..Self::HEADER_SIZE + self.name.len()].copy_from_slice(self.name.as_bytes());
+        true
+    }
+}
+
+/// Read `inode` from `offset` into `buf`, return the number of bytes read
+fn read_inode(inode: &Arc<dyn VfsInode>, mut offset: usize, mut buf: UserBuffer) -> usize {
+    let mut total_read_size = 0usize;
+    for slice in buf.buffers.iter_mut() {
+        let read_size = inode.read_at(offset, slice);
+        if read_size == 0 {
+            break;
+        }
+        offset += read_size;
+        total_read_size += read_size;
+    }
+    total_read_size
+}
+
+/// Write `buf` to `inode` at `offset`, return the number of bytes written
+fn write_inode(inode: &Arc<dyn VfsInode>, mut offset: usize, buf: UserBuffer) -> usize {
+    let mut total_write_size = 0usize;
+    for slice in buf.buffers.iter() {
+        let write_size = inode.write_at(offset, slice);
+        offset += write_size;
+        total_write_size += write_size;
+        if write_size < slice.len() {
+            break;
+        }
+    }
+    total_write_size
+}
+
+/// `renameat2` flag: fail with `EEXIST` instead of replacing the target
+pub const RENAME_NOREPLACE: u32 = 1 << 0;
+/// `renameat2` flag: swap the two names, not supported
+pub const RENAME_EXCHANGE: u32 = 1 << 1;
+
+/// Move `oldpath` to `newpath`, replacing `newpath` if it exists
+pub fn rename(oldpath: &str, newpath: &str, flags: u32) -> Result<(), isize> {
+    if flags & !RENAME_NOREPLACE != 0 {
+        return Err(EINVAL);
+    }
+    if is_mount_point(oldpath) || is_mount_point(newpath) {
+        return Err(EBUSY);
+    }
+    let (old_dir, old_name) = lookup_parent(oldpath)?;
+    let (new_dir, new_name) = lookup_parent(newpath)?;
+    let inode = old_dir.inode.lookup(old_name).ok_or(ENOENT)?;
+    if old_dir.dev != new_dir.dev {
+        return Err(EXDEV);
+    }
+    // a directory cannot be moved below itself
+    if newpath.strip_prefix(oldpath).is_some_and(|rest| rest.starts_with('/')) {
+        return Err(EINVAL);
+    }
+    if let Some(target) = new_dir.inode.lookup(new_name) {
+        if flags & RENAME_NOREPLACE != 0 {
+            return Err(EEXIST);
+        }
+        // two names of the same file, nothing to do
+        if target.ino() == inode.ino() {
+            return Ok(());
+        }
+        match (inode.itype() == InodeType::Dir, target.itype() == InodeType::Dir) {
+            (true, false) => return Err(ENOTDIR),
+            (false, true) => return Err(EISDIR),
+            (true, true) if !target.list().is_empty() => return Err(ENOTEMPTY),
+            _ => {}
+        }
+    }
+    old_dir.inode.rename(old_name, &new_dir.inode, new_name)
+}
+
+/// Set the size of the file at `path`
+pub fn truncate(path: &str, size: usize) -> Result<(), isize> {
+    let dentry = lookup(path)?;
+    if dentry.inode.itype() == InodeType::Dir {
+        return Err(EISDIR);
+    }
+    dentry.inode.truncate(size)
 }
 
 impl File for OSInode {
@@ -141,43 +415,201 @@ impl File for OSInode {
     fn writable(&self) -> bool {
         self.writable
     }
-    fn read(&self, mut buf: UserBuffer) -> usize {
+    fn read(&self, buf: UserBuffer) -> usize {
+        // a console read blocks, so another fd of this file may come in
+        // between and the borrow is not held over the read
+        let (inode, offset) = {
+            let inner = self.inner.exclusive_access();
+            (inner.inode.clone(), inner.offset)
+        };
+        let read_size = read_inode(&inode, offset, buf);
+        self.inner.exclusive_access().offset = offset + read_size;
+        read_size
+    }
+    fn write(&self, buf: UserBuffer) -> usize {
         let mut inner = self.inner.exclusive_access();
-        let mut total_read_size = 0usize;
-        for slice in buf.buffers.iter_mut() {
-            let read_size = inner.inode.read_at(inner.offset, *slice);
-            if read_size == 0 {
-                break;
-            }
-            inner.offset += read_size;
-            total_read_size += read_size;
+        if inner.status.contains(OpenFlags::APPEND) {
+            inner.offset = inner.inode.size();
         }
-        total_read_size
+        let write_size = write_inode(&inner.inode, inner.offset, buf);
+        inner.offset += write_size;
+        write_size
     }
-    fn write(&self, buf: UserBuffer) -> usize {
+    fn stat(&self) -> Stat {
+        let inode = self.inner.exclusive_access().inode.clone();
+        stat_inode(self.dev, &inode)
+    }
+    fn path(&self) -> Option<String> {
+        Some(self.path.clone())
+    }
+    /// A character device such as the console has no position
+    fn seekable(&self) -> bool {
+        self.inner.exclusive_access().inode.itype() != InodeType::CharDevice
+    }
+    fn status_flags(&self) -> OpenFlags {
+        self.inner.exclusive_access().status
+    }
+    fn set_status_flags(&self, flags: OpenFlags) {
+        self.inner.exclusive_access().status = flags & OpenFlags::STATUS;
+    }
+    fn read_ready(&self) -> bool {
+        self.inner.exclusive_access().inode.read_ready()
+    }
+    fn seek(&self, offset: isize, whence: usize) -> Result<usize, isize> {
+        let mut inner = self.inner.exclusive_access();
+        inner.offset = seek_position(inner.offset, inner.inode.size(), offset, whence)?;
+        Ok(inner.offset)
+    }
+    fn truncate(&self, size: usize) -> Result<(), isize> {
+        let inode = self.inner.exclusive_access().inode.clone();
+        if inode.itype() == InodeType::Dir {
+            return Err(EISDIR);
+        }
+        if !self.writable {
+            return Err(EINVAL);
+        }
+        inode.truncate(size)
+    }
+    fn getdents(&self, buf: &mut [u8]) -> Result<usize, isize> {
         let mut inner = self.inner.exclusive_access();
-        let mut total_write_size = 0usize;
-        for slice in buf.buffers.iter() {
-            let write_size = inner.inode.write_at(inner.offset, *slice);
-            assert_eq!(write_size, slice.len());
-            inner.offset += write_size;
-            total_write_size += write_size;
-        }
-        total_write_size
-    }
-    fn stat(&self) -> super::Stat {
-        let mut mode = StatMode::NULL;
-        let inner = self.inner.exclusive_access();
-        if inner.inode.is_dir() { mode = StatMode::DIR; }
-        else if inner.inode.is_file() { mode = StatMode::FILE; }
-        let ino = inner.inode.inode_id() as u64;
-        drop(inner);
-        super::Stat {
-            dev: 0,
-            ino,
-            mode,
-            nlink: hard_link_cnt(self),
-            pad: [0; 7],
+        if inner.inode.itype() != InodeType::Dir {
+            return Err(ENOTDIR);
+        }
+        let mut names = vec![String::from("."), String::from("..")];
+        names.extend(inner.inode.list());
+        for name in mount_points_in(&self.path) {
+            if !names.contains(&name) {
+                names.push(name);
+            }
+        }
+        let mut written = 0;
+        while let Some(name) = names.get(inner.offset) {
+            let path = match name.as_str() {
+                "." => self.path.clone(),
+                ".." => String::from(split_parent(&self.path).map_or("/", |(parent, _)| parent)),
+                _ => join(&self.path, name),
+            };
+            // the entry may have been removed since it was listed
+            if let Ok(dentry) = lookup(&path) {
+                let dirent = Dirent64 {
+                    ino: dentry.inode.ino(),
+                    off: inner.offset as u64 + 1,
+                    d_type: dentry.inode.itype().dirent_type(),
+                    name,
+                };
+                if !dirent.write_to(&mut buf[written..]) {
+                    break;
+                }
+                written += dirent.reclen();
+            }
+            inner.offset += 1;
+        }
+        if written == 0 && inner.offset < names.len() {
+            // not even one record fits
+            return Err(EINVAL);
         }
+        Ok(written)
+    }
+    fn read_at(&self, offset: usize, buf: UserBuffer) -> Result<usize, isize> {
+        let inode = self.inner.exclusive_access().inode.clone();
+        Ok(read_inode(&inode, offset, buf))
+    }
+    fn write_at(&self, offset: usize, buf: UserBuffer) -> Result<usize, isize> {
+        let inode = self.inner.exclusive_access().inode.clone();
+        Ok(write_inode(&inode, offset, buf))
+    }
+}
+
+#[allow(unused)]
+/// Run `test` on a tmpfs mounted on the new directory `dir` for it alone,
+/// unmounted and removed afterwards. What an earlier run that panicked
+/// left at `dir` is cleared first, so a failed test does not fail the
+/// next one.
+pub(super) fn with_test_tmpfs(dir: &str, test: impl FnOnce()) {
+    use super::vfs::{mount, umount};
+    if is_mount_point(dir) {
+        umount(dir).unwrap();
+    }
+    if lookup(dir).is_ok() {
+        remove_dir(dir).unwrap();
+    }
+    make_dir(dir).unwrap();
+    mount("none", dir, "tmpfs").unwrap();
+    test();
+    umount(dir).unwrap();
+    remove_dir(dir).unwrap();
+}
+
+#[allow(unused)]
+/// Check the combinations of `CREATE`, `EXCL`, `TRUNC` and `DIRECTORY`
+/// on a tmpfs mounted for the test, and the mode checked on open
+pub fn open_flags_test() {
+    use super::StatMode;
+    const DIR: &str = "/tmp/open_flags_test";
+    const FILE: &str = "/tmp/open_flags_test/file";
+    fn size(file: &OSInode) -> i64 {
+        file.stat().size
+    }
+    fn perm(file: &OSInode) -> u32 {
+        (file.stat().mode & StatMode::PERM_MASK).bits()
+    }
+    let open = open_file;
+    with_test_tmpfs(DIR, || {
+        // unknown bits and conflicting access modes
+        assert_eq!(OpenFlags::from_user(1 << 30).err(), Some(EINVAL));
+        assert_eq!(OpenFlags::from_user(0b11).err(), Some(EINVAL));
+        // no create on a missing file
+        assert_eq!(open(FILE, OpenFlags::RDONLY, 0).err(), Some(ENOENT));
+        // create records the mode
+        let file = open(FILE, OpenFlags::CREATE | OpenFlags::WRONLY, 0o600).unwrap();
+        assert_eq!((size(&file), perm(&file)), (0, 0o600));
+        file.inner.exclusive_access().inode.write_at(0, b"hello");
+        // create on an existing file keeps data and mode
+        let file = open(FILE, OpenFlags::CREATE | OpenFlags::RDWR, 0o644).unwrap();
+        assert_eq!((file.read_all(), perm(&file)), (Vec::from(*b"hello"), 0o600));
+        // exclusive create fails on an existing file
+        let flags = OpenFlags::CREATE | OpenFlags::EXCL | OpenFlags::WRONLY;
+        assert_eq!(open(FILE, flags, 0o644).err(), Some(EEXIST));
+        // truncate without create empties the file
+        let file = open(FILE, OpenFlags::TRUNC | OpenFlags::WRONLY, 0).unwrap();
+        assert_eq!(size(&file), 0);
+        // create with truncate empties the file and keeps its mode
+        file.inner.exclusive_access().inode.write_at(0, b"hello");
+        let file = open(FILE, OpenFlags::CREATE | OpenFlags::TRUNC | OpenFlags::WRONLY, 0o644).unwrap();
+        assert_eq!((size(&file), perm(&file)), (0, 0o600));
+        // exclusive create of a new file
+        let file = open("/tmp/open_flags_test/new", flags, 0o640).unwrap();
+        assert_eq!(perm(&file), 0o640);
+        // directories
+        assert!(open(DIR, OpenFlags::RDONLY | OpenFlags::DIRECTORY, 0).is_ok());
+        assert_eq!(open(DIR, OpenFlags::CREATE, 0o644).err(), Some(EISDIR));
+        assert_eq!(open(DIR, OpenFlags::TRUNC, 0).err(), Some(EISDIR));
+        assert_eq!(open(FILE, OpenFlags::DIRECTORY, 0).err(), Some(ENOTDIR));
+        // a failed open leaves the file as it was
+        open(FILE, OpenFlags::WRONLY, 0).unwrap().inner.exclusive_access().inode.write_at(0, b"hello");
+        assert_eq!(open(FILE, OpenFlags::TRUNC | OpenFlags::DIRECTORY, 0).err(), Some(ENOTDIR));
+        assert_eq!(open(FILE, OpenFlags::RDONLY, 0).unwrap().read_all(), Vec::from(*b"hello"));
+        assert_eq!(open(FILE, OpenFlags::CREATE | OpenFlags::DIRECTORY, 0o644).err(), Some(EINVAL));
+        // the disk under the root is opened only as its mode allows
+        assert!(open("/dev/vda", OpenFlags::RDONLY, 0).is_ok());
+        assert_eq!(open("/dev/vda", OpenFlags::RDWR, 0).err(), Some(EACCES));
+    });
+    println!("open_flags_test passed!");
+}
+
+#[allow(unused)]
+/// Check which files `lseek` moves in and the positions it computes
+pub fn seek_test() {
+    use super::{SEEK_CUR, SEEK_END, SEEK_SET};
+    for device in ["/dev/console", "/dev/tty", "/dev/null", "/dev/zero"] {
+        assert!(!open_file(device, OpenFlags::RDONLY, 0).unwrap().seekable());
     }
+    let disk = open_file("/dev/vda", OpenFlags::RDONLY, 0).unwrap();
+    assert!(disk.seekable());
+    let size = disk.inner.exclusive_access().inode.size();
+    assert_eq!(disk.seek(-512, SEEK_END), Ok(size - 512));
+    assert_eq!(disk.seek(-1024, SEEK_CUR), Ok(size - 1536));
+    assert_eq!(disk.seek(-1, SEEK_SET).err(), Some(EINVAL));
+    assert_eq!(disk.seek(0, 3).err(), Some(EINVAL));
+    println!("seek_test passed!");
 }
@@ -1,23 +1,56 @@
 //! File system in os
+mod devfs;
+mod easyfs;
+mod epoll;
 mod inode;
+mod path;
+mod vfs;
 mod stdio;
 mod pipe;
+mod poll;
+mod procfs;
+mod socket;
+mod tmpfs;
 
 use crate::mm::UserBuffer;
+use crate::syscall::errno::{EINVAL, ENOTDIR, ESPIPE};
+use crate::task::{MailFile, TaskControlBlock};
+use crate::timer::TimeSpec;
+use alloc::string::String;
+use alloc::sync::Arc;
 
+/// File status, laid out as `struct stat` of Linux on riscv64
 #[repr(C)]
 #[derive(Debug)]
 pub struct Stat {
-    /// 文件所在磁盘驱动器号，该实验中写死为 0 即可
+    /// 文件所在文件系统的设备号
     pub dev: u64,
     /// inode 文件所在 inode 编号
     pub ino: u64,
-    /// 文件类型
+    /// 文件类型与权限位
     pub mode: StatMode,
     /// 硬链接数量，初始为1
     pub nlink: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// 设备文件对应的设备号
+    pub rdev: u64,
+    pad1: u64,
+    /// 文件大小，单位为字节
+    pub size: i64,
+    /// 推荐的 I/O 块大小
+    pub blksize: i32,
+    pad2: i32,
+    /// 占用的 512 字节块数
+    pub blocks: i64,
+    /// 最后访问时间
+    pub atime: TimeSpec,
+    /// 最后修改内容的时间
+    pub mtime: TimeSpec,
+    /// 最后修改 inode 的时间
+    pub ctime: TimeSpec,
     /// 无需考虑，为了兼容性设计
-    pad: [u64; 7],
+    pad3: [u32; 2],
 }
 
 impl Stat {
@@ -26,20 +59,56 @@ impl Stat {
             dev: 0,
             ino: 0,
             mode: StatMode::NULL,
-            nlink: 0, 
-            pad: [0; 7],
+            nlink: 0,
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            pad1: 0,
+            size: 0,
+            blksize: 512,
+            pad2: 0,
+            blocks: 0,
+            atime: TimeSpec::zero(),
+            mtime: TimeSpec::zero(),
+            ctime: TimeSpec::zero(),
+            pad3: [0; 2],
         }
     }
 }
 
 bitflags! {
-    #[derive(Debug)]
+    #[derive(Copy, Clone, PartialEq, Eq, Debug)]
     pub struct StatMode: u32 {
         const NULL  = 0;
+        /// bits of the file type
+        const TYPE_MASK = 0o170000;
+        /// named pipe
+        const FIFO  = 0o010000;
+        /// character device
+        const CHR   = 0o020000;
         /// directory
         const DIR   = 0o040000;
+        /// block device
+        const BLK   = 0o060000;
         /// ordinary regular file
         const FILE  = 0o100000;
+        /// symbolic link
+        const LNK   = 0o120000;
+        /// socket
+        const SOCK  = 0o140000;
+        /// bits of the permissions
+        const PERM_MASK = 0o7777;
+    }
+}
+
+impl StatMode {
+    /// Mode of type `file_type` with permission bits `perm`
+    pub fn new(file_type: StatMode, perm: u32) -> Self {
+        Self::from_bits_retain(file_type.bits() | (perm & Self::PERM_MASK.bits()))
+    }
+    /// The file type bits alone
+    pub fn file_type(&self) -> StatMode {
+        *self & Self::TYPE_MASK
     }
 }
 
@@ -54,8 +123,104 @@ pub trait File: Send + Sync {
     /// Write `UserBuffer` to file
     fn write(&self, buf: UserBuffer) -> usize;
     fn stat(&self) -> Stat;
+    /// Absolute path of the file, if it lives in the directory tree
+    fn path(&self) -> Option<String>;
+    /// If the file has a position which can be moved
+    fn seekable(&self) -> bool;
+    /// Move the position as `lseek` does, return the new position
+    fn seek(&self, _offset: isize, _whence: usize) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// Read at `offset` without moving the position
+    fn read_at(&self, _offset: usize, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// Write at `offset` without moving the position
+    fn write_at(&self, _offset: usize, _buf: UserBuffer) -> Result<usize, isize> {
+        Err(ESPIPE)
+    }
+    /// Set the size of the file as `ftruncate` does
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+    /// Fill `buf` with `linux_dirent64` records from the position on,
+    /// return the number of bytes filled
+    fn getdents(&self, _buf: &mut [u8]) -> Result<usize, isize> {
+        Err(ENOTDIR)
+    }
+    /// Status flags of the open file, `APPEND` and `NONBLOCK`
+    fn status_flags(&self) -> OpenFlags {
+        OpenFlags::empty()
+    }
+    /// Replace the status flags, ignored by files which keep none
+    fn set_status_flags(&self, _flags: OpenFlags) {}
+    /// Whether `read` would return without blocking
+    fn read_ready(&self) -> bool {
+        true
+    }
+    /// Whether `write` would return without blocking
+    fn write_ready(&self) -> bool {
+        true
+    }
+    /// Events ready on the file
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable() && self.read_ready() {
+            events |= PollEvents::IN;
+        }
+        if self.writable() && self.write_ready() {
+            events |= PollEvents::OUT;
+        }
+        events
+    }
+    /// Wake `task` once the events ready may have changed, return false if
+    /// the file cannot tell and has to be polled again
+    fn add_waiter(&self, _task: &Arc<TaskControlBlock>) -> bool {
+        false
+    }
+    /// Undo `add_waiter`
+    fn remove_waiter(&self, _task: &Arc<TaskControlBlock>) {}
+    /// The epoll instance, if the file is one
+    fn as_epoll(&self) -> Option<&EpollFile> {
+        None
+    }
+    /// The pipe end, if the file is one
+    fn as_pipe(&self) -> Option<&Pipe> {
+        None
+    }
+    /// The socket, if the file is one
+    fn as_socket(&self) -> Option<&Socket> {
+        None
+    }
+    /// The open mailbox, if the file is one
+    fn as_mailbox(&self) -> Option<&MailFile> {
+        None
+    }
+}
+
+/// `whence` of `lseek`: set the position to `offset`
+pub const SEEK_SET: usize = 0;
+/// `whence` of `lseek`: move the position by `offset`
+pub const SEEK_CUR: usize = 1;
+/// `whence` of `lseek`: set the position to the file size plus `offset`
+pub const SEEK_END: usize = 2;
+
+/// The position `lseek` moves to from `pos` in a file of `size` bytes
+pub fn seek_position(pos: usize, size: usize, offset: isize, whence: usize) -> Result<usize, isize> {
+    let base = match whence {
+        SEEK_SET => 0,
+        SEEK_CUR => pos,
+        SEEK_END => size,
+        _ => return Err(EINVAL),
+    };
+    base.checked_add_signed(offset).ok_or(EINVAL)
 }
 
-pub use inode::{OSInode, OpenFlags, list_apps, open_file, create_hard_link, delete_hard_link, hard_link_cnt};
+pub use inode::{OSInode, OpenFlags, list_apps, open_file, open_path, make_dir, make_node, remove_dir, create_hard_link, delete_hard_link, readlink, rename, symlink, truncate};
+pub use vfs::{lookup, lookup_nofollow, mount, stat_inode, umount, InodeType};
+pub use path::normalize;
 pub use stdio::{Stdin, Stdout};
-pub use pipe::make_pipe;
+pub use pipe::{make_pipe, Pipe};
+pub use poll::{poll_wait, PollEvents};
+pub use epoll::{EpollEvent, EpollFile};
+pub use socket::{Received, Socket, SocketType, UnixAddr, AF_UNIX, SOCKET_BUFFER_SIZE};
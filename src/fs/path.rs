@@ -0,0 +1,57 @@
+//! Lexical path handling
+//!
+//! Paths are resolved to normalized absolute paths before they reach the
+//! filesystem: `.` is dropped and `..` removes the previous component,
+//! stopping at the root.
+use alloc::string::String;
+use alloc::vec::Vec;
+
+/// Split `path` into its components, ignoring empty ones and `.`
+pub fn components(path: &str) -> impl Iterator<Item = &str> {
+    path.split('/').filter(|name| !name.is_empty() && *name != ".")
+}
+
+/// Resolve `path` against the absolute directory `base` into a normalized
+/// absolute path
+pub fn normalize(base: &str, path: &str) -> String {
+    let mut stack: Vec<&str> = Vec::new();
+    let prefix = if path.starts_with('/') { "" } else { base };
+    for name in components(prefix).chain(components(path)) {
+        if name == ".." {
+            stack.pop();
+        } else {
+            stack.push(name);
+        }
+    }
+    let mut normalized = String::new();
+    for name in stack {
+        normalized.push('/');
+        normalized.push_str(name);
+    }
+    if normalized.is_empty() {
+        normalized.push('/');
+    }
+    normalized
+}
+
+/// Append `name` to the normalized absolute directory `dir`
+pub fn join(dir: &str, name: &str) -> String {
+    let mut path = String::from(dir);
+    if path != "/" {
+        path.push('/');
+    }
+    path.push_str(name);
+    path
+}
+
+/// Split a normalized absolute path into its parent directory and last
+/// component, `None` for the root
+pub fn split_parent(path: &str) -> Option<(&str, &str)> {
+    let pos = path.rfind('/')?;
+    let name = &path[pos + 1..];
+    if name.is_empty() {
+        return None;
+    }
+    let parent = if pos == 0 { "/" } else { &path[..pos] };
+    Some((parent, name))
+}
@@ -1,108 +1,268 @@
-use super::File;
-use crate::mm::UserBuffer;
-use crate::sync::UPSafeCell;
+use super::vfs::{stat_inode, Dentry};
+use super::{File, OpenFlags, PollEvents};
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::vec;
+use alloc::vec::Vec;
+use crate::config::{PAGE_SIZE, PIPE_MAX_SIZE};
+use crate::mm::{frame_alloc, FrameTracker, UserBuffer};
+use crate::sync::{UPSafeCell, WaitQueue};
+use crate::syscall::errno::{EBUSY, ENOMEM, ENXIO, EPERM};
 use alloc::sync::{Arc, Weak};
+use lazy_static::*;
 
-use crate::task::suspend_current_and_run_next;
+use crate::task::{suspend_current_and_run_next, TaskControlBlock};
+
+/// Writes of at most `PIPE_BUF` bytes are not interleaved with other writes
+pub const PIPE_BUF: usize = PAGE_SIZE;
 
 pub struct Pipe {
     readable: bool,
     writable: bool,
+    /// `NONBLOCK` if set on the end
+    status: UPSafeCell<OpenFlags>,
     buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+    /// the FIFO the end was opened from, `None` for an anonymous pipe
+    fifo: Option<Dentry>,
 }
 
 impl Pipe {
-    pub fn read_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
+    fn new(
+        buffer: Arc<UPSafeCell<PipeRingBuffer>>,
+        readable: bool,
+        writable: bool,
+        status: OpenFlags,
+        fifo: Option<Dentry>,
+    ) -> Self {
+        let mut ring_buffer = buffer.exclusive_access();
+        if readable {
+            ring_buffer.readers += 1;
+            ring_buffer.reader_opens += 1;
+        }
+        if writable {
+            ring_buffer.writers += 1;
+            ring_buffer.writer_opens += 1;
+        }
+        drop(ring_buffer);
         Self {
-            readable: true,
-            writable: false,
+            readable,
+            writable,
+            status: unsafe { UPSafeCell::new(status) },
             buffer,
+            fifo,
         }
     }
-    pub fn write_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>) -> Self {
-        Self {
-            readable: false,
-            writable: true,
-            buffer,
+    pub fn read_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>, status: OpenFlags) -> Self {
+        Self::new(buffer, true, false, status, None)
+    }
+    pub fn write_end_with_buffer(buffer: Arc<UPSafeCell<PipeRingBuffer>>, status: OpenFlags) -> Self {
+        Self::new(buffer, false, true, status, None)
+    }
+    fn nonblock(&self) -> bool {
+        self.status.exclusive_access().contains(OpenFlags::NONBLOCK)
+    }
+    /// Size of the buffer in bytes
+    pub fn capacity(&self) -> usize {
+        self.buffer.exclusive_access().capacity()
+    }
+    /// Resize the buffer to hold at least `size` bytes, rounded up to a
+    /// power of two of pages, return the new size
+    pub fn set_capacity(&self, size: usize) -> Result<usize, isize> {
+        if size > PIPE_MAX_SIZE {
+            return Err(EPERM);
         }
+        let pages = size.div_ceil(PAGE_SIZE).max(1).next_power_of_two();
+        let mut ring_buffer = self.buffer.exclusive_access();
+        ring_buffer.resize(pages)?;
+        // room may be made for the writers
+        ring_buffer.waiters.wake_all();
+        Ok(ring_buffer.capacity())
     }
 }
 
-const RING_BUFFER_SIZE: usize = 32;
-
-#[derive(Copy, Clone, PartialEq)]
-enum RingBufferStatus {
-    Full,
-    Empty,
-    Normal,
+/// Allocate the pages of a buffer
+fn alloc_pages(count: usize) -> Result<Vec<FrameTracker>, isize> {
+    (0..count).map(|_| frame_alloc().ok_or(ENOMEM)).collect()
 }
 
+/// The buffer shared by the ends of a pipe, a ring over whole pages
 pub struct PipeRingBuffer {
-    arr: [u8; RING_BUFFER_SIZE],
+    pages: Vec<FrameTracker>,
+    /// position of the first byte to read
     head: usize,
-    tail: usize,
-    status: RingBufferStatus,
-    write_end: Option<Weak<Pipe>>,
+    /// number of bytes held
+    len: usize,
+    /// open read ends, each shared by the fds `dup` and `fork` make
+    readers: usize,
+    /// open write ends
+    writers: usize,
+    /// read ends ever opened, so an opener of a FIFO waiting for a reader
+    /// sees one that came and went
+    reader_opens: usize,
+    /// write ends ever opened
+    writer_opens: usize,
+    /// tasks polling either end
+    waiters: WaitQueue,
 }
 
 impl PipeRingBuffer {
-    pub fn new() -> Self {
-        Self {
-            arr: [0; RING_BUFFER_SIZE],
+    /// An empty buffer of one page, with no ends
+    pub fn new() -> Result<Self, isize> {
+        Ok(Self {
+            pages: alloc_pages(1)?,
             head: 0,
-            tail: 0,
-            status: RingBufferStatus::Empty,
-            write_end: None,
-        }
+            len: 0,
+            readers: 0,
+            writers: 0,
+            reader_opens: 0,
+            writer_opens: 0,
+            waiters: WaitQueue::new(),
+        })
     }
-    pub fn set_write_end(&mut self, write_end: &Arc<Pipe>) {
-        self.write_end = Some(Arc::downgrade(write_end));
+    pub fn capacity(&self) -> usize {
+        self.pages.len() * PAGE_SIZE
+    }
+    pub fn available_read(&self) -> usize {
+        self.len
     }
-    pub fn write_byte(&mut self, byte: u8) {
-        self.status = RingBufferStatus::Normal;
-        self.arr[self.tail] = byte;
-        self.tail = (self.tail + 1) % RING_BUFFER_SIZE;
-        if self.tail == self.head {
-            self.status = RingBufferStatus::Full;
+    pub fn available_write(&self) -> usize {
+        self.capacity() - self.len
+    }
+    pub fn all_read_ends_closed(&self) -> bool {
+        self.readers == 0
+    }
+    pub fn all_write_ends_closed(&self) -> bool {
+        self.writers == 0
+    }
+    /// At most `max` bytes of the ring from `pos` on, up to the end of its page
+    fn chunk(&self, pos: usize, max: usize) -> &'static mut [u8] {
+        let pos = pos % self.capacity();
+        let offset = pos % PAGE_SIZE;
+        let len = max.min(PAGE_SIZE - offset);
+        &mut self.pages[pos / PAGE_SIZE].ppn.get_bytes_array()[offset..offset + len]
+    }
+    /// Move as many bytes as held to `dst`, return their number
+    pub fn read_into(&mut self, dst: &mut [u8]) -> usize {
+        let total = dst.len().min(self.len);
+        let mut done = 0;
+        while done < total {
+            let chunk = self.chunk(self.head, total - done);
+            dst[done..done + chunk.len()].copy_from_slice(chunk);
+            done += chunk.len();
+            self.head = (self.head + chunk.len()) % self.capacity();
+            self.len -= chunk.len();
         }
+        total
     }
-    pub fn read_byte(&mut self) -> u8 {
-        self.status = RingBufferStatus::Normal;
-        let c = self.arr[self.head];
-        self.head = (self.head + 1) % RING_BUFFER_SIZE;
-        if self.head == self.tail {
-            self.status = RingBufferStatus::Empty;
+    /// Append as many bytes of `src` as fit, return their number
+    pub fn write_from(&mut self, src: &[u8]) -> usize {
+        let total = src.len().min(self.available_write());
+        let mut done = 0;
+        while done < total {
+            let chunk = self.chunk(self.head + self.len, total - done);
+            let len = chunk.len();
+            chunk.copy_from_slice(&src[done..done + len]);
+            done += len;
+            self.len += len;
         }
-        c
+        total
     }
-    pub fn available_read(&self) -> usize {
-        if self.status == RingBufferStatus::Empty {
-            0
-        } else if self.tail > self.head {
-            self.tail - self.head
-        } else {
-            self.tail + RING_BUFFER_SIZE - self.head
+    /// Change the buffer to `count` pages keeping what it holds, `EBUSY` if
+    /// that does not fit
+    pub fn resize(&mut self, count: usize) -> Result<(), isize> {
+        if count * PAGE_SIZE < self.len {
+            return Err(EBUSY);
         }
+        let pages = alloc_pages(count)?;
+        let mut data = vec![0u8; self.len];
+        self.read_into(&mut data);
+        self.pages = pages;
+        self.head = 0;
+        self.write_from(&data);
+        Ok(())
     }
-    pub fn available_write(&self) -> usize {
-        if self.status == RingBufferStatus::Full {
-            0
-        } else {
-            RING_BUFFER_SIZE - self.available_read()
+}
+
+/// Return (read_end, write_end), both with the `NONBLOCK` of `flags`,
+/// `ENOMEM` if there is no page for the buffer
+pub fn make_pipe(flags: OpenFlags) -> Result<(Arc<Pipe>, Arc<Pipe>), isize> {
+    let status = flags & OpenFlags::NONBLOCK;
+    let buffer = Arc::new(unsafe { UPSafeCell::new(PipeRingBuffer::new()?) });
+    let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone(), status));
+    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone(), status));
+    Ok((read_end, write_end))
+}
+
+lazy_static! {
+    /// Buffers of the open FIFOs by device and inode number, a buffer goes
+    /// with the last end and the data left in it
+    static ref FIFOS: UPSafeCell<BTreeMap<(u64, u64), Weak<UPSafeCell<PipeRingBuffer>>>> =
+        unsafe { UPSafeCell::new(BTreeMap::new()) };
+}
+
+/// Open an end of the FIFO `dentry` with `flags`, sharing the buffer of the
+/// ends already open.
+///
+/// A read-only or write-only open waits until the other side is opened
+/// too, unless `NONBLOCK` is given: then a reader returns at once and a
+/// writer fails with `ENXIO` if there is no reader. A read-write open
+/// never waits.
+pub fn open_fifo(dentry: Dentry, flags: OpenFlags) -> Result<Arc<Pipe>, isize> {
+    let (readable, writable) = flags.read_write();
+    let nonblock = flags.contains(OpenFlags::NONBLOCK);
+    let key = (dentry.dev, dentry.inode.ino());
+    let buffer = {
+        let mut fifos = FIFOS.exclusive_access();
+        fifos.retain(|_, buffer| buffer.strong_count() > 0);
+        match fifos.get(&key).and_then(|buffer| buffer.upgrade()) {
+            Some(buffer) => buffer,
+            None => {
+                let buffer = Arc::new(unsafe { UPSafeCell::new(PipeRingBuffer::new()?) });
+                fifos.insert(key, Arc::downgrade(&buffer));
+                buffer
+            }
         }
+    };
+    if writable && !readable && nonblock && buffer.exclusive_access().all_read_ends_closed() {
+        return Err(ENXIO);
     }
-    pub fn all_write_ends_closed(&self) -> bool {
-        self.write_end.as_ref().unwrap().upgrade().is_none()
+    let pipe = Arc::new(Pipe::new(buffer.clone(), readable, writable, flags & OpenFlags::NONBLOCK, Some(dentry)));
+    // pollers of the other side see the new end
+    buffer.exclusive_access().waiters.wake_all();
+    if readable == writable || nonblock {
+        return Ok(pipe);
+    }
+    let (readers_seen, writers_seen) = {
+        let ring_buffer = buffer.exclusive_access();
+        (ring_buffer.reader_opens, ring_buffer.writer_opens)
+    };
+    loop {
+        let ring_buffer = buffer.exclusive_access();
+        let met = if readable {
+            ring_buffer.writers > 0 || ring_buffer.writer_opens != writers_seen
+        } else {
+            ring_buffer.readers > 0 || ring_buffer.reader_opens != readers_seen
+        };
+        if met {
+            return Ok(pipe);
+        }
+        drop(ring_buffer);
+        suspend_current_and_run_next();
     }
 }
 
-/// Return (read_end, write_end)
-pub fn make_pipe() -> (Arc<Pipe>, Arc<Pipe>) {
-    let buffer = Arc::new(unsafe { UPSafeCell::new(PipeRingBuffer::new()) });
-    let read_end = Arc::new(Pipe::read_end_with_buffer(buffer.clone()));
-    let write_end = Arc::new(Pipe::write_end_with_buffer(buffer.clone()));
-    buffer.exclusive_access().set_write_end(&write_end);
-    (read_end, write_end)
+impl Drop for Pipe {
+    fn drop(&mut self) {
+        let mut ring_buffer = self.buffer.exclusive_access();
+        if self.readable {
+            ring_buffer.readers -= 1;
+        }
+        if self.writable {
+            ring_buffer.writers -= 1;
+        }
+        // the closing of an end is a hang-up or an error for the pollers
+        ring_buffer.waiters.wake_all();
+    }
 }
 
 impl File for Pipe {
@@ -114,63 +274,176 @@ impl File for Pipe {
     }
     fn read(&self, buf: UserBuffer) -> usize {
         assert!(self.readable());
-        let want_to_read = buf.len();
-        let mut buf_iter = buf.into_iter();
-        let mut already_read = 0usize;
-        loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
-            let loop_read = ring_buffer.available_read();
-            if loop_read == 0 {
-                if ring_buffer.all_write_ends_closed() {
-                    return already_read;
-                }
-                drop(ring_buffer);
-                suspend_current_and_run_next();
-                continue;
+        if buf.len() == 0 {
+            return 0;
+        }
+        let mut ring_buffer = loop {
+            let ring_buffer = self.buffer.exclusive_access();
+            if ring_buffer.available_read() > 0 {
+                break ring_buffer;
             }
-            for _ in 0..loop_read {
-                if let Some(byte_ref) = buf_iter.next() {
-                    unsafe {
-                        *byte_ref = ring_buffer.read_byte();
-                    }
-                    already_read += 1;
-                    if already_read == want_to_read {
-                        return want_to_read;
-                    }
-                } else {
-                    return already_read;
-                }
+            if ring_buffer.all_write_ends_closed() || self.nonblock() {
+                return 0;
             }
+            drop(ring_buffer);
+            suspend_current_and_run_next();
+        };
+        let mut already_read = 0usize;
+        for slice in buf.buffers {
+            already_read += ring_buffer.read_into(slice);
         }
+        // room is made for the writers
+        ring_buffer.waiters.wake_all();
+        already_read
     }
+    /// Return the number of bytes written, which is short if all the read
+    /// ends close or the end is nonblocking and the buffer fills
     fn write(&self, buf: UserBuffer) -> usize {
         assert!(self.writable());
         let want_to_write = buf.len();
-        let mut buf_iter = buf.into_iter();
+        // a small write waits until it fits as a whole
+        let atomic = want_to_write <= PIPE_BUF;
         let mut already_write = 0usize;
-        loop {
-            let mut ring_buffer = self.buffer.exclusive_access();
-            let loop_write = ring_buffer.available_write();
-            if loop_write == 0 {
-                drop(ring_buffer);
-                suspend_current_and_run_next();
-                continue;
-            }
-            // write at most loop_write bytes
-            for _ in 0..loop_write {
-                if let Some(byte_ref) = buf_iter.next() {
-                    ring_buffer.write_byte(unsafe { *byte_ref });
-                    already_write += 1;
-                    if already_write == want_to_write {
-                        return want_to_write;
-                    }
-                } else {
+        for slice in buf.buffers.iter() {
+            let mut written = 0;
+            while written < slice.len() {
+                let mut ring_buffer = self.buffer.exclusive_access();
+                if ring_buffer.all_read_ends_closed() {
                     return already_write;
                 }
+                let needed = if atomic { want_to_write - already_write } else { 1 };
+                if ring_buffer.available_write() < needed {
+                    if self.nonblock() {
+                        return already_write;
+                    }
+                    drop(ring_buffer);
+                    suspend_current_and_run_next();
+                    continue;
+                }
+                let loop_write = ring_buffer.write_from(&slice[written..]);
+                written += loop_write;
+                already_write += loop_write;
+                // data comes for the readers
+                ring_buffer.waiters.wake_all();
             }
         }
+        already_write
+    }
+    fn path(&self) -> Option<String> {
+        self.fifo.as_ref().map(|dentry| dentry.path.clone())
+    }
+    fn seekable(&self) -> bool {
+        false
+    }
+    fn status_flags(&self) -> OpenFlags {
+        *self.status.exclusive_access()
+    }
+    fn set_status_flags(&self, flags: OpenFlags) {
+        *self.status.exclusive_access() = flags & OpenFlags::NONBLOCK;
+    }
+    fn read_ready(&self) -> bool {
+        let ring_buffer = self.buffer.exclusive_access();
+        ring_buffer.available_read() > 0 || ring_buffer.all_write_ends_closed()
+    }
+    fn write_ready(&self) -> bool {
+        let ring_buffer = self.buffer.exclusive_access();
+        ring_buffer.available_write() > 0 || ring_buffer.all_read_ends_closed()
+    }
+    fn poll(&self) -> PollEvents {
+        let ring_buffer = self.buffer.exclusive_access();
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if ring_buffer.available_read() > 0 {
+                events |= PollEvents::IN;
+            }
+            if ring_buffer.all_write_ends_closed() {
+                events |= PollEvents::HUP;
+            }
+        }
+        if self.writable {
+            if ring_buffer.available_write() > 0 {
+                events |= PollEvents::OUT;
+            }
+            if ring_buffer.all_read_ends_closed() {
+                events |= PollEvents::ERR;
+            }
+        }
+        events
+    }
+    fn add_waiter(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.buffer.exclusive_access().waiters.add(task);
+        true
+    }
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.buffer.exclusive_access().waiters.remove(task);
+    }
+    fn as_pipe(&self) -> Option<&Pipe> {
+        Some(self)
     }
     fn stat(&self) -> super::Stat {
-        super::Stat::empty()
+        if let Some(dentry) = &self.fifo {
+            return stat_inode(dentry.dev, &dentry.inode);
+        }
+        let mut st = super::Stat::empty();
+        st.mode = super::StatMode::new(super::StatMode::FIFO, 0o600);
+        st.nlink = 1;
+        st.blksize = PIPE_BUF as i32;
+        st
     }
 }
+
+#[allow(unused)]
+/// Check that the ring keeps the byte order across pages and through a
+/// resize, and refuses to shrink below what it holds
+pub fn pipe_buffer_test() {
+    let mut ring_buffer = PipeRingBuffer::new().unwrap();
+    ring_buffer.resize(2).unwrap();
+    let data: Vec<u8> = (0..3 * PAGE_SIZE).map(|i| (i % 251) as u8).collect();
+    // move the head near the end of the first page
+    assert_eq!(ring_buffer.write_from(&data[..PAGE_SIZE - 10]), PAGE_SIZE - 10);
+    let mut out = vec![0u8; 3 * PAGE_SIZE];
+    assert_eq!(ring_buffer.read_into(&mut out), PAGE_SIZE - 10);
+    // a write wrapping around both pages is cut at the capacity
+    assert_eq!(ring_buffer.write_from(&data), 2 * PAGE_SIZE);
+    assert_eq!(ring_buffer.available_write(), 0);
+    assert_eq!(ring_buffer.resize(1), Err(EBUSY));
+    ring_buffer.resize(4).unwrap();
+    assert_eq!(ring_buffer.capacity(), 4 * PAGE_SIZE);
+    assert_eq!(ring_buffer.read_into(&mut out), 2 * PAGE_SIZE);
+    assert_eq!(out[..2 * PAGE_SIZE], data[..2 * PAGE_SIZE]);
+    assert_eq!(ring_buffer.available_read(), 0);
+}
+
+#[allow(unused)]
+/// Check the nonblocking opens of a FIFO on a tmpfs mounted for the test
+/// and that its ends share one buffer
+pub fn fifo_test() {
+    use super::inode::{delete_hard_link, make_node, open_path, with_test_tmpfs};
+    use super::vfs::InodeType;
+    use super::StatMode;
+    use crate::println;
+    const DIR: &str = "/tmp/fifo_test";
+    const FIFO: &str = "/tmp/fifo_test/fifo";
+    with_test_tmpfs(DIR, || {
+        make_node(FIFO, InodeType::Fifo, 0o640).unwrap();
+        let write_only = OpenFlags::WRONLY | OpenFlags::NONBLOCK;
+        // no reader yet
+        assert_eq!(open_path(FIFO, write_only, 0).err(), Some(ENXIO));
+        let reader = open_path(FIFO, OpenFlags::RDONLY | OpenFlags::NONBLOCK, 0).unwrap();
+        assert_eq!(reader.poll(), PollEvents::HUP);
+        let writer = open_path(FIFO, write_only, 0).unwrap();
+        assert_eq!(reader.poll(), PollEvents::empty());
+        assert_eq!(writer.poll(), PollEvents::OUT);
+        let st = writer.stat();
+        assert_eq!((st.mode.file_type(), (st.mode & StatMode::PERM_MASK).bits()), (StatMode::FIFO, 0o640));
+        assert_eq!(writer.path().as_deref(), Some(FIFO));
+        // a read-write open never waits and shares the buffer
+        let both = open_path(FIFO, OpenFlags::RDWR, 0).unwrap();
+        assert_eq!(reader.as_pipe().unwrap().set_capacity(2 * PAGE_SIZE), Ok(2 * PAGE_SIZE));
+        assert_eq!(both.as_pipe().unwrap().capacity(), 2 * PAGE_SIZE);
+        drop((reader, writer, both));
+        assert!(FIFOS.exclusive_access().values().all(|buffer| buffer.strong_count() == 0));
+        delete_hard_link(FIFO).unwrap();
+    });
+    println!("fifo_test passed!");
+}
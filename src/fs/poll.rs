@@ -0,0 +1,70 @@
+//! Readiness of files and waiting for several of them at once
+use super::File;
+use crate::syscall::errno::EINTR;
+use crate::task::{block_current_and_run_next, current_has_pending_signal, current_task};
+use crate::timer::{add_timer, get_time_ms, remove_timer, MSEC_PER_TICK};
+use alloc::sync::Arc;
+
+bitflags! {
+    /// Events of `poll`, also the low bits of the events of `epoll`
+    #[derive(Copy, Clone, PartialEq, Debug)]
+    pub struct PollEvents: u16 {
+        /// data to read
+        const IN = 0x001;
+        /// urgent data to read
+        const PRI = 0x002;
+        /// writing would not block
+        const OUT = 0x004;
+        /// error, reported whether asked or not
+        const ERR = 0x008;
+        /// the other end is closed, reported whether asked or not
+        const HUP = 0x010;
+        /// the fd is not open, only for `poll`
+        const NVAL = 0x020;
+    }
+}
+
+/// Call `scan` until it reports ready files or `timeout_ms` passes, and
+/// return its last result, 0 on timeout, `EINTR` if a signal comes first.
+/// In between the task blocks on the wait queues of `files`; files which
+/// cannot wake it up are checked again every tick. `None` waits forever.
+pub fn poll_wait(
+    files: &[Arc<dyn File + Send + Sync>],
+    timeout_ms: Option<usize>,
+    mut scan: impl FnMut() -> usize,
+) -> Result<usize, isize> {
+    let deadline = timeout_ms.map(|ms| get_time_ms() + ms);
+    let task = current_task().unwrap();
+    loop {
+        let ready = scan();
+        if ready > 0 {
+            return Ok(ready);
+        }
+        let now = get_time_ms();
+        if deadline.is_some_and(|deadline| now >= deadline) {
+            return Ok(0);
+        }
+        if current_has_pending_signal() {
+            return Err(EINTR);
+        }
+        let mut notified = true;
+        for file in files {
+            if !file.add_waiter(&task) {
+                notified = false;
+            }
+        }
+        let wake_ms = if notified {
+            deadline
+        } else {
+            Some(deadline.map_or(now + MSEC_PER_TICK, |deadline| deadline.min(now + MSEC_PER_TICK)))
+        };
+        if let Some(wake_ms) = wake_ms {
+            add_timer(wake_ms, task.clone());
+        }
+        block_current_and_run_next();
+        remove_timer(&task);
+        for file in files {
+            file.remove_waiter(&task);
+        }
+    }
+}
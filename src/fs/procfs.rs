@@ -0,0 +1,372 @@
+//! Read-only process filesystem, mounted on `/proc`
+//!
+//! A file is rendered when it is first read or stat'ed through the inode
+//! a path walk found, so a reader of one open file sees a consistent
+//! snapshot no matter how many `read`s it takes.
+//!
+//! - `meminfo`, `uptime`, `stat`: system-wide statistics
+//! - `<pid>/status`, `<pid>/maps`: per task
+//! - `<pid>/fd/<fd>`: links to the files open in the task
+//! - `self`: a link to the directory of the calling task
+use super::vfs::{InodeAttr, InodeType, SuperBlock, VfsInode};
+use super::StatMode;
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_stats, heap_stats, MapPermission, VirtAddr};
+use crate::sync::UPSafeCell;
+use crate::syscall::errno::{EINVAL, ENOENT, EPERM};
+use crate::task::{all_tasks, cpu_time_stats, current_task, pid2task, TaskControlBlock, TaskStatus};
+use crate::timer::{cycles_to_ms, cycles_to_ticks, get_time};
+use alloc::format;
+use alloc::string::{String, ToString};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::fmt::Write;
+
+/// The process filesystem
+pub struct ProcFs;
+
+impl SuperBlock for ProcFs {
+    fn root(&self) -> Arc<dyn VfsInode> {
+        ProcInode::new(ProcEntry::Root)
+    }
+    fn fs_type(&self) -> &'static str {
+        "proc"
+    }
+}
+
+/// What an inode of the process filesystem shows
+#[derive(Copy, Clone, PartialEq, Debug)]
+enum ProcEntry {
+    Root,
+    Meminfo,
+    Uptime,
+    Stat,
+    /// `self`
+    SelfLink,
+    /// `<pid>`
+    Task(usize),
+    /// `<pid>/status`
+    Status(usize),
+    /// `<pid>/maps`
+    Maps(usize),
+    /// `<pid>/fd`
+    FdDir(usize),
+    /// `<pid>/fd/<fd>`
+    Fd(usize, usize),
+}
+
+/// Entries of the root besides the tasks
+const ROOT_ENTRIES: &[(&str, ProcEntry)] = &[
+    ("meminfo", ProcEntry::Meminfo),
+    ("uptime", ProcEntry::Uptime),
+    ("stat", ProcEntry::Stat),
+    ("self", ProcEntry::SelfLink),
+];
+
+/// Entries of the directory of a task
+const TASK_ENTRIES: &[&str] = &["status", "maps", "fd"];
+
+impl ProcEntry {
+    /// Inode number: the root entries come first, the entries of a task
+    /// are numbered after its pid in the upper half
+    fn ino(&self) -> u64 {
+        let task = |pid: usize, slot: usize| ((pid as u64 + 1) << 32) | slot as u64;
+        match *self {
+            ProcEntry::Root => 1,
+            ProcEntry::Meminfo => 2,
+            ProcEntry::Uptime => 3,
+            ProcEntry::Stat => 4,
+            ProcEntry::SelfLink => 5,
+            ProcEntry::Task(pid) => task(pid, 0),
+            ProcEntry::Status(pid) => task(pid, 1),
+            ProcEntry::Maps(pid) => task(pid, 2),
+            ProcEntry::FdDir(pid) => task(pid, 3),
+            ProcEntry::Fd(pid, fd) => task(pid, 4 + fd),
+        }
+    }
+    fn itype(&self) -> InodeType {
+        match self {
+            ProcEntry::Root | ProcEntry::Task(_) | ProcEntry::FdDir(_) => InodeType::Dir,
+            ProcEntry::SelfLink | ProcEntry::Fd(..) => InodeType::Symlink,
+            _ => InodeType::File,
+        }
+    }
+}
+
+/// An inode of the process filesystem
+pub struct ProcInode {
+    entry: ProcEntry,
+    /// the rendered file, kept from the first access on
+    content: UPSafeCell<Option<Vec<u8>>>,
+}
+
+impl ProcInode {
+    fn new(entry: ProcEntry) -> Arc<Self> {
+        Arc::new(Self {
+            entry,
+            content: unsafe { UPSafeCell::new(None) },
+        })
+    }
+    /// Run `f` on the rendered file
+    fn with_content<T>(&self, f: impl FnOnce(&[u8]) -> T) -> T {
+        let mut content = self.content.exclusive_access();
+        let content = content.get_or_insert_with(|| render(self.entry).into_bytes());
+        f(content)
+    }
+}
+
+impl VfsInode for ProcInode {
+    fn ino(&self) -> u64 {
+        self.entry.ino()
+    }
+    fn itype(&self) -> InodeType {
+        self.entry.itype()
+    }
+    fn nlink(&self) -> u32 {
+        if self.itype() == InodeType::Dir {
+            2
+        } else {
+            1
+        }
+    }
+    fn size(&self) -> usize {
+        match self.itype() {
+            InodeType::File => self.with_content(|content| content.len()),
+            InodeType::Symlink => self.readlink().map_or(0, |target| target.len()),
+            _ => 0,
+        }
+    }
+    fn attr(&self) -> InodeAttr {
+        let mut attr = InodeAttr::default_for(self.itype());
+        attr.perm = match self.itype() {
+            InodeType::Dir => 0o555,
+            InodeType::File => 0o444,
+            _ => 0o777,
+        };
+        attr
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        if self.itype() != InodeType::File {
+            return 0;
+        }
+        self.with_content(|content| {
+            let remain = &content[offset.min(content.len())..];
+            let len = remain.len().min(buf.len());
+            buf[..len].copy_from_slice(&remain[..len]);
+            len
+        })
+    }
+    fn write_at(&self, _offset: usize, _buf: &[u8]) -> usize {
+        0
+    }
+    fn clear(&self) {}
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        let entry = match self.entry {
+            ProcEntry::Root => match ROOT_ENTRIES.iter().find(|(entry, _)| *entry == name) {
+                Some((_, entry)) => *entry,
+                None => {
+                    let pid = name.parse().ok()?;
+                    pid2task(pid)?;
+                    ProcEntry::Task(pid)
+                }
+            },
+            ProcEntry::Task(pid) => {
+                pid2task(pid)?;
+                match name {
+                    "status" => ProcEntry::Status(pid),
+                    "maps" => ProcEntry::Maps(pid),
+                    "fd" => ProcEntry::FdDir(pid),
+                    _ => return None,
+                }
+            }
+            ProcEntry::FdDir(pid) => {
+                let fd = name.parse().ok()?;
+                pid2task(pid)?.inner_exclusive_access().fd_table.get(fd).ok()?;
+                ProcEntry::Fd(pid, fd)
+            }
+            _ => return None,
+        };
+        Some(ProcInode::new(entry))
+    }
+    fn create(&self, _name: &str, _itype: InodeType) -> Result<Arc<dyn VfsInode>, isize> {
+        Err(EPERM)
+    }
+    fn link(&self, _name: &str, _target: &Arc<dyn VfsInode>) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn unlink(&self, _name: &str) -> Result<(), isize> {
+        Err(EPERM)
+    }
+    fn readlink(&self) -> Result<String, isize> {
+        match self.entry {
+            // relative, so it resolves within whichever mount it is read from
+            ProcEntry::SelfLink => Ok(current_task().unwrap().getpid().to_string()),
+            ProcEntry::Fd(pid, fd) => {
+                let file = pid2task(pid)
+                    .ok_or(ENOENT)?
+                    .inner_exclusive_access()
+                    .fd_table
+                    .get(fd)
+                    .map_err(|_| ENOENT)?;
+                // files outside the directory tree are named by their type
+                Ok(file.path().unwrap_or_else(|| {
+                    let st = file.stat();
+                    let kind = if st.mode.file_type() == StatMode::FIFO {
+                        "pipe"
+                    } else if st.mode.file_type() == StatMode::SOCK {
+                        "socket"
+                    } else {
+                        "anon_inode"
+                    };
+                    format!("{}:[{}]", kind, st.ino)
+                }))
+            }
+            _ => Err(EINVAL),
+        }
+    }
+    fn list(&self) -> Vec<String> {
+        match self.entry {
+            ProcEntry::Root => {
+                let mut names: Vec<String> = ROOT_ENTRIES.iter().map(|(name, _)| String::from(*name)).collect();
+                names.extend(all_tasks().iter().map(|task| task.getpid().to_string()));
+                names
+            }
+            ProcEntry::Task(_) => TASK_ENTRIES.iter().map(|name| String::from(*name)).collect(),
+            ProcEntry::FdDir(pid) => {
+                let Some(task) = pid2task(pid) else {
+                    return Vec::new();
+                };
+                let inner = task.inner_exclusive_access();
+                let fds = inner.fd_table.iter().map(|(fd, _)| fd.to_string()).collect();
+                fds
+            }
+            _ => Vec::new(),
+        }
+    }
+}
+
+/// Render the file of `entry`, empty once its task is gone
+fn render(entry: ProcEntry) -> String {
+    match entry {
+        ProcEntry::Meminfo => render_meminfo(),
+        ProcEntry::Uptime => render_uptime(),
+        ProcEntry::Stat => render_stat(),
+        ProcEntry::Status(pid) => pid2task(pid).map(|task| render_status(&task)).unwrap_or_default(),
+        ProcEntry::Maps(pid) => pid2task(pid).map(|task| render_maps(&task)).unwrap_or_default(),
+        _ => String::new(),
+    }
+}
+
+fn render_meminfo() -> String {
+    let (total_frames, free_frames) = frame_stats();
+    let (heap_total, heap_used) = heap_stats();
+    let mut s = String::new();
+    writeln!(s, "MemTotal:\t{} kB", total_frames * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "MemFree:\t{} kB", free_frames * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "HeapTotal:\t{} kB", heap_total / 1024).unwrap();
+    writeln!(s, "HeapUsed:\t{} kB", heap_used / 1024).unwrap();
+    s
+}
+
+/// Format milliseconds as seconds with two decimals
+fn seconds(ms: usize) -> String {
+    alloc::format!("{}.{:02}", ms / 1000, ms % 1000 / 10)
+}
+
+fn render_uptime() -> String {
+    let (_, idle) = cpu_time_stats();
+    alloc::format!("{} {}\n", seconds(cycles_to_ms(get_time())), seconds(cycles_to_ms(idle)))
+}
+
+fn render_stat() -> String {
+    let (busy, idle) = cpu_time_stats();
+    let tasks = all_tasks();
+    let (mut user, mut ctxt, mut running, mut blocked) = (0, 0, 0, 0);
+    for task in tasks.iter() {
+        let inner = task.inner_exclusive_access();
+        user += inner.task_info.utime;
+        ctxt += inner.task_info.nvcsw + inner.task_info.nivcsw;
+        match inner.task_status {
+            TaskStatus::Ready | TaskStatus::Running => running += 1,
+            TaskStatus::Blocked => blocked += 1,
+            TaskStatus::Zombie => {}
+        }
+    }
+    // the time of exited tasks is only known in total, charge it to system time
+    let user = user.min(busy);
+    let system = busy - user;
+    let mut s = String::new();
+    writeln!(
+        s,
+        "cpu  {} 0 {} {} 0 0 0",
+        cycles_to_ticks(user),
+        cycles_to_ticks(system),
+        cycles_to_ticks(idle)
+    )
+    .unwrap();
+    writeln!(s, "ctxt {}", ctxt).unwrap();
+    writeln!(s, "processes {}", tasks.len()).unwrap();
+    writeln!(s, "procs_running {}", running).unwrap();
+    writeln!(s, "procs_blocked {}", blocked).unwrap();
+    s
+}
+
+fn render_status(task: &Arc<TaskControlBlock>) -> String {
+    let inner = task.inner_exclusive_access();
+    let state = match inner.task_status {
+        TaskStatus::Ready => "R (ready)",
+        TaskStatus::Running => "R (running)",
+        TaskStatus::Blocked => "S (sleeping)",
+        TaskStatus::Zombie => "Z (zombie)",
+    };
+    let ppid = inner
+        .parent
+        .as_ref()
+        .and_then(|parent| parent.upgrade())
+        .map_or(0, |parent| parent.getpid());
+    let vm_pages: usize = inner
+        .memory_set
+        .areas_info()
+        .iter()
+        .map(|area| area.vpn_range.get_end().0 - area.vpn_range.get_start().0)
+        .sum();
+    let info = &inner.task_info;
+    let mut s = String::new();
+    writeln!(s, "Name:\t{}", inner.name).unwrap();
+    writeln!(s, "State:\t{}", state).unwrap();
+    writeln!(s, "Pid:\t{}", task.getpid()).unwrap();
+    writeln!(s, "PPid:\t{}", ppid).unwrap();
+    writeln!(s, "Policy:\t{:?}", inner.sched_policy).unwrap();
+    writeln!(s, "Priority:\t{}", inner.priority).unwrap();
+    writeln!(s, "RtPriority:\t{}", inner.rt_priority).unwrap();
+    writeln!(s, "Nice:\t{}", inner.nice).unwrap();
+    writeln!(s, "VmSize:\t{} kB", vm_pages * PAGE_SIZE / 1024).unwrap();
+    writeln!(s, "FDSize:\t{}", inner.fd_table.slots()).unwrap();
+    writeln!(s, "UserTime:\t{} ms", cycles_to_ms(info.utime)).unwrap();
+    writeln!(s, "SystemTime:\t{} ms", cycles_to_ms(info.stime)).unwrap();
+    writeln!(s, "voluntary_ctxt_switches:\t{}", info.nvcsw).unwrap();
+    writeln!(s, "nonvoluntary_ctxt_switches:\t{}", info.nivcsw).unwrap();
+    s
+}
+
+fn render_maps(task: &Arc<TaskControlBlock>) -> String {
+    let inner = task.inner_exclusive_access();
+    let mut s = String::new();
+    for area in inner.memory_set.areas_info() {
+        let start: VirtAddr = area.vpn_range.get_start().into();
+        let end: VirtAddr = area.vpn_range.get_end().into();
+        let perm = area.map_perm;
+        writeln!(
+            s,
+            "{:016x}-{:016x} {}{}{}p{}",
+            start.0,
+            end.0,
+            if perm.contains(MapPermission::R) { 'r' } else { '-' },
+            if perm.contains(MapPermission::W) { 'w' } else { '-' },
+            if perm.contains(MapPermission::X) { 'x' } else { '-' },
+            if perm.contains(MapPermission::U) { "" } else { " [kernel]" },
+        )
+        .unwrap();
+    }
+    s
+}
@@ -0,0 +1,820 @@
+//! Unix domain sockets
+//!
+//! A socket is a [`File`] around a `SocketCore`, which holds the queue of
+//! what arrives for it. Connected sockets and bound addresses refer to the
+//! core weakly, so a socket goes with its last fd and its peer sees the
+//! end of the stream.
+//!
+//! A stream connection is made by `connect` creating the server side
+//! socket at once and queueing it on the listening socket until `accept`
+//! takes it. Files passed along with the data are held in the queue and
+//! delivered with the first byte of the data they were sent with. A socket
+//! held that way keeps the receiving one alive, so passing a socket that
+//! would end up holding itself fails with `ELOOP` rather than leaking both.
+use super::inode::make_node;
+use super::vfs::{lookup, InodeType};
+use super::{File, OpenFlags, PollEvents, Stat, StatMode};
+use crate::config::PAGE_SIZE;
+use crate::mm::UserBuffer;
+use crate::sync::{UPSafeCell, WaitQueue};
+use crate::syscall::errno::{
+    EADDRINUSE, EAGAIN, ECONNREFUSED, EEXIST, EINVAL, EISCONN, ELOOP, EMSGSIZE, ENOTCONN, EOPNOTSUPP, EPIPE,
+    EPROTOTYPE,
+};
+use crate::task::{suspend_current_and_run_next, TaskControlBlock};
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::format;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Address family of Unix domain sockets
+pub const AF_UNIX: u16 = 1;
+/// Bytes a socket may hold for reading
+pub const SOCKET_BUFFER_SIZE: usize = 4 * PAGE_SIZE;
+/// Most connections waiting for `accept` on a listening socket
+pub const SOMAXCONN: usize = 4096;
+
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SocketType {
+    /// `SOCK_STREAM`, a connected byte stream
+    Stream,
+    /// `SOCK_DGRAM`, messages sent to an address
+    Datagram,
+}
+
+/// Address of a Unix domain socket
+#[derive(Clone, PartialEq, Debug)]
+pub enum UnixAddr {
+    /// an unbound socket
+    Unnamed,
+    /// a socket inode at the normalized absolute path
+    Path(String),
+    /// a name outside the filesystem, gone with the socket
+    Abstract(Vec<u8>),
+}
+
+impl UnixAddr {
+    /// The address as `struct sockaddr_un`, as long as it needs to be
+    pub fn to_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::from(AF_UNIX.to_le_bytes());
+        match self {
+            UnixAddr::Unnamed => {}
+            UnixAddr::Path(path) => {
+                bytes.extend_from_slice(path.as_bytes());
+                bytes.push(0);
+            }
+            UnixAddr::Abstract(name) => {
+                bytes.push(0);
+                bytes.extend_from_slice(name);
+            }
+        }
+        bytes
+    }
+}
+
+/// What a bound address is registered under
+#[derive(Clone, PartialEq, Eq, PartialOrd, Ord)]
+enum AddrKey {
+    /// device and inode number of the socket inode
+    Inode(u64, u64),
+    Abstract(Vec<u8>),
+}
+
+/// The bound sockets
+struct Registry {
+    bound: BTreeMap<AddrKey, Weak<SocketCore>>,
+    /// number of the next name given by autobind
+    next_auto: usize,
+}
+
+lazy_static! {
+    static ref REGISTRY: UPSafeCell<Registry> = unsafe {
+        UPSafeCell::new(Registry {
+            bound: BTreeMap::new(),
+            next_auto: 0,
+        })
+    };
+}
+
+/// The key of `addr`, `ECONNREFUSED` if no socket can be there
+fn addr_key(addr: &UnixAddr) -> Result<AddrKey, isize> {
+    match addr {
+        UnixAddr::Unnamed => Err(EINVAL),
+        UnixAddr::Path(path) => {
+            let dentry = lookup(path)?;
+            if dentry.inode.itype() != InodeType::Socket {
+                return Err(ECONNREFUSED);
+            }
+            Ok(AddrKey::Inode(dentry.dev, dentry.inode.ino()))
+        }
+        UnixAddr::Abstract(name) => Ok(AddrKey::Abstract(name.clone())),
+    }
+}
+
+/// The socket bound to `addr`
+fn find_bound(addr: &UnixAddr) -> Result<Arc<SocketCore>, isize> {
+    let key = addr_key(addr)?;
+    let registry = REGISTRY.exclusive_access();
+    registry.bound.get(&key).and_then(|core| core.upgrade()).ok_or(ECONNREFUSED)
+}
+
+/// Data sent in one call
+struct Message {
+    data: Vec<u8>,
+    /// bytes of `data` already read from a stream
+    read: usize,
+    /// address of the sender of a datagram
+    from: UnixAddr,
+    /// files passed with `SCM_RIGHTS`
+    rights: Vec<Arc<dyn File + Send + Sync>>,
+}
+
+/// What arrives for a socket
+struct RecvQueue {
+    messages: VecDeque<Message>,
+    /// bytes held
+    len: usize,
+    /// no more data comes, reads return 0 once the queue is empty
+    eof: bool,
+    /// reading was shut down, sends fail with `EPIPE`
+    refuse: bool,
+    /// tasks polling the socket or a peer sending to it
+    waiters: WaitQueue,
+}
+
+impl RecvQueue {
+    fn room(&self) -> usize {
+        SOCKET_BUFFER_SIZE - self.len
+    }
+    fn push(&mut self, message: Message) {
+        self.len += message.data.len();
+        self.messages.push_back(message);
+        self.waiters.wake_all();
+    }
+}
+
+enum SocketState {
+    Idle,
+    Listening {
+        backlog: usize,
+        /// server sides of the connections not accepted yet
+        pending: VecDeque<Arc<Socket>>,
+    },
+    /// a stream socket with a peer, or a datagram socket with a default
+    /// destination
+    Connected,
+}
+
+struct SocketInner {
+    local: UnixAddr,
+    state: SocketState,
+    peer: Weak<SocketCore>,
+    /// address of the peer when it was connected
+    peer_addr: UnixAddr,
+    /// writing was shut down
+    shut_write: bool,
+}
+
+/// The state of a socket shared with its peer and its address
+struct SocketCore {
+    stype: SocketType,
+    inner: UPSafeCell<SocketInner>,
+    queue: UPSafeCell<RecvQueue>,
+}
+
+impl SocketCore {
+    fn new(stype: SocketType) -> Arc<Self> {
+        Arc::new(Self {
+            stype,
+            inner: unsafe {
+                UPSafeCell::new(SocketInner {
+                    local: UnixAddr::Unnamed,
+                    state: SocketState::Idle,
+                    peer: Weak::new(),
+                    peer_addr: UnixAddr::Unnamed,
+                    shut_write: false,
+                })
+            },
+            queue: unsafe {
+                UPSafeCell::new(RecvQueue {
+                    messages: VecDeque::new(),
+                    len: 0,
+                    eof: false,
+                    refuse: false,
+                    waiters: WaitQueue::new(),
+                })
+            },
+        })
+    }
+    /// Make `a` and `b` the peers of each other
+    fn connect_pair(a: &Arc<Self>, b: &Arc<Self>) {
+        for (this, other) in [(a, b), (b, a)] {
+            let other_addr = other.inner.exclusive_access().local.clone();
+            let mut inner = this.inner.exclusive_access();
+            inner.state = SocketState::Connected;
+            inner.peer = Arc::downgrade(other);
+            inner.peer_addr = other_addr;
+        }
+    }
+    /// Whether `target` is this socket or held by it, through the sockets
+    /// in flight in its queue or waiting to be accepted. Those never hold
+    /// their holder, as `check_rights` refuses it.
+    fn reaches(&self, target: &SocketCore) -> bool {
+        if core::ptr::eq(self, target) {
+            return true;
+        }
+        let mut held: Vec<Arc<SocketCore>> = Vec::new();
+        for message in self.queue.exclusive_access().messages.iter() {
+            held.extend(message.rights.iter().filter_map(|file| Some(file.as_socket()?.core.clone())));
+        }
+        if let SocketState::Listening { pending, .. } = &self.inner.exclusive_access().state {
+            held.extend(pending.iter().map(|socket| socket.core.clone()));
+        }
+        held.iter().any(|core| core.reaches(target))
+    }
+    /// `ELOOP` if queueing `rights` for this socket would make it hold
+    /// itself
+    fn check_rights(&self, rights: &[Arc<dyn File + Send + Sync>]) -> Result<(), isize> {
+        let cycle = rights
+            .iter()
+            .any(|file| file.as_socket().is_some_and(|socket| socket.core.reaches(self)));
+        if cycle {
+            return Err(ELOOP);
+        }
+        Ok(())
+    }
+    /// The connected peer, if it is still open
+    fn peer(&self) -> Option<Arc<SocketCore>> {
+        let inner = self.inner.exclusive_access();
+        match inner.state {
+            SocketState::Connected => inner.peer.upgrade(),
+            _ => None,
+        }
+    }
+}
+
+impl Drop for SocketCore {
+    fn drop(&mut self) {
+        // the end of the stream for the peer
+        if let Some(peer) = self.inner.exclusive_access().peer.upgrade() {
+            let mut queue = peer.queue.exclusive_access();
+            if self.stype == SocketType::Stream {
+                queue.eof = true;
+            }
+            queue.waiters.wake_all();
+        }
+    }
+}
+
+/// What one receive got
+pub struct Received {
+    pub len: usize,
+    pub from: UnixAddr,
+    pub rights: Vec<Arc<dyn File + Send + Sync>>,
+    /// a datagram longer than the buffer was cut
+    pub truncated: bool,
+}
+
+/// A Unix domain socket open as a file
+pub struct Socket {
+    /// `NONBLOCK` if set
+    status: UPSafeCell<OpenFlags>,
+    core: Arc<SocketCore>,
+}
+
+impl Socket {
+    fn with_core(core: Arc<SocketCore>, status: OpenFlags) -> Arc<Self> {
+        Arc::new(Self {
+            status: unsafe { UPSafeCell::new(status & OpenFlags::NONBLOCK) },
+            core,
+        })
+    }
+    /// A new unbound socket with the `NONBLOCK` of `status`
+    pub fn new(stype: SocketType, status: OpenFlags) -> Arc<Self> {
+        Self::with_core(SocketCore::new(stype), status)
+    }
+    /// Two sockets connected to each other
+    pub fn pair(stype: SocketType, status: OpenFlags) -> (Arc<Self>, Arc<Self>) {
+        let (a, b) = (SocketCore::new(stype), SocketCore::new(stype));
+        SocketCore::connect_pair(&a, &b);
+        (Self::with_core(a, status), Self::with_core(b, status))
+    }
+    pub fn socket_type(&self) -> SocketType {
+        self.core.stype
+    }
+    fn nonblock(&self) -> bool {
+        self.status.exclusive_access().contains(OpenFlags::NONBLOCK)
+    }
+    pub fn local_addr(&self) -> UnixAddr {
+        self.core.inner.exclusive_access().local.clone()
+    }
+    /// Address of the peer, `ENOTCONN` if there is none
+    pub fn peer_addr(&self) -> Result<UnixAddr, isize> {
+        let inner = self.core.inner.exclusive_access();
+        match inner.state {
+            SocketState::Connected => Ok(inner.peer_addr.clone()),
+            _ => Err(ENOTCONN),
+        }
+    }
+    /// Bind the socket to `addr`, an unnamed one gets an abstract name.
+    /// A path gets a socket inode, which stays after the socket is closed.
+    pub fn bind(&self, addr: UnixAddr) -> Result<(), isize> {
+        if self.local_addr() != UnixAddr::Unnamed {
+            return Err(EINVAL);
+        }
+        let addr = match addr {
+            UnixAddr::Unnamed => {
+                let mut registry = REGISTRY.exclusive_access();
+                registry.next_auto += 1;
+                UnixAddr::Abstract(Vec::from(format!("{:05x}", registry.next_auto).as_bytes()))
+            }
+            addr => addr,
+        };
+        if let UnixAddr::Path(path) = &addr {
+            make_node(path, InodeType::Socket, 0o777).map_err(|errno| if errno == EEXIST { EADDRINUSE } else { errno })?;
+        }
+        let key = addr_key(&addr)?;
+        let mut registry = REGISTRY.exclusive_access();
+        registry.bound.retain(|_, core| core.strong_count() > 0);
+        if registry.bound.contains_key(&key) {
+            return Err(EADDRINUSE);
+        }
+        registry.bound.insert(key, Arc::downgrade(&self.core));
+        drop(registry);
+        self.core.inner.exclusive_access().local = addr;
+        Ok(())
+    }
+    /// Accept connections, at most `backlog` waiting at once
+    pub fn listen(&self, backlog: usize) -> Result<(), isize> {
+        if self.core.stype != SocketType::Stream {
+            return Err(EOPNOTSUPP);
+        }
+        let mut inner = self.core.inner.exclusive_access();
+        if inner.local == UnixAddr::Unnamed {
+            return Err(EINVAL);
+        }
+        let backlog = backlog.clamp(1, SOMAXCONN);
+        // listening again changes the backlog
+        if let SocketState::Listening { backlog: old, .. } = &mut inner.state {
+            *old = backlog;
+            return Ok(());
+        }
+        if matches!(inner.state, SocketState::Connected) {
+            return Err(EINVAL);
+        }
+        inner.state = SocketState::Listening {
+            backlog,
+            pending: VecDeque::new(),
+        };
+        Ok(())
+    }
+    /// Take a connection made to the listening socket, waiting for one
+    /// unless `nonblock`
+    pub fn accept(&self, nonblock: bool) -> Result<Arc<Socket>, isize> {
+        loop {
+            let mut inner = self.core.inner.exclusive_access();
+            let SocketState::Listening { pending, .. } = &mut inner.state else {
+                return Err(EINVAL);
+            };
+            if let Some(socket) = pending.pop_front() {
+                return Ok(socket);
+            }
+            if nonblock || self.nonblock() {
+                return Err(EAGAIN);
+            }
+            drop(inner);
+            suspend_current_and_run_next();
+        }
+    }
+    /// Connect a stream socket to the listening socket at `addr`, waiting
+    /// while its backlog is full, or set the default destination of a
+    /// datagram socket
+    pub fn connect(&self, addr: &UnixAddr) -> Result<(), isize> {
+        match self.core.inner.exclusive_access().state {
+            SocketState::Listening { .. } => return Err(EINVAL),
+            SocketState::Connected if self.core.stype == SocketType::Stream => return Err(EISCONN),
+            _ => {}
+        }
+        let target = find_bound(addr)?;
+        if target.stype != self.core.stype {
+            return Err(EPROTOTYPE);
+        }
+        if self.core.stype == SocketType::Datagram {
+            let mut inner = self.core.inner.exclusive_access();
+            inner.state = SocketState::Connected;
+            inner.peer = Arc::downgrade(&target);
+            inner.peer_addr = addr.clone();
+            return Ok(());
+        }
+        loop {
+            let mut target_inner = target.inner.exclusive_access();
+            let local = target_inner.local.clone();
+            let SocketState::Listening { backlog, pending } = &mut target_inner.state else {
+                return Err(ECONNREFUSED);
+            };
+            if pending.len() >= *backlog {
+                if self.nonblock() {
+                    return Err(EAGAIN);
+                }
+                drop(target_inner);
+                suspend_current_and_run_next();
+                continue;
+            }
+            // the server side of the connection, bound to the same address
+            let server = SocketCore::new(SocketType::Stream);
+            server.inner.exclusive_access().local = local;
+            SocketCore::connect_pair(&self.core, &server);
+            pending.push_back(Socket::with_core(server, OpenFlags::empty()));
+            drop(target_inner);
+            target.queue.exclusive_access().waiters.wake_all();
+            return Ok(());
+        }
+    }
+    /// Stop receiving, sending or both: `how` is `SHUT_RD`, `SHUT_WR` or
+    /// `SHUT_RDWR`
+    pub fn shutdown(&self, how: usize) -> Result<(), isize> {
+        const SHUT_RD: usize = 0;
+        const SHUT_WR: usize = 1;
+        const SHUT_RDWR: usize = 2;
+        if how > SHUT_RDWR {
+            return Err(EINVAL);
+        }
+        let connected = matches!(self.core.inner.exclusive_access().state, SocketState::Connected);
+        if self.core.stype == SocketType::Stream && !connected {
+            return Err(ENOTCONN);
+        }
+        if how != SHUT_WR {
+            let mut queue = self.core.queue.exclusive_access();
+            queue.eof = true;
+            queue.refuse = true;
+            queue.waiters.wake_all();
+        }
+        if how != SHUT_RD {
+            self.core.inner.exclusive_access().shut_write = true;
+            if let Some(peer) = self.core.peer() {
+                let mut queue = peer.queue.exclusive_access();
+                if self.core.stype == SocketType::Stream {
+                    queue.eof = true;
+                }
+                queue.waiters.wake_all();
+            }
+        }
+        Ok(())
+    }
+    /// Send `data` with the files `rights` to the peer, or to `to` for a
+    /// datagram socket. A stream send waits until all of it is queued
+    /// unless `nonblock`, then it queues what fits.
+    pub fn send(
+        &self,
+        data: &[u8],
+        rights: Vec<Arc<dyn File + Send + Sync>>,
+        to: Option<&UnixAddr>,
+        nonblock: bool,
+    ) -> Result<usize, isize> {
+        let nonblock = nonblock || self.nonblock();
+        if self.core.inner.exclusive_access().shut_write {
+            return Err(EPIPE);
+        }
+        match self.core.stype {
+            SocketType::Stream => {
+                if to.is_some() {
+                    let connected = matches!(self.core.inner.exclusive_access().state, SocketState::Connected);
+                    return Err(if connected { EISCONN } else { EOPNOTSUPP });
+                }
+                self.send_stream(data, rights, nonblock)
+            }
+            SocketType::Datagram => self.send_datagram(data, rights, to, nonblock),
+        }
+    }
+    fn send_stream(&self, data: &[u8], rights: Vec<Arc<dyn File + Send + Sync>>, nonblock: bool) -> Result<usize, isize> {
+        if !matches!(self.core.inner.exclusive_access().state, SocketState::Connected) {
+            return Err(ENOTCONN);
+        }
+        if let Some(peer) = self.core.peer() {
+            peer.check_rights(&rights)?;
+        }
+        let mut rights = Some(rights);
+        let mut sent = 0;
+        while sent < data.len() {
+            let broken = if sent > 0 { Ok(sent) } else { Err(EPIPE) };
+            let Some(peer) = self.core.peer() else {
+                return broken;
+            };
+            let mut queue = peer.queue.exclusive_access();
+            if queue.refuse {
+                return broken;
+            }
+            if queue.room() == 0 {
+                if nonblock {
+                    return if sent > 0 { Ok(sent) } else { Err(EAGAIN) };
+                }
+                drop(queue);
+                drop(peer);
+                suspend_current_and_run_next();
+                continue;
+            }
+            let len = queue.room().min(data.len() - sent);
+            queue.push(Message {
+                data: Vec::from(&data[sent..sent + len]),
+                read: 0,
+                from: UnixAddr::Unnamed,
+                rights: rights.take().unwrap_or_default(),
+            });
+            sent += len;
+        }
+        Ok(sent)
+    }
+    fn send_datagram(
+        &self,
+        data: &[u8],
+        rights: Vec<Arc<dyn File + Send + Sync>>,
+        to: Option<&UnixAddr>,
+        nonblock: bool,
+    ) -> Result<usize, isize> {
+        let target = match to {
+            Some(addr) => find_bound(addr)?,
+            None => {
+                let inner = self.core.inner.exclusive_access();
+                if !matches!(inner.state, SocketState::Connected) {
+                    return Err(ENOTCONN);
+                }
+                inner.peer.upgrade().ok_or(ECONNREFUSED)?
+            }
+        };
+        if target.stype != SocketType::Datagram {
+            return Err(EPROTOTYPE);
+        }
+        if data.len() > SOCKET_BUFFER_SIZE {
+            return Err(EMSGSIZE);
+        }
+        target.check_rights(&rights)?;
+        let from = self.local_addr();
+        loop {
+            let mut queue = target.queue.exclusive_access();
+            if queue.refuse {
+                return Err(EPIPE);
+            }
+            if queue.room() >= data.len() {
+                queue.push(Message {
+                    data: Vec::from(data),
+                    read: 0,
+                    from,
+                    rights,
+                });
+                return Ok(data.len());
+            }
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            drop(queue);
+            suspend_current_and_run_next();
+        }
+    }
+    /// Receive into `buf`, waiting for data unless `nonblock`. A stream
+    /// read stops before data sent with files, so they come with their
+    /// own data; a datagram read takes one datagram and drops what does
+    /// not fit.
+    pub fn recv(&self, buf: &mut [u8], nonblock: bool) -> Result<Received, isize> {
+        let nonblock = nonblock || self.nonblock();
+        match self.core.inner.exclusive_access().state {
+            SocketState::Listening { .. } => return Err(EINVAL),
+            SocketState::Idle if self.core.stype == SocketType::Stream => return Err(ENOTCONN),
+            _ => {}
+        }
+        loop {
+            let mut queue = self.core.queue.exclusive_access();
+            if !queue.messages.is_empty() {
+                let received = match self.core.stype {
+                    SocketType::Stream => read_stream(&mut queue, buf),
+                    SocketType::Datagram => read_datagram(&mut queue, buf),
+                };
+                // room is made for the senders
+                queue.waiters.wake_all();
+                return Ok(received);
+            }
+            if queue.eof || (buf.is_empty() && self.core.stype == SocketType::Stream) {
+                return Ok(Received {
+                    len: 0,
+                    from: UnixAddr::Unnamed,
+                    rights: Vec::new(),
+                    truncated: false,
+                });
+            }
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            drop(queue);
+            suspend_current_and_run_next();
+        }
+    }
+}
+
+/// Read the stream bytes held by `queue` into `buf`
+fn read_stream(queue: &mut RecvQueue, buf: &mut [u8]) -> Received {
+    let mut len = 0;
+    let mut rights = Vec::new();
+    while len < buf.len() {
+        let Some(message) = queue.messages.front_mut() else {
+            break;
+        };
+        if len > 0 && !message.rights.is_empty() {
+            break;
+        }
+        rights.append(&mut message.rights);
+        let count = (message.data.len() - message.read).min(buf.len() - len);
+        buf[len..len + count].copy_from_slice(&message.data[message.read..message.read + count]);
+        message.read += count;
+        len += count;
+        if message.read == message.data.len() {
+            queue.messages.pop_front();
+        }
+    }
+    queue.len -= len;
+    Received {
+        len,
+        from: UnixAddr::Unnamed,
+        rights,
+        truncated: false,
+    }
+}
+
+/// Take the first datagram of `queue` into `buf`
+fn read_datagram(queue: &mut RecvQueue, buf: &mut [u8]) -> Received {
+    let message = queue.messages.pop_front().unwrap();
+    queue.len -= message.data.len();
+    let len = message.data.len().min(buf.len());
+    buf[..len].copy_from_slice(&message.data[..len]);
+    Received {
+        len,
+        from: message.from,
+        rights: message.rights,
+        truncated: len < message.data.len(),
+    }
+}
+
+impl File for Socket {
+    fn readable(&self) -> bool {
+        true
+    }
+    fn writable(&self) -> bool {
+        true
+    }
+    /// Receive as `recv` does, errors read nothing and files passed along
+    /// are closed
+    fn read(&self, buf: UserBuffer) -> usize {
+        let mut data = vec![0u8; buf.len()];
+        let Ok(received) = self.recv(&mut data, false) else {
+            return 0;
+        };
+        let mut copied = 0;
+        for slice in buf.buffers {
+            let len = slice.len().min(received.len - copied);
+            slice[..len].copy_from_slice(&data[copied..copied + len]);
+            copied += len;
+        }
+        received.len
+    }
+    /// Send as `send` does to the peer, errors write nothing
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut data = Vec::with_capacity(buf.len());
+        for slice in buf.buffers.iter() {
+            data.extend_from_slice(slice);
+        }
+        self.send(&data, Vec::new(), None, false).unwrap_or(0)
+    }
+    fn stat(&self) -> Stat {
+        let mut st = Stat::empty();
+        st.mode = StatMode::new(StatMode::SOCK, 0o777);
+        st.nlink = 1;
+        st.blksize = PAGE_SIZE as i32;
+        st
+    }
+    fn path(&self) -> Option<String> {
+        None
+    }
+    fn seekable(&self) -> bool {
+        false
+    }
+    fn status_flags(&self) -> OpenFlags {
+        *self.status.exclusive_access()
+    }
+    fn set_status_flags(&self, flags: OpenFlags) {
+        *self.status.exclusive_access() = flags & OpenFlags::NONBLOCK;
+    }
+    fn read_ready(&self) -> bool {
+        self.poll().intersects(PollEvents::IN | PollEvents::HUP)
+    }
+    fn write_ready(&self) -> bool {
+        self.poll().intersects(PollEvents::OUT | PollEvents::HUP | PollEvents::ERR)
+    }
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        let inner = self.core.inner.exclusive_access();
+        let connected = match &inner.state {
+            SocketState::Listening { pending, .. } => {
+                if !pending.is_empty() {
+                    events |= PollEvents::IN;
+                }
+                return events;
+            }
+            SocketState::Connected => true,
+            SocketState::Idle => false,
+        };
+        let shut_write = inner.shut_write;
+        drop(inner);
+        let eof = {
+            let queue = self.core.queue.exclusive_access();
+            if !queue.messages.is_empty() || queue.eof {
+                events |= PollEvents::IN;
+            }
+            queue.eof
+        };
+        // the peer may be this very socket, so its queue is borrowed alone
+        let peer = self.core.peer();
+        let peer_room = peer.as_ref().map(|peer| {
+            let queue = peer.queue.exclusive_access();
+            !queue.refuse && queue.room() > 0
+        });
+        match self.core.stype {
+            SocketType::Stream => {
+                if peer_room == Some(true) && !shut_write {
+                    events |= PollEvents::OUT;
+                }
+                if !connected || (eof && (shut_write || peer.is_none())) {
+                    events |= PollEvents::HUP;
+                }
+            }
+            SocketType::Datagram => {
+                // an unconnected socket may send anywhere
+                if !shut_write && (!connected || peer_room == Some(true)) {
+                    events |= PollEvents::OUT;
+                }
+            }
+        }
+        events
+    }
+    fn add_waiter(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.core.queue.exclusive_access().waiters.add(task);
+        // room made in the queue of the peer
+        if let Some(peer) = self.core.peer() {
+            peer.queue.exclusive_access().waiters.add(task);
+        }
+        true
+    }
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.core.queue.exclusive_access().waiters.remove(task);
+        if let Some(peer) = self.core.peer() {
+            peer.queue.exclusive_access().waiters.remove(task);
+        }
+    }
+    fn as_socket(&self) -> Option<&Socket> {
+        Some(self)
+    }
+}
+
+#[allow(unused)]
+/// Check a stream pair, files passed along with the data, and datagrams
+/// between abstract addresses, all without waiting
+pub fn socket_test() {
+    use crate::println;
+    let (a, b) = Socket::pair(SocketType::Stream, OpenFlags::NONBLOCK);
+    let mut buf = [0u8; 8];
+    assert_eq!(b.recv(&mut buf, false).err(), Some(EAGAIN));
+    let (reader, _writer) = super::make_pipe(OpenFlags::empty()).unwrap();
+    assert_eq!(a.send(b"abc", Vec::new(), None, false), Ok(3));
+    assert_eq!(a.send(b"de", alloc::vec![reader as Arc<dyn File + Send + Sync>], None, false), Ok(2));
+    assert_eq!(b.poll(), PollEvents::IN | PollEvents::OUT);
+    // the read stops before the bytes carrying the pipe
+    let received = b.recv(&mut buf, false).unwrap();
+    assert_eq!((&buf[..received.len], received.rights.len()), (&b"abc"[..], 0));
+    let received = b.recv(&mut buf, false).unwrap();
+    assert_eq!((&buf[..received.len], received.rights.len()), (&b"de"[..], 1));
+    // a socket cannot be queued for itself, nor in a ring through its peer
+    let itself = alloc::vec![b.clone() as Arc<dyn File + Send + Sync>];
+    assert_eq!(a.send(b"f", itself, None, false).err(), Some(ELOOP));
+    assert_eq!(a.send(b"f", alloc::vec![a.clone() as Arc<dyn File + Send + Sync>], None, false), Ok(1));
+    assert_eq!(b.send(b"g", alloc::vec![b.clone() as Arc<dyn File + Send + Sync>], None, false).err(), Some(ELOOP));
+    let received = b.recv(&mut buf, false).unwrap();
+    assert_eq!((&buf[..received.len], received.rights.len()), (&b"f"[..], 1));
+    drop(received);
+    drop(a);
+    assert_eq!(b.recv(&mut buf, false).map(|received| received.len), Ok(0));
+    assert!(b.poll().contains(PollEvents::HUP));
+
+    let server = Socket::new(SocketType::Datagram, OpenFlags::NONBLOCK);
+    let client = Socket::new(SocketType::Datagram, OpenFlags::NONBLOCK);
+    let name = UnixAddr::Abstract(Vec::from(&b"socket_test"[..]));
+    server.bind(name.clone()).unwrap();
+    assert_eq!(client.bind(name.clone()).err(), Some(EADDRINUSE));
+    assert_eq!(client.send(b"hello", Vec::new(), Some(&name), false), Ok(5));
+    let received = server.recv(&mut buf[..4], false).unwrap();
+    assert_eq!((received.len, received.truncated), (4, true));
+    // the client is not bound, so it cannot be answered
+    assert_eq!(received.from, UnixAddr::Unnamed);
+    drop(server);
+    assert_eq!(client.send(b"x", Vec::new(), Some(&name), false).err(), Some(ECONNREFUSED));
+    println!("socket_test passed!");
+}
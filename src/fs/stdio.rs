@@ -1,14 +1,71 @@
 //!Stdin & Stdout
-use super::File;
+use super::devfs::{makedev, CONSOLE_RDEV};
+use super::{File, OpenFlags, Stat, StatMode};
+use alloc::string::String;
 use crate::mm::UserBuffer;
 use crate::sbi::console_getchar;
+use crate::sync::UPSafeCell;
 use crate::task::suspend_current_and_run_next;
 use crate::print;
+use lazy_static::*;
 ///Standard input
-pub struct Stdin;
+pub struct Stdin {
+    /// `NONBLOCK` if set
+    status: UPSafeCell<OpenFlags>,
+}
 ///Standard output
 pub struct Stdout;
 
+lazy_static! {
+    /// A character taken from the console to tell it is ready, not read yet
+    static ref CONSOLE_PENDING: UPSafeCell<Option<u8>> = unsafe { UPSafeCell::new(None) };
+}
+
+impl Stdin {
+    pub fn new() -> Self {
+        Self {
+            status: unsafe { UPSafeCell::new(OpenFlags::empty()) },
+        }
+    }
+}
+
+/// Whether a character of the console can be read without blocking
+pub fn console_ready() -> bool {
+    let mut pending = CONSOLE_PENDING.exclusive_access();
+    if pending.is_none() {
+        let c = console_getchar();
+        if c != 0 {
+            *pending = Some(c as u8);
+        }
+    }
+    pending.is_some()
+}
+
+/// Read a character of the console, yielding until one arrives
+pub fn console_read_char() -> u8 {
+    // busy loop
+    loop {
+        if let Some(c) = CONSOLE_PENDING.exclusive_access().take() {
+            return c;
+        }
+        let c = console_getchar();
+        if c != 0 {
+            return c as u8;
+        }
+        suspend_current_and_run_next();
+    }
+}
+
+/// Status of stdin and stdout, which are the console
+fn console_stat() -> Stat {
+    let (major, minor) = CONSOLE_RDEV;
+    let mut st = Stat::empty();
+    st.mode = StatMode::new(StatMode::CHR, 0o620);
+    st.nlink = 1;
+    st.rdev = makedev(major, minor);
+    st
+}
+
 impl File for Stdin {
     fn readable(&self) -> bool {
         true
@@ -18,18 +75,7 @@ impl File for Stdin {
     }
     fn read(&self, mut user_buf: UserBuffer) -> usize {
         assert_eq!(user_buf.len(), 1);
-        // busy loop
-        let mut c: usize;
-        loop {
-            c = console_getchar();
-            if c == 0 {
-                suspend_current_and_run_next();
-                continue;
-            } else {
-                break;
-            }
-        }
-        let ch = c as u8;
+        let ch = console_read_char();
         unsafe {
             user_buf.buffers[0].as_mut_ptr().write_volatile(ch);
         }
@@ -38,8 +84,23 @@ impl File for Stdin {
     fn write(&self, _user_buf: UserBuffer) -> usize {
         panic!("Cannot write to stdin!");
     }
-    fn stat(&self) -> super::Stat {
-        super::Stat::empty()
+    fn path(&self) -> Option<String> {
+        None
+    }
+    fn seekable(&self) -> bool {
+        false
+    }
+    fn stat(&self) -> Stat {
+        console_stat()
+    }
+    fn status_flags(&self) -> OpenFlags {
+        *self.status.exclusive_access()
+    }
+    fn set_status_flags(&self, flags: OpenFlags) {
+        *self.status.exclusive_access() = flags & OpenFlags::NONBLOCK;
+    }
+    fn read_ready(&self) -> bool {
+        console_ready()
     }
 }
 
@@ -59,7 +120,13 @@ impl File for Stdout {
         }
         user_buf.len()
     }
-    fn stat(&self) -> super::Stat {
-        super::Stat::empty()
+    fn path(&self) -> Option<String> {
+        None
+    }
+    fn seekable(&self) -> bool {
+        false
+    }
+    fn stat(&self) -> Stat {
+        console_stat()
     }
 }
@@ -0,0 +1,360 @@
+//! In-memory filesystem
+//!
+//! File data lives in frames taken from the frame allocator and is freed
+//! with the last link and the last open file of an inode. Every instance
+//! keeps a table of its live inodes, so `link` can find the inode behind a
+//! `dyn VfsInode` by its number.
+use super::vfs::{InodeAttr, InodeType, SuperBlock, VfsInode};
+use crate::config::PAGE_SIZE;
+use crate::mm::{frame_alloc, FrameTracker};
+use crate::sync::UPSafeCell;
+use crate::syscall::errno::{EEXIST, EINVAL, EISDIR, ENOENT, ENOSPC, ENOTDIR, EPERM, EXDEV};
+use crate::timer::TimeSpec;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::{Arc, Weak};
+use alloc::vec::Vec;
+
+/// State shared by the inodes of one tmpfs instance
+struct TmpFsShared {
+    next_ino: u64,
+    inodes: BTreeMap<u64, Weak<TmpInode>>,
+}
+
+type SharedRef = Arc<UPSafeCell<TmpFsShared>>;
+
+/// A tmpfs instance
+pub struct TmpFs {
+    root: Arc<TmpInode>,
+}
+
+impl TmpFs {
+    pub fn new() -> Self {
+        let shared = Arc::new(unsafe {
+            UPSafeCell::new(TmpFsShared {
+                next_ino: 1,
+                inodes: BTreeMap::new(),
+            })
+        });
+        Self {
+            root: TmpInode::new(&shared, InodeType::Dir),
+        }
+    }
+}
+
+impl SuperBlock for TmpFs {
+    fn root(&self) -> Arc<dyn VfsInode> {
+        self.root.clone()
+    }
+    fn fs_type(&self) -> &'static str {
+        "tmpfs"
+    }
+}
+
+enum TmpContent {
+    File { size: usize, pages: Vec<FrameTracker> },
+    Dir(BTreeMap<String, Arc<TmpInode>>),
+    Symlink(String),
+    /// a FIFO, its data lives in the pipe buffer while it is open
+    Fifo,
+    /// a socket address, the socket bound to it is found by the inode number
+    Socket,
+}
+
+struct TmpInodeInner {
+    nlink: u32,
+    attr: InodeAttr,
+    content: TmpContent,
+}
+
+impl TmpInodeInner {
+    /// Record a change of the content
+    fn touch(&mut self) {
+        let now = TimeSpec::now();
+        self.attr.mtime = now;
+        self.attr.ctime = now;
+    }
+}
+
+/// An inode of a tmpfs
+pub struct TmpInode {
+    ino: u64,
+    fs: SharedRef,
+    inner: UPSafeCell<TmpInodeInner>,
+}
+
+impl TmpInode {
+    fn new(fs: &SharedRef, itype: InodeType) -> Arc<Self> {
+        let mut shared = fs.exclusive_access();
+        let ino = shared.next_ino;
+        shared.next_ino += 1;
+        let (nlink, content) = match itype {
+            // `.` and the entry in the parent
+            InodeType::Dir => (2, TmpContent::Dir(BTreeMap::new())),
+            InodeType::Symlink => (1, TmpContent::Symlink(String::new())),
+            InodeType::Fifo => (1, TmpContent::Fifo),
+            InodeType::Socket => (1, TmpContent::Socket),
+            _ => (1, TmpContent::File { size: 0, pages: Vec::new() }),
+        };
+        let now = TimeSpec::now();
+        let mut attr = InodeAttr::default_for(itype);
+        attr.blksize = PAGE_SIZE as u32;
+        attr.atime = now;
+        attr.mtime = now;
+        attr.ctime = now;
+        let inode = Arc::new(Self {
+            ino,
+            fs: fs.clone(),
+            inner: unsafe { UPSafeCell::new(TmpInodeInner { nlink, attr, content }) },
+        });
+        shared.inodes.insert(ino, Arc::downgrade(&inode));
+        inode
+    }
+    /// The live inode `ino` of the same instance
+    fn get_inode(&self, ino: u64) -> Option<Arc<TmpInode>> {
+        self.fs.exclusive_access().inodes.get(&ino).and_then(|inode| inode.upgrade())
+    }
+}
+
+impl Drop for TmpInode {
+    fn drop(&mut self) {
+        self.fs.exclusive_access().inodes.remove(&self.ino);
+    }
+}
+
+/// Resize file data to `new_size`, return false if out of frames
+fn resize(size: &mut usize, pages: &mut Vec<FrameTracker>, new_size: usize) -> bool {
+    let page_count = new_size.div_ceil(PAGE_SIZE);
+    while pages.len() < page_count {
+        match frame_alloc() {
+            Some(frame) => pages.push(frame),
+            None => return false,
+        }
+    }
+    pages.truncate(page_count);
+    // keep the bytes past the end zeroed, growing again must read zeros
+    if new_size < *size && new_size % PAGE_SIZE != 0 {
+        pages.last().unwrap().ppn.get_bytes_array()[new_size % PAGE_SIZE..].fill(0);
+    }
+    *size = new_size;
+    true
+}
+
+/// Account for the removal of the entry of `inode` from the directory `parent`
+fn release_link(parent: &mut TmpInodeInner, inode: &TmpInode) {
+    let mut inode_inner = inode.inner.exclusive_access();
+    inode_inner.attr.ctime = TimeSpec::now();
+    if matches!(inode_inner.content, TmpContent::Dir(_)) {
+        parent.nlink -= 1;
+        inode_inner.nlink = 0;
+    } else {
+        inode_inner.nlink -= 1;
+    }
+}
+
+impl VfsInode for TmpInode {
+    fn ino(&self) -> u64 {
+        self.ino
+    }
+    fn itype(&self) -> InodeType {
+        match self.inner.exclusive_access().content {
+            TmpContent::File { .. } => InodeType::File,
+            TmpContent::Dir(_) => InodeType::Dir,
+            TmpContent::Symlink(_) => InodeType::Symlink,
+            TmpContent::Fifo => InodeType::Fifo,
+            TmpContent::Socket => InodeType::Socket,
+        }
+    }
+    fn nlink(&self) -> u32 {
+        self.inner.exclusive_access().nlink
+    }
+    fn attr(&self) -> InodeAttr {
+        self.inner.exclusive_access().attr
+    }
+    fn size(&self) -> usize {
+        match &self.inner.exclusive_access().content {
+            TmpContent::File { size, .. } => *size,
+            TmpContent::Dir(_) | TmpContent::Fifo | TmpContent::Socket => 0,
+            TmpContent::Symlink(target) => target.len(),
+        }
+    }
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        inner.attr.atime = TimeSpec::now();
+        let TmpContent::File { size, pages } = &inner.content else {
+            return 0;
+        };
+        let end = (offset + buf.len()).min(*size);
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let page = pages[pos / PAGE_SIZE].ppn.get_bytes_array();
+            buf[pos - offset..pos - offset + len].copy_from_slice(&page[page_offset..page_offset + len]);
+            pos += len;
+        }
+        end.saturating_sub(offset)
+    }
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize {
+        let mut inner = self.inner.exclusive_access();
+        inner.touch();
+        let TmpContent::File { size, pages } = &mut inner.content else {
+            return 0;
+        };
+        let mut end = offset + buf.len();
+        if end > *size && !resize(size, pages, end) {
+            // out of frames, write what fits in the pages we got
+            end = (pages.len() * PAGE_SIZE).max(offset);
+            *size = end.max(*size);
+        }
+        let mut pos = offset;
+        while pos < end {
+            let page_offset = pos % PAGE_SIZE;
+            let len = (PAGE_SIZE - page_offset).min(end - pos);
+            let page = pages[pos / PAGE_SIZE].ppn.get_bytes_array();
+            page[page_offset..page_offset + len].copy_from_slice(&buf[pos - offset..pos - offset + len]);
+            pos += len;
+        }
+        end - offset
+    }
+    fn clear(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.touch();
+        if let TmpContent::File { size, pages } = &mut inner.content {
+            resize(size, pages, 0);
+        }
+    }
+    fn set_perm(&self, perm: u32) {
+        let mut inner = self.inner.exclusive_access();
+        inner.attr.perm = perm;
+        inner.attr.ctime = TimeSpec::now();
+    }
+    fn truncate(&self, new_size: usize) -> Result<(), isize> {
+        let mut inner = self.inner.exclusive_access();
+        inner.touch();
+        match &mut inner.content {
+            TmpContent::File { size, pages } => {
+                if resize(size, pages, new_size) {
+                    Ok(())
+                } else {
+                    Err(ENOSPC)
+                }
+            }
+            TmpContent::Dir(_) => Err(EISDIR),
+            TmpContent::Symlink(_) | TmpContent::Fifo | TmpContent::Socket => Err(EINVAL),
+        }
+    }
+    fn lookup(&self, name: &str) -> Option<Arc<dyn VfsInode>> {
+        match &self.inner.exclusive_access().content {
+            TmpContent::Dir(entries) => entries.get(name).map(|inode| inode.clone() as Arc<dyn VfsInode>),
+            _ => None,
+        }
+    }
+    fn create(&self, name: &str, itype: InodeType) -> Result<Arc<dyn VfsInode>, isize> {
+        let mut inner = self.inner.exclusive_access();
+        let TmpContent::Dir(entries) = &mut inner.content else {
+            return Err(ENOTDIR);
+        };
+        if entries.contains_key(name) {
+            return Err(EEXIST);
+        }
+        // no device nodes on tmpfs
+        if !matches!(itype, InodeType::File | InodeType::Dir | InodeType::Fifo | InodeType::Socket) {
+            return Err(EPERM);
+        }
+        let inode = TmpInode::new(&self.fs, itype);
+        entries.insert(String::from(name), inode.clone());
+        inner.touch();
+        if itype == InodeType::Dir {
+            // `..` of the new directory
+            inner.nlink += 1;
+        }
+        Ok(inode)
+    }
+    fn link(&self, name: &str, target: &Arc<dyn VfsInode>) -> Result<(), isize> {
+        let target = self.get_inode(target.ino()).ok_or(EXDEV)?;
+        let mut inner = self.inner.exclusive_access();
+        let TmpContent::Dir(entries) = &mut inner.content else {
+            return Err(ENOTDIR);
+        };
+        if entries.contains_key(name) {
+            return Err(EEXIST);
+        }
+        entries.insert(String::from(name), target.clone());
+        inner.touch();
+        drop(inner);
+        let mut target_inner = target.inner.exclusive_access();
+        target_inner.nlink += 1;
+        target_inner.attr.ctime = TimeSpec::now();
+        Ok(())
+    }
+    fn unlink(&self, name: &str) -> Result<(), isize> {
+        let mut inner = self.inner.exclusive_access();
+        let TmpContent::Dir(entries) = &mut inner.content else {
+            return Err(ENOTDIR);
+        };
+        let inode = entries.remove(name).ok_or(ENOENT)?;
+        inner.touch();
+        release_link(&mut inner, &inode);
+        Ok(())
+    }
+    fn rename(&self, old_name: &str, new_dir: &Arc<dyn VfsInode>, new_name: &str) -> Result<(), isize> {
+        let new_dir = self.get_inode(new_dir.ino()).ok_or(EXDEV)?;
+        if new_dir.itype() != InodeType::Dir {
+            return Err(ENOTDIR);
+        }
+        let mut inner = self.inner.exclusive_access();
+        let TmpContent::Dir(entries) = &mut inner.content else {
+            return Err(ENOTDIR);
+        };
+        let inode = entries.remove(old_name).ok_or(ENOENT)?;
+        let is_dir = inode.itype() == InodeType::Dir;
+        inner.touch();
+        if is_dir {
+            // `..` of the moved directory
+            inner.nlink -= 1;
+        }
+        // `new_dir` may be this directory
+        drop(inner);
+        let mut new_inner = new_dir.inner.exclusive_access();
+        new_inner.touch();
+        if is_dir {
+            new_inner.nlink += 1;
+        }
+        let TmpContent::Dir(entries) = &mut new_inner.content else {
+            unreachable!();
+        };
+        if let Some(replaced) = entries.insert(String::from(new_name), inode.clone()) {
+            release_link(&mut new_inner, &replaced);
+        }
+        drop(new_inner);
+        inode.inner.exclusive_access().attr.ctime = TimeSpec::now();
+        Ok(())
+    }
+    fn symlink(&self, name: &str, target: &str) -> Result<(), isize> {
+        let mut inner = self.inner.exclusive_access();
+        let TmpContent::Dir(entries) = &mut inner.content else {
+            return Err(ENOTDIR);
+        };
+        if entries.contains_key(name) {
+            return Err(EEXIST);
+        }
+        let inode = TmpInode::new(&self.fs, InodeType::Symlink);
+        inode.inner.exclusive_access().content = TmpContent::Symlink(String::from(target));
+        entries.insert(String::from(name), inode);
+        inner.touch();
+        Ok(())
+    }
+    fn readlink(&self) -> Result<String, isize> {
+        match &self.inner.exclusive_access().content {
+            TmpContent::Symlink(target) => Ok(target.clone()),
+            _ => Err(EINVAL),
+        }
+    }
+    fn list(&self) -> Vec<String> {
+        match &self.inner.exclusive_access().content {
+            TmpContent::Dir(entries) => entries.keys().cloned().collect(),
+            _ => Vec::new(),
+        }
+    }
+}
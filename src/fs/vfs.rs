@@ -0,0 +1,423 @@
+//! Virtual filesystem layer
+//!
+//! A filesystem provides a [`SuperBlock`] and [`VfsInode`]s, and is grafted
+//! into the single directory tree by the mount table. Paths are normalized
+//! before they are walked, so `..` never has to climb out of a mounted
+//! filesystem; walking down, a directory which is a mount point is replaced
+//! by the root of the filesystem mounted on it.
+use super::devfs::DevFs;
+use super::easyfs::EasyFsSuperBlock;
+use super::procfs::ProcFs;
+use super::tmpfs::TmpFs;
+use super::path::{components, join, normalize, split_parent};
+use super::{Stat, StatMode};
+use crate::sync::UPSafeCell;
+use crate::task::all_tasks;
+use crate::syscall::errno::{EBUSY, EINVAL, ELOOP, ENODEV, ENOENT, ENOTDIR};
+use crate::timer::TimeSpec;
+use alloc::collections::BTreeMap;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use lazy_static::*;
+
+/// Type of an inode
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum InodeType {
+    File,
+    Dir,
+    CharDevice,
+    BlockDevice,
+    Symlink,
+    /// named pipe
+    Fifo,
+    /// the address of a Unix domain socket
+    Socket,
+}
+
+impl InodeType {
+    /// File type bits of `st_mode`
+    pub fn stat_mode(&self) -> StatMode {
+        match self {
+            InodeType::File => StatMode::FILE,
+            InodeType::Dir => StatMode::DIR,
+            InodeType::CharDevice => StatMode::CHR,
+            InodeType::BlockDevice => StatMode::BLK,
+            InodeType::Symlink => StatMode::LNK,
+            InodeType::Fifo => StatMode::FIFO,
+            InodeType::Socket => StatMode::SOCK,
+        }
+    }
+    /// `d_type` of a directory entry
+    pub fn dirent_type(&self) -> u8 {
+        match self {
+            InodeType::Fifo => 1,
+            InodeType::CharDevice => 2,
+            InodeType::Dir => 4,
+            InodeType::BlockDevice => 6,
+            InodeType::File => 8,
+            InodeType::Symlink => 10,
+            InodeType::Socket => 12,
+        }
+    }
+}
+
+/// Attributes of an inode besides its type, size and links
+#[derive(Copy, Clone)]
+pub struct InodeAttr {
+    /// permission bits
+    pub perm: u32,
+    pub uid: u32,
+    pub gid: u32,
+    /// device number of a device node
+    pub rdev: u64,
+    /// preferred I/O block size
+    pub blksize: u32,
+    pub atime: TimeSpec,
+    pub mtime: TimeSpec,
+    pub ctime: TimeSpec,
+}
+
+impl InodeAttr {
+    /// Attributes of a filesystem which stores none, owned by root
+    pub fn default_for(itype: InodeType) -> Self {
+        Self {
+            perm: match itype {
+                InodeType::Dir => 0o755,
+                InodeType::Symlink => 0o777,
+                _ => 0o644,
+            },
+            uid: 0,
+            gid: 0,
+            rdev: 0,
+            blksize: 512,
+            atime: TimeSpec::zero(),
+            mtime: TimeSpec::zero(),
+            ctime: TimeSpec::zero(),
+        }
+    }
+}
+
+/// An inode of a mounted filesystem
+pub trait VfsInode: Send + Sync {
+    /// Inode number, unique within the filesystem
+    fn ino(&self) -> u64;
+    fn itype(&self) -> InodeType;
+    /// Number of names linked to the inode
+    fn nlink(&self) -> u32;
+    /// Size of the file in bytes
+    fn size(&self) -> usize;
+    fn attr(&self) -> InodeAttr {
+        InodeAttr::default_for(self.itype())
+    }
+    /// Read at `offset`, return the number of bytes read
+    fn read_at(&self, offset: usize, buf: &mut [u8]) -> usize;
+    /// Write at `offset`, return the number of bytes written
+    fn write_at(&self, offset: usize, buf: &[u8]) -> usize;
+    /// Truncate the file to zero length
+    fn clear(&self);
+    /// Grow or shrink the file to `size` bytes
+    fn truncate(&self, _size: usize) -> Result<(), isize> {
+        Err(EINVAL)
+    }
+    /// Find `name` in this directory
+    fn lookup(&self, _name: &str) -> Option<Arc<dyn VfsInode>> {
+        None
+    }
+    /// Create `name` of type `itype` in this directory
+    fn create(&self, _name: &str, _itype: InodeType) -> Result<Arc<dyn VfsInode>, isize> {
+        Err(ENOTDIR)
+    }
+    /// Link `target`, an inode of the same filesystem, as `name` in this directory
+    fn link(&self, _name: &str, _target: &Arc<dyn VfsInode>) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+    /// Remove `name` from this directory
+    fn unlink(&self, _name: &str) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+    /// Move `old_name` of this directory to `new_name` of `new_dir`, a
+    /// directory of the same filesystem, replacing what is there
+    fn rename(&self, _old_name: &str, _new_dir: &Arc<dyn VfsInode>, _new_name: &str) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+    /// Create a symbolic link `name` to `target` in this directory
+    fn symlink(&self, _name: &str, _target: &str) -> Result<(), isize> {
+        Err(ENOTDIR)
+    }
+    /// Target of this symbolic link
+    fn readlink(&self) -> Result<String, isize> {
+        Err(EINVAL)
+    }
+    /// Set the permission bits, ignored by a filesystem which stores none
+    fn set_perm(&self, _perm: u32) {}
+    /// Whether `read_at` would return without blocking
+    fn read_ready(&self) -> bool {
+        true
+    }
+    /// Names in this directory
+    fn list(&self) -> Vec<String> {
+        Vec::new()
+    }
+}
+
+/// A mounted filesystem instance
+pub trait SuperBlock: Send + Sync {
+    fn root(&self) -> Arc<dyn VfsInode>;
+    /// Name of the filesystem type, as passed to `mount`
+    fn fs_type(&self) -> &'static str;
+}
+
+/// An inode reached through a path
+#[derive(Clone)]
+pub struct Dentry {
+    /// normalized absolute path
+    pub path: String,
+    /// device number of the filesystem the inode belongs to
+    pub dev: u64,
+    pub inode: Arc<dyn VfsInode>,
+}
+
+/// A filesystem mounted on a directory
+pub struct Mount {
+    pub dev: u64,
+    pub source: String,
+    pub sb: Arc<dyn SuperBlock>,
+}
+
+/// Mounted filesystems keyed by the normalized path they are mounted on
+pub struct MountTable {
+    mounts: BTreeMap<String, Mount>,
+    next_dev: u64,
+}
+
+impl MountTable {
+    fn new() -> Self {
+        Self {
+            mounts: BTreeMap::new(),
+            next_dev: 0,
+        }
+    }
+    fn add(&mut self, target: &str, source: &str, sb: Arc<dyn SuperBlock>) {
+        let dev = self.next_dev;
+        self.next_dev += 1;
+        self.mounts.insert(
+            String::from(target),
+            Mount {
+                dev,
+                source: String::from(source),
+                sb,
+            },
+        );
+    }
+    /// The root dentry of the filesystem mounted on `path`
+    fn root_at(&self, path: &str) -> Option<Dentry> {
+        self.mounts.get(path).map(|mount| Dentry {
+            path: String::from(path),
+            dev: mount.dev,
+            inode: mount.sb.root(),
+        })
+    }
+}
+
+lazy_static! {
+    static ref MOUNTS: UPSafeCell<MountTable> = unsafe {
+        let mut table = MountTable::new();
+        table.add("/", "/dev/vda", Arc::new(EasyFsSuperBlock));
+        table.add("/dev", "devfs", Arc::new(DevFs));
+        table.add("/proc", "proc", Arc::new(ProcFs));
+        // the disk holds no directories, new ones go here
+        table.add("/tmp", "tmpfs", Arc::new(TmpFs::new()));
+        UPSafeCell::new(table)
+    };
+}
+
+/// Instantiate a filesystem of type `fs_type` for `mount`
+fn make_fs(fs_type: &str, _source: &str) -> Result<Arc<dyn SuperBlock>, isize> {
+    match fs_type {
+        // the disk is mounted on `/` at boot and cannot be mounted twice
+        "easyfs" => Err(EBUSY),
+        "tmpfs" => Ok(Arc::new(TmpFs::new())),
+        "devfs" => Ok(Arc::new(DevFs)),
+        "proc" => Ok(Arc::new(ProcFs)),
+        _ => Err(ENODEV),
+    }
+}
+
+/// Symbolic links followed in one path walk before giving up with `ELOOP`
+const MAX_SYMLINK_FOLLOWS: usize = 40;
+
+/// Result of walking a path up to the first symbolic link to follow
+enum Walked {
+    Found(Dentry),
+    /// the path to walk instead, with the link replaced by its target
+    Redirect(String),
+}
+
+/// Walk the normalized absolute `path` across mount points until a symbolic
+/// link is met which has to be followed, that is any but a last one if
+/// `follow_last` is false.
+///
+/// The target of a link is resolved against the directory containing the
+/// link and the rest of the path is appended. `..` in the path given was
+/// already applied lexically, so it is not resolved through links.
+fn walk_once(path: &str, follow_last: bool) -> Result<Walked, isize> {
+    let names: Vec<&str> = components(path).collect();
+    let mut dentry = MOUNTS.exclusive_access().root_at("/").unwrap();
+    for (i, name) in names.iter().enumerate() {
+        if dentry.inode.itype() != InodeType::Dir {
+            return Err(ENOTDIR);
+        }
+        let child = join(&dentry.path, name);
+        let mounted = MOUNTS.exclusive_access().root_at(&child);
+        let next = match mounted {
+            Some(root) => root,
+            None => Dentry {
+                inode: dentry.inode.lookup(name).ok_or(ENOENT)?,
+                path: child,
+                dev: dentry.dev,
+            },
+        };
+        let is_last = i + 1 == names.len();
+        if next.inode.itype() == InodeType::Symlink && (follow_last || !is_last) {
+            let mut target = next.inode.readlink()?;
+            for rest in &names[i + 1..] {
+                target.push('/');
+                target.push_str(rest);
+            }
+            return Ok(Walked::Redirect(normalize(&dentry.path, &target)));
+        }
+        dentry = next;
+    }
+    Ok(Walked::Found(dentry))
+}
+
+/// Walk `path`, following up to `MAX_SYMLINK_FOLLOWS` symbolic links
+fn walk(path: &str, follow_last: bool) -> Result<Dentry, isize> {
+    let mut path = String::from(path);
+    for _ in 0..=MAX_SYMLINK_FOLLOWS {
+        match walk_once(&path, follow_last)? {
+            Walked::Found(dentry) => return Ok(dentry),
+            Walked::Redirect(target) => path = target,
+        }
+    }
+    Err(ELOOP)
+}
+
+/// Find what the normalized absolute `path` refers to, following symbolic links
+pub fn lookup(path: &str) -> Result<Dentry, isize> {
+    walk(path, true)
+}
+
+/// Find what the normalized absolute `path` refers to, a symbolic link at
+/// the end is returned itself
+pub fn lookup_nofollow(path: &str) -> Result<Dentry, isize> {
+    walk(path, false)
+}
+
+/// Find the directory containing `path`, return it with the last component.
+/// `EBUSY` for the root, which has no parent.
+pub fn lookup_parent(path: &str) -> Result<(Dentry, &str), isize> {
+    let (parent, name) = split_parent(path).ok_or(EBUSY)?;
+    let dir = lookup(parent)?;
+    if dir.inode.itype() != InodeType::Dir {
+        return Err(ENOTDIR);
+    }
+    Ok((dir, name))
+}
+
+/// Status of `inode` on the filesystem with device number `dev`
+pub fn stat_inode(dev: u64, inode: &Arc<dyn VfsInode>) -> Stat {
+    let attr = inode.attr();
+    let size = inode.size();
+    let mut st = Stat::empty();
+    st.dev = dev;
+    st.ino = inode.ino();
+    st.mode = StatMode::new(inode.itype().stat_mode(), attr.perm);
+    st.nlink = inode.nlink();
+    st.uid = attr.uid;
+    st.gid = attr.gid;
+    st.rdev = attr.rdev;
+    st.size = size as i64;
+    st.blksize = attr.blksize as i32;
+    st.blocks = size.div_ceil(512) as i64;
+    st.atime = attr.atime;
+    st.mtime = attr.mtime;
+    st.ctime = attr.ctime;
+    st
+}
+
+/// Whether a filesystem is mounted on `path`
+pub fn is_mount_point(path: &str) -> bool {
+    MOUNTS.exclusive_access().mounts.contains_key(path)
+}
+
+/// Whether a filesystem is mounted from the device at `source`
+pub fn is_mounted_from(source: &str) -> bool {
+    MOUNTS.exclusive_access().mounts.values().any(|mount| mount.source == source)
+}
+
+/// Names of the mount points directly inside the directory `path`
+pub fn mount_points_in(path: &str) -> Vec<String> {
+    MOUNTS
+        .exclusive_access()
+        .mounts
+        .keys()
+        .filter_map(|target| match split_parent(target) {
+            Some((parent, name)) if parent == path => Some(String::from(name)),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Mount a new `fs_type` filesystem on the directory `target`, which is
+/// registered by the path it resolves to
+pub fn mount(source: &str, target: &str, fs_type: &str) -> Result<(), isize> {
+    let dir = lookup(target)?;
+    if dir.inode.itype() != InodeType::Dir {
+        return Err(ENOTDIR);
+    }
+    if is_mount_point(&dir.path) {
+        return Err(EBUSY);
+    }
+    let sb = make_fs(fs_type, source)?;
+    MOUNTS.exclusive_access().add(&dir.path, source, sb);
+    Ok(())
+}
+
+/// Whether a task has a file open on the filesystem `dev` or its cwd in it
+fn dev_in_use(dev: u64) -> bool {
+    all_tasks().iter().any(|task| {
+        let inner = task.inner_exclusive_access();
+        let cwd = inner.cwd.clone();
+        let files: Vec<_> = inner.fd_table.iter().map(|(_, entry)| entry.file.clone()).collect();
+        drop(inner);
+        lookup(&cwd).is_ok_and(|dentry| dentry.dev == dev) || files.iter().any(|file| file.stat().dev == dev)
+    })
+}
+
+/// Unmount the filesystem mounted on the directory `target` resolves to,
+/// `EBUSY` while a task has a file open on it or its cwd in it
+pub fn umount(target: &str) -> Result<(), isize> {
+    let target = lookup(target)?.path;
+    let target = target.as_str();
+    let dev = {
+        let table = MOUNTS.exclusive_access();
+        let dev = table.mounts.get(target).ok_or(EINVAL)?.dev;
+        // the root and filesystems with others mounted below them stay
+        let busy = target == "/"
+            || table
+                .mounts
+                .keys()
+                .any(|path| path.len() > target.len() && path.starts_with(target) && path.as_bytes()[target.len()] == b'/');
+        if busy {
+            return Err(EBUSY);
+        }
+        dev
+    };
+    if dev_in_use(dev) {
+        return Err(EBUSY);
+    }
+    MOUNTS.exclusive_access().mounts.remove(target);
+    Ok(())
+}
@@ -74,6 +74,10 @@ impl MemorySet {
             .map(|area| MapAreaInfo::from(area))
 
     }
+    ///Get the description of all `MapArea`s
+    pub fn areas_info(&self) -> Vec<MapAreaInfo> {
+        self.areas.iter().map(MapAreaInfo::from).collect()
+    }
     ///Remove `MapArea` that starts with `start_vpn`
     pub fn remove_area_with_start_vpn(&mut self, start_vpn: VirtPageNum) {
         if let Some((idx, area)) = self
@@ -175,6 +175,28 @@ pub fn translated_byte_buffer(token: usize, ptr: *const u8, len: usize) -> Vec<&
     v
 }
 
+/// Whether the `len` bytes at `ptr` are mapped for the user to read, and
+/// to write too if `write`
+pub fn user_range_valid(token: usize, ptr: *const u8, len: usize, write: bool) -> bool {
+    let page_table = PageTable::from_token(token);
+    let start = ptr as usize;
+    let Some(end) = start.checked_add(len) else {
+        return false;
+    };
+    if start == 0 {
+        return false;
+    }
+    let mut vpn = VirtAddr::from(start).floor();
+    while VirtAddr::from(vpn).0 < end {
+        match page_table.translate(vpn) {
+            Some(pte) if pte.is_valid() && pte.flags().contains(PTEFlags::U) && pte.readable() && (!write || pte.writable()) => {}
+            _ => return false,
+        }
+        vpn.step();
+    }
+    true
+}
+
 /// translate a pointer to a mutable u8 Vec end with `\0` through page table to a `String`
 pub fn translated_str(token: usize, ptr: *const u8) -> String {
     let page_table = PageTable::from_token(token);
@@ -1,5 +1,7 @@
 //! Synchronization and interior mutability primitives
 
 mod up;
+mod wait_queue;
 
 pub use up::UPSafeCell;
+pub use wait_queue::WaitQueue;
@@ -0,0 +1,33 @@
+//! Queue of tasks blocked until an event
+
+use crate::task::{wakeup_task, TaskControlBlock};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Tasks to wake when an event happens. The owner of the event keeps it
+/// beside the state the event is about.
+#[derive(Default)]
+pub struct WaitQueue {
+    tasks: Vec<Arc<TaskControlBlock>>,
+}
+
+impl WaitQueue {
+    pub fn new() -> Self {
+        Self { tasks: Vec::new() }
+    }
+    /// Wake `task` on the next event
+    pub fn add(&mut self, task: &Arc<TaskControlBlock>) {
+        if !self.tasks.iter().any(|waiter| Arc::ptr_eq(waiter, task)) {
+            self.tasks.push(task.clone());
+        }
+    }
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) {
+        self.tasks.retain(|waiter| !Arc::ptr_eq(waiter, task));
+    }
+    /// Wake the waiting tasks, which add themselves again if they still wait
+    pub fn wake_all(&mut self) {
+        for task in self.tasks.drain(..) {
+            wakeup_task(task);
+        }
+    }
+}
@@ -0,0 +1,38 @@
+//! Error numbers returned by syscalls, negated, as in Linux
+
+pub const EPERM: isize = 1;
+pub const ENOENT: isize = 2;
+pub const ESRCH: isize = 3;
+pub const EINTR: isize = 4;
+pub const ENXIO: isize = 6;
+pub const EBADF: isize = 9;
+pub const EAGAIN: isize = 11;
+pub const ENOMEM: isize = 12;
+pub const EACCES: isize = 13;
+pub const EFAULT: isize = 14;
+pub const EBUSY: isize = 16;
+pub const EEXIST: isize = 17;
+pub const EXDEV: isize = 18;
+pub const ENODEV: isize = 19;
+pub const ENOTDIR: isize = 20;
+pub const EISDIR: isize = 21;
+pub const EINVAL: isize = 22;
+pub const EMFILE: isize = 24;
+pub const ENOSPC: isize = 28;
+pub const ESPIPE: isize = 29;
+pub const EPIPE: isize = 32;
+pub const ERANGE: isize = 34;
+pub const ENAMETOOLONG: isize = 36;
+pub const ENOTEMPTY: isize = 39;
+pub const ELOOP: isize = 40;
+pub const ENOTSOCK: isize = 88;
+pub const EMSGSIZE: isize = 90;
+pub const EPROTOTYPE: isize = 91;
+pub const EPROTONOSUPPORT: isize = 93;
+pub const ESOCKTNOSUPPORT: isize = 94;
+pub const EOPNOTSUPP: isize = 95;
+pub const EAFNOSUPPORT: isize = 97;
+pub const EADDRINUSE: isize = 98;
+pub const EISCONN: isize = 106;
+pub const ENOTCONN: isize = 107;
+pub const ECONNREFUSED: isize = 111;
@@ -1,26 +1,45 @@
 //! File and filesystem-related syscalls
+use alloc::string::String;
 use alloc::sync::Arc;
+use alloc::vec;
 
-use crate::fs::{create_hard_link, delete_hard_link, hard_link_cnt, make_pipe, open_file, OpenFlags, Stat, StatMode};
+use super::errno::{EAGAIN, EBADF, EINVAL, ENOTDIR, EPERM, EPIPE, ERANGE, ESPIPE};
+use crate::fs::{create_hard_link, delete_hard_link, lookup, lookup_nofollow, make_dir, make_node, make_pipe, mount, normalize, open_path, readlink, remove_dir, rename, stat_inode, symlink, truncate, umount, File, InodeType, OpenFlags, PollEvents, Stat, StatMode};
+use crate::config::PAGE_SIZE;
 use crate::mm::{translated_byte_buffer, translated_refmut, translated_str, UserBuffer};
-use crate::task::{current_task, current_user_token};
+use crate::task::{current_add_signal, current_task, current_user_token, SignalFlags};
 use crate::debug;
+use super::net::{socket_read, socket_write};
 
 pub fn sys_write(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
     let task = current_task().unwrap();
     let inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
-    }
-    if let Some(file) = &inner.fd_table[fd] {
+    if let Ok(file) = inner.fd_table.get(fd) {
         if !file.writable() {
             return -1;
         }
-        let file = file.clone();
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
-        file.write(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
+        if file.as_socket().is_some() {
+            return socket_write(&file, token, buf, len);
+        }
+        if file.status_flags().contains(OpenFlags::NONBLOCK) && !file.write_ready() {
+            return -EAGAIN;
+        }
+        let written = file.write(UserBuffer::new(translated_byte_buffer(token, buf, len)));
+        if written == 0 && len > 0 {
+            // nothing is written when all the readers are gone or the
+            // nonblocking file is full
+            if file.poll().contains(PollEvents::ERR) {
+                current_add_signal(SignalFlags::SIGPIPE);
+                return -EPIPE;
+            }
+            if file.status_flags().contains(OpenFlags::NONBLOCK) {
+                return -EAGAIN;
+            }
+        }
+        written as isize
     } else {
         -1
     }
@@ -30,62 +49,333 @@ pub fn sys_read(fd: usize, buf: *const u8, len: usize) -> isize {
     let token = current_user_token();
     let task = current_task().unwrap();
     let inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
-    }
-    if let Some(file) = &inner.fd_table[fd] {
-        let file = file.clone();
+    if let Ok(file) = inner.fd_table.get(fd) {
         if !file.readable() {
             return -1;
         }
         // release current task TCB manually to avoid multi-borrow
         drop(inner);
+        if file.as_socket().is_some() {
+            return socket_read(&file, token, buf, len);
+        }
+        if file.status_flags().contains(OpenFlags::NONBLOCK) && !file.read_ready() {
+            return -EAGAIN;
+        }
         file.read(UserBuffer::new(translated_byte_buffer(token, buf, len))) as isize
     } else {
         -1
     }
 }
 
-pub fn sys_open(path: *const u8, flags: u32) -> isize {
+/// The open file behind `fd`
+fn get_file(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
     let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    inner.fd_table.get(fd)
+}
+
+pub fn sys_lseek(fd: usize, offset: isize, whence: usize) -> isize {
+    let result = get_file(fd).and_then(|file| {
+        if !file.seekable() {
+            return Err(ESPIPE);
+        }
+        file.seek(offset, whence)
+    });
+    match result {
+        Ok(pos) => pos as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_pread64(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
     let token = current_user_token();
-    let path = translated_str(token, path);
-    if let Some(inode) = open_file(path.as_str(), OpenFlags::from_bits(flags).unwrap()) {
-        let mut inner = task.inner_exclusive_access();
-        let fd = inner.alloc_fd();
-        inner.fd_table[fd] = Some(inode);
-        fd as isize
+    let result = get_file(fd).and_then(|file| {
+        if !file.readable() {
+            return Err(EBADF);
+        }
+        if !file.seekable() {
+            return Err(ESPIPE);
+        }
+        file.read_at(offset, UserBuffer::new(translated_byte_buffer(token, buf, len)))
+    });
+    match result {
+        Ok(read_size) => read_size as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_pwrite64(fd: usize, buf: *const u8, len: usize, offset: usize) -> isize {
+    let token = current_user_token();
+    let result = get_file(fd).and_then(|file| {
+        if !file.writable() {
+            return Err(EBADF);
+        }
+        if !file.seekable() {
+            return Err(ESPIPE);
+        }
+        file.write_at(offset, UserBuffer::new(translated_byte_buffer(token, buf, len)))
+    });
+    match result {
+        Ok(write_size) => write_size as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_getdents64(fd: usize, dirp: *mut u8, count: usize) -> isize {
+    let token = current_user_token();
+    // callers loop until 0 is returned, one page per call is enough
+    let mut records = vec![0u8; count.min(PAGE_SIZE)];
+    let result = get_file(fd).and_then(|file| file.getdents(&mut records));
+    match result {
+        Ok(len) => {
+            let mut copied = 0;
+            for slice in translated_byte_buffer(token, dirp, len) {
+                slice.copy_from_slice(&records[copied..copied + slice.len()]);
+                copied += slice.len();
+            }
+            len as isize
+        }
+        Err(errno) => -errno,
+    }
+}
+
+/// Special `dirfd` meaning the current working directory
+pub const AT_FDCWD: i32 = -100;
+/// `unlinkat` flag to remove a directory instead of a file
+pub const AT_REMOVEDIR: u32 = 0x200;
+/// `fstatat` flag to report a symbolic link itself
+pub const AT_SYMLINK_NOFOLLOW: u32 = 0x100;
+
+/// Resolve `path` relative to the directory `dirfd` into a normalized absolute path
+pub(super) fn resolve_at(dirfd: i32, path: &str) -> Result<String, isize> {
+    let task = current_task().unwrap();
+    let inner = task.inner_exclusive_access();
+    if path.starts_with('/') || dirfd == AT_FDCWD {
+        return Ok(normalize(&inner.cwd, path));
+    }
+    let file = inner.fd_table.get(dirfd as usize)?;
+    drop(inner);
+    let base = file.path().ok_or(ENOTDIR)?;
+    if file.stat().mode.file_type() != StatMode::DIR {
+        return Err(ENOTDIR);
+    }
+    Ok(normalize(&base, path))
+}
+
+pub fn sys_openat(dirfd: i32, path: *const u8, flags: u32, mode: u32) -> isize {
+    let task = current_task().unwrap();
+    let token = current_user_token();
+    let flags = match OpenFlags::from_user(flags) {
+        Ok(flags) => flags,
+        Err(errno) => return -errno,
+    };
+    let cloexec = flags.contains(OpenFlags::CLOEXEC);
+    let path = match resolve_at(dirfd, &translated_str(token, path)) {
+        Ok(path) => path,
+        Err(errno) => return -errno,
+    };
+    // opening a FIFO may wait for the other side, without holding the TCB
+    let result = open_path(path.as_str(), flags, mode)
+        .and_then(|file| task.inner_exclusive_access().fd_table.alloc(file, cloexec));
+    match result {
+        Ok(fd) => fd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_getcwd(buf: *mut u8, size: usize) -> isize {
+    let token = current_user_token();
+    let cwd = current_task().unwrap().inner_exclusive_access().cwd.clone();
+    // room for the terminating nul
+    if cwd.len() + 1 > size {
+        return -ERANGE;
+    }
+    let mut bytes = cwd.into_bytes();
+    bytes.push(0);
+    let mut copied = 0;
+    for slice in translated_byte_buffer(token, buf, bytes.len()) {
+        slice.copy_from_slice(&bytes[copied..copied + slice.len()]);
+        copied += slice.len();
+    }
+    buf as isize
+}
+
+pub fn sys_chdir(path: *const u8) -> isize {
+    let token = current_user_token();
+    let path = match resolve_at(AT_FDCWD, &translated_str(token, path)) {
+        Ok(path) => path,
+        Err(errno) => return -errno,
+    };
+    match lookup(path.as_str()) {
+        Ok(dentry) if dentry.inode.itype() == InodeType::Dir => {
+            current_task().unwrap().inner_exclusive_access().cwd = path;
+            0
+        }
+        Ok(_) => -ENOTDIR,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_mkdirat(dirfd: i32, path: *const u8, _mode: u32) -> isize {
+    let token = current_user_token();
+    let result = resolve_at(dirfd, &translated_str(token, path)).and_then(|path| make_dir(path.as_str()));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_mknodat(dirfd: i32, path: *const u8, mode: u32, _dev: u64) -> isize {
+    let token = current_user_token();
+    let mode = StatMode::from_bits_retain(mode);
+    let file_type = mode.file_type();
+    // no type is a regular file
+    let itype = if file_type == StatMode::NULL || file_type == StatMode::FILE {
+        InodeType::File
+    } else if file_type == StatMode::FIFO {
+        InodeType::Fifo
+    } else if file_type == StatMode::SOCK {
+        InodeType::Socket
+    } else if file_type == StatMode::CHR || file_type == StatMode::BLK {
+        return -EPERM;
     } else {
-        -1
+        return -EINVAL;
+    };
+    let perm = (mode & StatMode::PERM_MASK).bits();
+    let result = resolve_at(dirfd, &translated_str(token, path)).and_then(|path| make_node(path.as_str(), itype, perm));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_mount(source: *const u8, target: *const u8, fstype: *const u8, _flags: u32, _data: *const u8) -> isize {
+    let token = current_user_token();
+    let source = translated_str(token, source);
+    let fstype = translated_str(token, fstype);
+    let result = resolve_at(AT_FDCWD, &translated_str(token, target))
+        .and_then(|target| mount(source.as_str(), target.as_str(), fstype.as_str()));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_umount2(target: *const u8, _flags: u32) -> isize {
+    let token = current_user_token();
+    let result = resolve_at(AT_FDCWD, &translated_str(token, target)).and_then(|target| umount(target.as_str()));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
     }
 }
 
 pub fn sys_close(fd: usize) -> isize {
     let task = current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
-    }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    let closed = inner.fd_table.close(fd);
+    // the file is closed out of the TCB borrow
+    drop(inner);
+    match closed {
+        Ok(_) => 0,
+        Err(_) => -1,
     }
-    inner.fd_table[fd].take();
-    0
 }
 
-pub fn sys_linkat(olddirfd: i32, oldpath: *const u8, newdirfd: i32, newpath: *const u8, flags: u32) -> isize {
+pub fn sys_linkat(olddirfd: i32, oldpath: *const u8, newdirfd: i32, newpath: *const u8, _flags: u32) -> isize {
     let token = current_user_token();
     let oldpath = translated_str(token, oldpath);
     let newpath = translated_str(token, newpath);
     debug!("linkat {} {}", oldpath, newpath);
-    create_hard_link(oldpath.as_str(), newpath.as_str())
+    let result = resolve_at(olddirfd, &oldpath).and_then(|oldpath| {
+        let newpath = resolve_at(newdirfd, &newpath)?;
+        create_hard_link(oldpath.as_str(), newpath.as_str())
+    });
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
 }
 
 pub fn sys_unlinkat(dirfd: i32, path: *const u8, flags: u32) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
     debug!("unlinkat {}", path);
-    delete_hard_link(path.as_str())
+    let result = resolve_at(dirfd, &path).and_then(|path| {
+        if flags & AT_REMOVEDIR != 0 {
+            remove_dir(path.as_str())
+        } else {
+            delete_hard_link(path.as_str())
+        }
+    });
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_renameat2(olddirfd: i32, oldpath: *const u8, newdirfd: i32, newpath: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let oldpath = translated_str(token, oldpath);
+    let newpath = translated_str(token, newpath);
+    let result = resolve_at(olddirfd, &oldpath).and_then(|oldpath| {
+        let newpath = resolve_at(newdirfd, &newpath)?;
+        rename(oldpath.as_str(), newpath.as_str(), flags)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_truncate(path: *const u8, length: isize) -> isize {
+    if length < 0 {
+        return -EINVAL;
+    }
+    let token = current_user_token();
+    let result = resolve_at(AT_FDCWD, &translated_str(token, path)).and_then(|path| truncate(path.as_str(), length as usize));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_ftruncate(fd: usize, length: isize) -> isize {
+    if length < 0 {
+        return -EINVAL;
+    }
+    match get_file(fd).and_then(|file| file.truncate(length as usize)) {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_symlinkat(target: *const u8, newdirfd: i32, linkpath: *const u8) -> isize {
+    let token = current_user_token();
+    let target = translated_str(token, target);
+    let result = resolve_at(newdirfd, &translated_str(token, linkpath)).and_then(|linkpath| symlink(target.as_str(), linkpath.as_str()));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_readlinkat(dirfd: i32, path: *const u8, buf: *mut u8, bufsiz: usize) -> isize {
+    let token = current_user_token();
+    let result = resolve_at(dirfd, &translated_str(token, path)).and_then(|path| readlink(path.as_str()));
+    match result {
+        Ok(target) => {
+            // no terminating nul, truncated to the buffer
+            let len = target.len().min(bufsiz);
+            let mut copied = 0;
+            for slice in translated_byte_buffer(token, buf, len) {
+                slice.copy_from_slice(&target.as_bytes()[copied..copied + slice.len()]);
+                copied += slice.len();
+            }
+            len as isize
+        }
+        Err(errno) => -errno,
+    }
 }
 
 pub fn sys_fstat(fd: i32, st: *mut Stat) -> isize {
@@ -93,8 +383,7 @@ pub fn sys_fstat(fd: i32, st: *mut Stat) -> isize {
     let st = translated_refmut(current_user_token(), st);
     let task = current_task().unwrap();
     let inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() { return -1; }
-    if let Some(file) = &inner.fd_table[fd].clone() {
+    if let Ok(file) = inner.fd_table.get(fd) {
         drop(inner);
         *st = file.stat();
         0
@@ -104,15 +393,49 @@ pub fn sys_fstat(fd: i32, st: *mut Stat) -> isize {
     }
 }
 
-pub fn sys_pipe(pipe: *mut usize) -> isize {
+pub fn sys_fstatat(dirfd: i32, path: *const u8, st: *mut Stat, flags: u32) -> isize {
+    let token = current_user_token();
+    let result = resolve_at(dirfd, &translated_str(token, path)).and_then(|path| {
+        let dentry = if flags & AT_SYMLINK_NOFOLLOW != 0 {
+            lookup_nofollow(path.as_str())?
+        } else {
+            lookup(path.as_str())?
+        };
+        Ok(stat_inode(dentry.dev, &dentry.inode))
+    });
+    match result {
+        Ok(stat) => {
+            *translated_refmut(token, st) = stat;
+            0
+        }
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_pipe(pipe: *mut usize, flags: u32) -> isize {
     let task = current_task().unwrap();
     let token = current_user_token();
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) if (OpenFlags::NONBLOCK | OpenFlags::CLOEXEC).contains(flags) => flags,
+        _ => return -EINVAL,
+    };
+    let cloexec = flags.contains(OpenFlags::CLOEXEC);
     let mut inner = task.inner_exclusive_access();
-    let (pipe_read, pipe_write) = make_pipe();
-    let read_fd = inner.alloc_fd();
-    inner.fd_table[read_fd] = Some(pipe_read);
-    let write_fd = inner.alloc_fd();
-    inner.fd_table[write_fd] = Some(pipe_write);
+    let (pipe_read, pipe_write) = match make_pipe(flags) {
+        Ok(ends) => ends,
+        Err(errno) => return -errno,
+    };
+    let read_fd = match inner.fd_table.alloc(pipe_read, cloexec) {
+        Ok(fd) => fd,
+        Err(errno) => return -errno,
+    };
+    let write_fd = match inner.fd_table.alloc(pipe_write, cloexec) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            let _ = inner.fd_table.close(read_fd);
+            return -errno;
+        }
+    };
     *translated_refmut(token, pipe) = read_fd;
     *translated_refmut(token, unsafe { pipe.add(1) }) = write_fd;
     0
@@ -121,14 +444,127 @@ pub fn sys_pipe(pipe: *mut usize) -> isize {
 pub fn sys_dup(fd: usize) -> isize {
     let task = current_task().unwrap();
     let mut inner = task.inner_exclusive_access();
-    if fd >= inner.fd_table.len() {
-        return -1;
+    let file = match inner.fd_table.get(fd) {
+        Ok(file) => file,
+        Err(_) => return -1,
+    };
+    match inner.fd_table.alloc(file, false) {
+        Ok(new_fd) => new_fd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// Make `newfd` refer to the file of `oldfd`, closing what `newfd` had
+pub fn sys_dup3(oldfd: usize, newfd: usize, flags: u32) -> isize {
+    let flags = match OpenFlags::from_bits(flags) {
+        Some(flags) if OpenFlags::CLOEXEC.contains(flags) => flags,
+        _ => return -EINVAL,
+    };
+    if oldfd == newfd {
+        return -EINVAL;
     }
-    if inner.fd_table[fd].is_none() {
-        return -1;
+    dup_to(oldfd, newfd, flags.contains(OpenFlags::CLOEXEC))
+}
+
+/// `dup3` without flags, which does nothing if both fds are the same
+pub fn sys_dup2(oldfd: usize, newfd: usize) -> isize {
+    if oldfd == newfd {
+        return match get_file(oldfd) {
+            Ok(_) => newfd as isize,
+            Err(errno) => -errno,
+        };
+    }
+    dup_to(oldfd, newfd, false)
+}
+
+fn dup_to(oldfd: usize, newfd: usize, cloexec: bool) -> isize {
+    let file = match get_file(oldfd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let closed = inner.fd_table.install(newfd, file, cloexec);
+    drop(inner);
+    // the file `newfd` had is closed out of the TCB borrow
+    match closed {
+        Ok(_) => newfd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// `fcntl` commands
+const F_DUPFD: usize = 0;
+const F_GETFD: usize = 1;
+const F_SETFD: usize = 2;
+const F_GETFL: usize = 3;
+const F_SETFL: usize = 4;
+const F_DUPFD_CLOEXEC: usize = 1030;
+/// Resize the buffer of a pipe
+const F_SETPIPE_SZ: usize = 1031;
+/// Get the size of the buffer of a pipe
+const F_GETPIPE_SZ: usize = 1032;
+/// The only fd flag, close-on-exec
+const FD_CLOEXEC: usize = 1;
+
+pub fn sys_fcntl(fd: usize, cmd: usize, arg: usize) -> isize {
+    let file = match get_file(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    match cmd {
+        F_DUPFD | F_DUPFD_CLOEXEC => {
+            if arg as u64 >= inner.fd_table.limit().cur {
+                return -EINVAL;
+            }
+            match inner.fd_table.alloc_from(arg, file, cmd == F_DUPFD_CLOEXEC) {
+                Ok(new_fd) => new_fd as isize,
+                Err(errno) => -errno,
+            }
+        }
+        F_GETFD => match inner.fd_table.entry(fd) {
+            Ok(entry) if entry.cloexec => FD_CLOEXEC as isize,
+            Ok(_) => 0,
+            Err(errno) => -errno,
+        },
+        F_SETFD => match inner.fd_table.entry_mut(fd) {
+            Ok(entry) => {
+                entry.cloexec = arg & FD_CLOEXEC != 0;
+                0
+            }
+            Err(errno) => -errno,
+        },
+        F_GETFL => {
+            drop(inner);
+            let access = match (file.readable(), file.writable()) {
+                (true, true) => OpenFlags::RDWR,
+                (false, true) => OpenFlags::WRONLY,
+                _ => OpenFlags::RDONLY,
+            };
+            (access | file.status_flags()).bits() as isize
+        }
+        F_SETFL => {
+            drop(inner);
+            // the access mode and the creation flags are ignored
+            file.set_status_flags(OpenFlags::from_bits_truncate(arg as u32) & OpenFlags::STATUS);
+            0
+        }
+        F_SETPIPE_SZ | F_GETPIPE_SZ => {
+            drop(inner);
+            let Some(pipe) = file.as_pipe() else {
+                return -EBADF;
+            };
+            if cmd == F_GETPIPE_SZ {
+                return pipe.capacity() as isize;
+            }
+            match pipe.set_capacity(arg) {
+                Ok(size) => size as isize,
+                Err(errno) => -errno,
+            }
+        }
+        _ => -EINVAL,
     }
-    let new_fd = inner.alloc_fd();
-    inner.fd_table[new_fd] = Some(Arc::clone(inner.fd_table[fd].as_ref().unwrap()));
-    new_fd as isize
 }
 
@@ -11,15 +11,37 @@
 //! submodules, and you should also implement syscalls this way.
 
 const SYSCALL_DUP: usize = 24;
+const SYSCALL_FCNTL: usize = 25;
 const SYSCALL_PIPE: usize = 59;
-const SYSCALL_OPEN: usize = 56;
+const SYSCALL_OPENAT: usize = 56;
+const SYSCALL_GETCWD: usize = 17;
+const SYSCALL_EPOLL_CREATE1: usize = 20;
+const SYSCALL_EPOLL_CTL: usize = 21;
+const SYSCALL_EPOLL_PWAIT: usize = 22;
+const SYSCALL_PSELECT6: usize = 72;
+const SYSCALL_PPOLL: usize = 73;
+const SYSCALL_CHDIR: usize = 49;
+const SYSCALL_MKNODAT: usize = 33;
+const SYSCALL_MKDIRAT: usize = 34;
+const SYSCALL_UMOUNT2: usize = 39;
+const SYSCALL_MOUNT: usize = 40;
 const SYSCALL_CLOSE: usize = 57;
+const SYSCALL_GETDENTS64: usize = 61;
+const SYSCALL_LSEEK: usize = 62;
 const SYSCALL_READ: usize = 63;
 const SYSCALL_WRITE: usize = 64;
+const SYSCALL_PREAD64: usize = 67;
+const SYSCALL_PWRITE64: usize = 68;
 const SYSCALL_EXIT: usize = 93;
 const SYSCALL_YIELD: usize = 124;
+const SYSCALL_NANOSLEEP: usize = 101;
 const SYSCALL_GET_TIME: usize = 169;
 const SYSCALL_TASKINFO: usize = 410;
+const SYSCALL_TIMES: usize = 153;
+const SYSCALL_GETRUSAGE: usize = 165;
+const SYSCALL_GETRLIMIT: usize = 163;
+const SYSCALL_SETRLIMIT: usize = 164;
+const SYSCALL_PRLIMIT64: usize = 261;
 const SYSCALL_MMAP: usize = 222;
 const SYSCALL_MUNMAP: usize = 215;
 const SYSCALL_GETPID: usize = 172;
@@ -28,8 +50,21 @@ const SYSCALL_EXEC: usize = 221;
 const SYSCALL_WAITPID: usize = 260;
 const SYSCALL_SPAWN: usize = 400;
 const SYSCALL_SET_PRIORITY: usize = 140;
+const SYSCALL_SCHED_SETPARAM: usize = 118;
+const SYSCALL_SCHED_SETSCHEDULER: usize = 119;
+const SYSCALL_SCHED_GETSCHEDULER: usize = 120;
+const SYSCALL_SCHED_GETPARAM: usize = 121;
+const SYSCALL_SCHED_GET_PRIORITY_MAX: usize = 125;
+const SYSCALL_SCHED_GET_PRIORITY_MIN: usize = 126;
+const SYSCALL_SCHED_RR_GET_INTERVAL: usize = 127;
 const SYSCALL_LINKAT: usize = 37;
+const SYSCALL_TRUNCATE: usize = 45;
+const SYSCALL_FTRUNCATE: usize = 46;
+const SYSCALL_RENAMEAT2: usize = 276;
 const SYSCALL_UNLINKAT: usize = 35;
+const SYSCALL_SYMLINKAT: usize = 36;
+const SYSCALL_READLINKAT: usize = 78;
+const SYSCALL_FSTATAT: usize = 79;
 const SYSCALL_FSTAT: usize = 80;
 const SYSCALL_SIGACTION: usize = 134;
 const SYSCALL_SIGPROCMASK: usize = 135;
@@ -37,50 +72,97 @@ const SYSCALL_SIGRETURN: usize = 139;
 const SYSCALL_KILL: usize = 129;
 const SYSCALL_MAILREAD: usize = 401;
 const SYSCALL_MAILWRITE: usize = 402;
+const SYSCALL_NICE: usize = 403;
+const SYSCALL_MAILOPEN: usize = 406;
+const SYSCALL_MAILRECV: usize = 407;
+const SYSCALL_MAILCONFIG: usize = 408;
+const SYSCALL_SOCKET: usize = 198;
+const SYSCALL_SOCKETPAIR: usize = 199;
+const SYSCALL_BIND: usize = 200;
+const SYSCALL_LISTEN: usize = 201;
+const SYSCALL_ACCEPT: usize = 202;
+const SYSCALL_CONNECT: usize = 203;
+const SYSCALL_GETSOCKNAME: usize = 204;
+const SYSCALL_GETPEERNAME: usize = 205;
+const SYSCALL_SENDTO: usize = 206;
+const SYSCALL_RECVFROM: usize = 207;
+const SYSCALL_SHUTDOWN: usize = 210;
+const SYSCALL_SENDMSG: usize = 211;
+const SYSCALL_RECVMSG: usize = 212;
+const SYSCALL_ACCEPT4: usize = 242;
+// 24 is taken by `dup`, `dup3` of Linux
+const SYSCALL_DUP2: usize = 404;
+const SYSCALL_DUP3: usize = 405;
+/// Number of distinct syscalls counted in `UserTaskInfo`
 pub const MAX_SYSCALL_NUM: usize = 27;
+/// Number of distinct syscalls counted in `UserTaskInfoExt`
+pub const MAX_SYSCALL_NUM_EXT: usize = 64;
 
+pub mod errno;
 mod fs;
 mod process;
 mod mem;
+mod net;
+mod poll;
 
 use fs::*;
 use process::*;
-use crate::{fs::Stat, task::{SignalAction, UserTaskInfo}};
+use crate::{fs::{EpollEvent, Stat}, task::{count_current_syscall, RLimit, SchedParam, SignalAction}, timer::TimeSpec};
 use mem::*;
+use net::*;
+use poll::*;
 
 /// handle syscall exception with `syscall_id` and other arguments
 pub fn syscall(syscall_id: usize, args: [usize; 7]) -> isize {
-    // let mut inner = TASK_MANAGER.inner.exclusive_access();
-    // let cur = inner.current();
-    // match syscall_id {
-    //     SYSCALL_EXIT | SYSCALL_GET_TIME | SYSCALL_TASKINFO | SYSCALL_READ |
-    //     SYSCALL_WRITE | SYSCALL_YIELD | SYSCALL_MMAP | SYSCALL_MUNMAP | 
-    //     SYSCALL_GETPID | SYSCALL_FORK | SYSCALL_EXEC | SYSCALL_WAITPID => (),
-    //     _ => panic!("Unsupported syscall_id: {}", syscall_id),
-    // }
-    // for i in 0..MAX_SYSCALL_NUM {
-    //     if inner.taskinfo[cur].call[i].id == syscall_id {
-    //         inner.taskinfo[cur].call[i].times += 1;
-    //         break;
-    //     }
-    //     else if inner.taskinfo[cur].call[i].id == 0 {
-    //         inner.taskinfo[cur].call[i].id = syscall_id;
-    //         inner.taskinfo[cur].call[i].times = 1;
-    //         break;
-    //     }
-    // }
-    // drop(inner);
+    count_current_syscall(syscall_id);
     match syscall_id {
         SYSCALL_DUP => sys_dup(args[0]),
-        SYSCALL_PIPE => sys_pipe(args[0] as *mut usize),
-        SYSCALL_OPEN => sys_open(args[0] as *const u8, args[1] as u32),
+        SYSCALL_EPOLL_CREATE1 => sys_epoll_create1(args[0] as u32),
+        SYSCALL_EPOLL_CTL => sys_epoll_ctl(args[0], args[1], args[2], args[3] as *const EpollEvent),
+        SYSCALL_EPOLL_PWAIT => sys_epoll_pwait(args[0], args[1] as *mut EpollEvent, args[2] as i32, args[3] as i32, args[4]),
+        SYSCALL_PSELECT6 => sys_pselect6(
+            args[0],
+            args[1] as *mut u8,
+            args[2] as *mut u8,
+            args[3] as *mut u8,
+            args[4] as *const TimeSpec,
+            args[5],
+        ),
+        SYSCALL_PPOLL => sys_ppoll(args[0] as *mut u8, args[1], args[2] as *const TimeSpec, args[3]),
+        SYSCALL_DUP2 => sys_dup2(args[0], args[1]),
+        SYSCALL_DUP3 => sys_dup3(args[0], args[1], args[2] as u32),
+        SYSCALL_FCNTL => sys_fcntl(args[0], args[1], args[2]),
+        SYSCALL_PIPE => sys_pipe(args[0] as *mut usize, args[1] as u32),
+        SYSCALL_OPENAT => sys_openat(args[0] as i32, args[1] as *const u8, args[2] as u32, args[3] as u32),
+        SYSCALL_GETCWD => sys_getcwd(args[0] as *mut u8, args[1]),
+        SYSCALL_CHDIR => sys_chdir(args[0] as *const u8),
+        SYSCALL_MKNODAT => sys_mknodat(args[0] as i32, args[1] as *const u8, args[2] as u32, args[3] as u64),
+        SYSCALL_MKDIRAT => sys_mkdirat(args[0] as i32, args[1] as *const u8, args[2] as u32),
+        SYSCALL_UMOUNT2 => sys_umount2(args[0] as *const u8, args[1] as u32),
+        SYSCALL_MOUNT => sys_mount(
+            args[0] as *const u8,
+            args[1] as *const u8,
+            args[2] as *const u8,
+            args[3] as u32,
+            args[4] as *const u8,
+        ),
         SYSCALL_CLOSE => sys_close(args[0] as usize),
+        SYSCALL_GETDENTS64 => sys_getdents64(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_LSEEK => sys_lseek(args[0], args[1] as isize, args[2]),
         SYSCALL_READ => sys_read(args[0], args[1] as *const u8, args[2]),
         SYSCALL_WRITE => sys_write(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_PREAD64 => sys_pread64(args[0], args[1] as *const u8, args[2], args[3]),
+        SYSCALL_PWRITE64 => sys_pwrite64(args[0], args[1] as *const u8, args[2], args[3]),
         SYSCALL_EXIT => sys_exit(args[0] as i32),
         SYSCALL_YIELD => sys_yield(),
+        SYSCALL_NANOSLEEP => sys_nanosleep(args[0] as *const TimeSpec, args[1] as *mut TimeSpec),
         SYSCALL_GET_TIME => sys_get_time(),
-        // SYSCALL_TASKINFO => sys_task_info(args[0], args[1] as *mut UserTaskInfo),
+        SYSCALL_TASKINFO => sys_task_info(args[0], args[1] as *mut u8, args[2]),
+        SYSCALL_TIMES => sys_times(args[0] as *mut Tms),
+        SYSCALL_GETRUSAGE => sys_getrusage(args[0] as i32, args[1] as *mut RUsage),
+        SYSCALL_GETRLIMIT => sys_getrlimit(args[0] as u32, args[1] as *mut RLimit),
+        SYSCALL_SETRLIMIT => sys_setrlimit(args[0] as u32, args[1] as *const RLimit),
+        SYSCALL_PRLIMIT64 => sys_prlimit64(args[0], args[1] as u32, args[2] as *const RLimit, args[3] as *mut RLimit),
         SYSCALL_MMAP => sys_mmap(args[0], args[1] , args[2]),
         SYSCALL_MUNMAP => sys_munmap(args[0]),
         SYSCALL_GETPID => sys_getpid(),
@@ -88,9 +170,23 @@ pub fn syscall(syscall_id: usize, args: [usize; 7]) -> isize {
         SYSCALL_EXEC => sys_exec(args[0] as *const u8, args[1] as *const usize),
         SYSCALL_WAITPID => sys_waitpid(args[0] as isize, args[1] as *mut i32),
         SYSCALL_SPAWN => sys_spawn(args[0] as *const u8, args[1] as *const usize),
-        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as u8),
+        SYSCALL_SET_PRIORITY => sys_set_priority(args[0] as isize),
+        SYSCALL_NICE => sys_nice(args[0] as i32),
+        SYSCALL_SCHED_SETPARAM => sys_sched_setparam(args[0], args[1] as *const SchedParam),
+        SYSCALL_SCHED_SETSCHEDULER => sys_sched_setscheduler(args[0], args[1] as i32, args[2] as *const SchedParam),
+        SYSCALL_SCHED_GETSCHEDULER => sys_sched_getscheduler(args[0]),
+        SYSCALL_SCHED_GETPARAM => sys_sched_getparam(args[0], args[1] as *mut SchedParam),
+        SYSCALL_SCHED_GET_PRIORITY_MAX => sys_sched_get_priority_max(args[0] as i32),
+        SYSCALL_SCHED_GET_PRIORITY_MIN => sys_sched_get_priority_min(args[0] as i32),
+        SYSCALL_SCHED_RR_GET_INTERVAL => sys_sched_rr_get_interval(args[0], args[1] as *mut TimeSpec),
         SYSCALL_LINKAT => sys_linkat(args[0] as i32, args[1] as *const u8, args[2] as i32, args[3] as *const u8, args[4] as u32),
+        SYSCALL_TRUNCATE => sys_truncate(args[0] as *const u8, args[1] as isize),
+        SYSCALL_FTRUNCATE => sys_ftruncate(args[0], args[1] as isize),
+        SYSCALL_RENAMEAT2 => sys_renameat2(args[0] as i32, args[1] as *const u8, args[2] as i32, args[3] as *const u8, args[4] as u32),
+        SYSCALL_SYMLINKAT => sys_symlinkat(args[0] as *const u8, args[1] as i32, args[2] as *const u8),
+        SYSCALL_READLINKAT => sys_readlinkat(args[0] as i32, args[1] as *const u8, args[2] as *mut u8, args[3]),
         SYSCALL_UNLINKAT => sys_unlinkat(args[0] as i32, args[1] as *const u8, args[2] as u32),
+        SYSCALL_FSTATAT => sys_fstatat(args[0] as i32, args[1] as *const u8, args[2] as *mut Stat, args[3] as u32),
         SYSCALL_FSTAT => sys_fstat(args[0] as i32, args[1] as *mut Stat),
         SYSCALL_KILL => sys_kill(args[0], args[1] as i32),
         SYSCALL_SIGACTION => sys_sigaction(
@@ -100,8 +196,25 @@ pub fn syscall(syscall_id: usize, args: [usize; 7]) -> isize {
         ),
         SYSCALL_SIGPROCMASK => sys_sigprocmask(args[0] as u32),
         SYSCALL_SIGRETURN => sys_sigreturn(),
+        SYSCALL_SOCKET => sys_socket(args[0], args[1], args[2]),
+        SYSCALL_SOCKETPAIR => sys_socketpair(args[0], args[1], args[2], args[3] as *mut i32),
+        SYSCALL_BIND => sys_bind(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_LISTEN => sys_listen(args[0], args[1] as i32),
+        SYSCALL_ACCEPT => sys_accept(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SYSCALL_ACCEPT4 => sys_accept4(args[0], args[1] as *mut u8, args[2] as *mut u32, args[3]),
+        SYSCALL_CONNECT => sys_connect(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_GETSOCKNAME => sys_getsockname(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SYSCALL_GETPEERNAME => sys_getpeername(args[0], args[1] as *mut u8, args[2] as *mut u32),
+        SYSCALL_SENDTO => sys_sendto(args[0], args[1] as *const u8, args[2], args[3] as u32, args[4] as *const u8, args[5]),
+        SYSCALL_RECVFROM => sys_recvfrom(args[0], args[1] as *mut u8, args[2], args[3] as u32, args[4] as *mut u8, args[5] as *mut u32),
+        SYSCALL_SHUTDOWN => sys_shutdown(args[0], args[1]),
+        SYSCALL_SENDMSG => sys_sendmsg(args[0], args[1] as *const u8, args[2] as u32),
+        SYSCALL_RECVMSG => sys_recvmsg(args[0], args[1] as *mut u8, args[2] as u32),
         SYSCALL_MAILREAD => sys_mailread(args[0] as *mut u8, args[1]),
         SYSCALL_MAILWRITE => sys_mailwrite(args[0], args[1] as *const u8, args[2]),
+        SYSCALL_MAILOPEN => sys_mailopen(args[0], args[1] as u32),
+        SYSCALL_MAILRECV => sys_mailrecv(args[0], args[1] as *mut u8, args[2], args[3], args[4] as *mut usize),
+        SYSCALL_MAILCONFIG => sys_mailconfig(args[0], args[1], args[2]),
         _ => panic!("Unsupported syscall_id: {}", syscall_id),
     }
 }
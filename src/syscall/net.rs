@@ -0,0 +1,535 @@
+//! Unix domain socket syscalls
+//!
+//! Addresses are `struct sockaddr_un`, a path in one is resolved against
+//! the working directory of the caller. The only control message is
+//! `SCM_RIGHTS`, which passes open files.
+use super::errno::{
+    EAFNOSUPPORT, EBADF, EINVAL, EMSGSIZE, ENOTSOCK, EOPNOTSUPP, EPIPE, EPROTONOSUPPORT, ESOCKTNOSUPPORT,
+};
+use super::fs::{resolve_at, AT_FDCWD};
+use super::poll::{fd_file, read_user, write_user};
+use crate::fs::{File, OpenFlags, Received, Socket, SocketType, UnixAddr, AF_UNIX, SOCKET_BUFFER_SIZE};
+use crate::mm::{translated_ref, translated_refmut};
+use crate::task::{current_add_signal, current_task, current_user_token, SignalFlags};
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::take;
+
+/// `socket` types
+const SOCK_STREAM: usize = 1;
+const SOCK_DGRAM: usize = 2;
+/// Bits of the type in the type argument of `socket`
+const SOCK_TYPE_MASK: usize = 0xf;
+/// `socket` flags in the type argument, as their `open` flags
+const SOCK_NONBLOCK: usize = 0o4000;
+const SOCK_CLOEXEC: usize = 0o2000000;
+/// Size of `struct sockaddr_un`
+const SOCKADDR_UN_LEN: usize = 110;
+
+/// `send` and `recv` flags
+const MSG_CTRUNC: u32 = 0x8;
+const MSG_TRUNC: u32 = 0x20;
+const MSG_DONTWAIT: u32 = 0x40;
+const MSG_NOSIGNAL: u32 = 0x4000;
+const MSG_CMSG_CLOEXEC: u32 = 0x40000000;
+
+/// Level and type of the control message passing files
+const SOL_SOCKET: i32 = 1;
+const SCM_RIGHTS: i32 = 1;
+/// Most files passed in one message
+const SCM_MAX_FD: usize = 253;
+/// Size of `struct cmsghdr` before the data
+const CMSG_HEADER_LEN: usize = 16;
+/// Size of `struct msghdr`
+const MSGHDR_LEN: usize = 56;
+/// Most `struct iovec`s of one message
+const UIO_MAXIOV: usize = 1024;
+
+/// User buffers, as (address, length), read and written as one
+struct Segments(Vec<(usize, usize)>);
+
+impl Segments {
+    fn total(&self) -> usize {
+        self.0.iter().map(|(_, len)| len).sum()
+    }
+    /// Copy `len` bytes from `offset` on out of user space
+    fn read(&self, token: usize, mut offset: usize, len: usize) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(len);
+        for &(base, seg_len) in self.0.iter() {
+            if bytes.len() == len {
+                break;
+            }
+            if offset >= seg_len {
+                offset -= seg_len;
+                continue;
+            }
+            let count = (seg_len - offset).min(len - bytes.len());
+            bytes.extend(read_user(token, (base + offset) as *const u8, count));
+            offset = 0;
+        }
+        bytes
+    }
+    /// Copy `bytes` to the start of the buffers
+    fn write(&self, token: usize, bytes: &[u8]) {
+        let mut copied = 0;
+        for &(base, seg_len) in self.0.iter() {
+            let count = seg_len.min(bytes.len() - copied);
+            write_user(token, base as *mut u8, &bytes[copied..copied + count]);
+            copied += count;
+        }
+    }
+}
+
+/// Check the domain and protocol arguments of `socket`
+fn check_domain(domain: usize, protocol: usize) -> Result<(), isize> {
+    if domain != AF_UNIX as usize {
+        return Err(EAFNOSUPPORT);
+    }
+    // 0 or `PF_UNIX`
+    if protocol != 0 && protocol != AF_UNIX as usize {
+        return Err(EPROTONOSUPPORT);
+    }
+    Ok(())
+}
+
+/// Split the type argument of `socket` into the type, the status flags
+/// and the close-on-exec flag of the new fds
+fn socket_type(stype: usize) -> Result<(SocketType, OpenFlags, bool), isize> {
+    if stype & !(SOCK_TYPE_MASK | SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return Err(EINVAL);
+    }
+    let socket_type = match stype & SOCK_TYPE_MASK {
+        SOCK_STREAM => SocketType::Stream,
+        SOCK_DGRAM => SocketType::Datagram,
+        _ => return Err(ESOCKTNOSUPPORT),
+    };
+    let status = if stype & SOCK_NONBLOCK != 0 { OpenFlags::NONBLOCK } else { OpenFlags::empty() };
+    Ok((socket_type, status, stype & SOCK_CLOEXEC != 0))
+}
+
+/// Read a `struct sockaddr_un` of `len` bytes
+fn read_addr(token: usize, addr: *const u8, len: usize) -> Result<UnixAddr, isize> {
+    if !(2..=SOCKADDR_UN_LEN).contains(&len) {
+        return Err(EINVAL);
+    }
+    let bytes = read_user(token, addr, len);
+    if u16::from_le_bytes([bytes[0], bytes[1]]) != AF_UNIX {
+        return Err(EINVAL);
+    }
+    let path = &bytes[2..];
+    match path.first() {
+        None => Ok(UnixAddr::Unnamed),
+        Some(0) => Ok(UnixAddr::Abstract(Vec::from(&path[1..]))),
+        Some(_) => {
+            let end = path.iter().position(|byte| *byte == 0).unwrap_or(path.len());
+            let path = core::str::from_utf8(&path[..end]).map_err(|_| EINVAL)?;
+            Ok(UnixAddr::Path(resolve_at(AT_FDCWD, path)?))
+        }
+    }
+}
+
+/// Write `value` to the buffer `addr` of `*addrlen` bytes, cut to fit,
+/// and its whole length to `*addrlen`
+fn write_addr(token: usize, addr: *mut u8, addrlen: *mut u32, value: &UnixAddr) {
+    if addr.is_null() {
+        return;
+    }
+    let bytes = value.to_bytes();
+    let room = *translated_ref(token, addrlen) as usize;
+    write_user(token, addr, &bytes[..bytes.len().min(room)]);
+    *translated_refmut(token, addrlen) = bytes.len() as u32;
+}
+
+/// The open file of `fd`, `ENOTSOCK` if it is not a socket
+fn socket_file(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let file = fd_file(fd).ok_or(EBADF)?;
+    if file.as_socket().is_none() {
+        return Err(ENOTSOCK);
+    }
+    Ok(file)
+}
+
+fn alloc_fd(file: Arc<dyn File + Send + Sync>, cloexec: bool) -> Result<usize, isize> {
+    current_task().unwrap().inner_exclusive_access().fd_table.alloc(file, cloexec)
+}
+
+/// Send the bytes of `segs` with the files `rights` on `socket`, a stream
+/// in pieces the size of its buffer. A broken connection raises `SIGPIPE`
+/// unless `MSG_NOSIGNAL`.
+fn send_on(
+    socket: &Socket,
+    token: usize,
+    segs: &Segments,
+    mut rights: Vec<Arc<dyn File + Send + Sync>>,
+    to: Option<&UnixAddr>,
+    flags: u32,
+) -> Result<usize, isize> {
+    if flags & !(MSG_DONTWAIT | MSG_NOSIGNAL) != 0 {
+        return Err(EOPNOTSUPP);
+    }
+    let nonblock = flags & MSG_DONTWAIT != 0;
+    let total = segs.total();
+    let result = if socket.socket_type() == SocketType::Datagram {
+        if total > SOCKET_BUFFER_SIZE {
+            return Err(EMSGSIZE);
+        }
+        socket.send(&segs.read(token, 0, total), rights, to, nonblock)
+    } else {
+        let mut sent = 0;
+        loop {
+            let piece = segs.read(token, sent, (total - sent).min(SOCKET_BUFFER_SIZE));
+            match socket.send(&piece, take(&mut rights), to, nonblock) {
+                Ok(len) => {
+                    sent += len;
+                    if len < piece.len() || sent == total {
+                        break Ok(sent);
+                    }
+                }
+                Err(errno) => break if sent > 0 { Ok(sent) } else { Err(errno) },
+            }
+        }
+    };
+    if result == Err(EPIPE) && flags & MSG_NOSIGNAL == 0 {
+        current_add_signal(SignalFlags::SIGPIPE);
+    }
+    result
+}
+
+/// Receive on `socket` into `segs`
+fn recv_on(socket: &Socket, token: usize, segs: &Segments, flags: u32) -> Result<Received, isize> {
+    if flags & !(MSG_DONTWAIT | MSG_CMSG_CLOEXEC) != 0 {
+        return Err(EOPNOTSUPP);
+    }
+    // no more than the buffer of the socket arrives at once
+    let mut data = vec![0u8; segs.total().min(SOCKET_BUFFER_SIZE)];
+    let received = socket.recv(&mut data, flags & MSG_DONTWAIT != 0)?;
+    segs.write(token, &data[..received.len]);
+    Ok(received)
+}
+
+/// `write` on a socket, a send without flags
+pub(super) fn socket_write(file: &Arc<dyn File + Send + Sync>, token: usize, buf: *const u8, len: usize) -> isize {
+    let segs = Segments(vec![(buf as usize, len)]);
+    match send_on(file.as_socket().unwrap(), token, &segs, Vec::new(), None, 0) {
+        Ok(len) => len as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// `read` on a socket, a receive without flags closing the files passed
+pub(super) fn socket_read(file: &Arc<dyn File + Send + Sync>, token: usize, buf: *const u8, len: usize) -> isize {
+    let segs = Segments(vec![(buf as usize, len)]);
+    match recv_on(file.as_socket().unwrap(), token, &segs, 0) {
+        Ok(received) => received.len as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_socket(domain: usize, stype: usize, protocol: usize) -> isize {
+    let result = check_domain(domain, protocol).and_then(|()| socket_type(stype)).and_then(|(stype, status, cloexec)| {
+        alloc_fd(Socket::new(stype, status), cloexec)
+    });
+    match result {
+        Ok(fd) => fd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_socketpair(domain: usize, stype: usize, protocol: usize, sv: *mut i32) -> isize {
+    let token = current_user_token();
+    let (stype, status, cloexec) = match check_domain(domain, protocol).and_then(|()| socket_type(stype)) {
+        Ok(args) => args,
+        Err(errno) => return -errno,
+    };
+    let (a, b) = Socket::pair(stype, status);
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    let fd_a = match inner.fd_table.alloc(a, cloexec) {
+        Ok(fd) => fd,
+        Err(errno) => return -errno,
+    };
+    let fd_b = match inner.fd_table.alloc(b, cloexec) {
+        Ok(fd) => fd,
+        Err(errno) => {
+            let _ = inner.fd_table.close(fd_a);
+            return -errno;
+        }
+    };
+    drop(inner);
+    *translated_refmut(token, sv) = fd_a as i32;
+    *translated_refmut(token, unsafe { sv.add(1) }) = fd_b as i32;
+    0
+}
+
+pub fn sys_bind(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| {
+        let addr = read_addr(token, addr, addrlen)?;
+        file.as_socket().unwrap().bind(addr)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_listen(fd: usize, backlog: i32) -> isize {
+    let result = socket_file(fd).and_then(|file| file.as_socket().unwrap().listen(backlog.max(0) as usize));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_accept4(fd: usize, addr: *mut u8, addrlen: *mut u32, flags: usize) -> isize {
+    let token = current_user_token();
+    if flags & !(SOCK_NONBLOCK | SOCK_CLOEXEC) != 0 {
+        return -EINVAL;
+    }
+    let result = socket_file(fd).and_then(|file| {
+        let accepted = file.as_socket().unwrap().accept(false)?;
+        if flags & SOCK_NONBLOCK != 0 {
+            accepted.set_status_flags(OpenFlags::NONBLOCK);
+        }
+        write_addr(token, addr, addrlen, &accepted.peer_addr()?);
+        alloc_fd(accepted, flags & SOCK_CLOEXEC != 0)
+    });
+    match result {
+        Ok(fd) => fd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_accept(fd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    sys_accept4(fd, addr, addrlen, 0)
+}
+
+pub fn sys_connect(fd: usize, addr: *const u8, addrlen: usize) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| {
+        let addr = read_addr(token, addr, addrlen)?;
+        file.as_socket().unwrap().connect(&addr)
+    });
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_getsockname(fd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    let token = current_user_token();
+    match socket_file(fd) {
+        Ok(file) => {
+            write_addr(token, addr, addrlen, &file.as_socket().unwrap().local_addr());
+            0
+        }
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_getpeername(fd: usize, addr: *mut u8, addrlen: *mut u32) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| file.as_socket().unwrap().peer_addr());
+    match result {
+        Ok(peer) => {
+            write_addr(token, addr, addrlen, &peer);
+            0
+        }
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_shutdown(fd: usize, how: usize) -> isize {
+    let result = socket_file(fd).and_then(|file| file.as_socket().unwrap().shutdown(how));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_sendto(fd: usize, buf: *const u8, len: usize, flags: u32, dest: *const u8, addrlen: usize) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| {
+        let to = if dest.is_null() { None } else { Some(read_addr(token, dest, addrlen)?) };
+        let segs = Segments(vec![(buf as usize, len)]);
+        send_on(file.as_socket().unwrap(), token, &segs, Vec::new(), to.as_ref(), flags)
+    });
+    match result {
+        Ok(len) => len as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_recvfrom(fd: usize, buf: *mut u8, len: usize, flags: u32, src: *mut u8, addrlen: *mut u32) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| {
+        let segs = Segments(vec![(buf as usize, len)]);
+        let received = recv_on(file.as_socket().unwrap(), token, &segs, flags)?;
+        write_addr(token, src, addrlen, &received.from);
+        Ok(received.len)
+    });
+    match result {
+        Ok(len) => len as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// The fields of a `struct msghdr` that matter here
+struct MsgHdr {
+    name: usize,
+    namelen: usize,
+    segs: Segments,
+    control: usize,
+    controllen: usize,
+}
+
+fn read_msghdr(token: usize, msg: *const u8) -> Result<MsgHdr, isize> {
+    let bytes = read_user(token, msg, MSGHDR_LEN);
+    let field = |offset: usize| usize::from_le_bytes(bytes[offset..offset + 8].try_into().unwrap());
+    let (iov, iovlen) = (field(16), field(24));
+    if iovlen > UIO_MAXIOV {
+        return Err(EMSGSIZE);
+    }
+    // `struct iovec` is a base and a length
+    let segs = read_user(token, iov as *const u8, iovlen * 16)
+        .chunks_exact(16)
+        .map(|iovec| {
+            (
+                usize::from_le_bytes(iovec[0..8].try_into().unwrap()),
+                usize::from_le_bytes(iovec[8..16].try_into().unwrap()),
+            )
+        })
+        .collect();
+    Ok(MsgHdr {
+        name: field(0),
+        namelen: u32::from_le_bytes(bytes[8..12].try_into().unwrap()) as usize,
+        segs: Segments(segs),
+        control: field(32),
+        controllen: field(40),
+    })
+}
+
+/// Control messages start at multiples of 8 bytes
+fn cmsg_align(len: usize) -> usize {
+    (len + 7) & !7
+}
+
+/// Open files of the `SCM_RIGHTS` messages in the control buffer
+fn read_rights(token: usize, control: usize, controllen: usize) -> Result<Vec<Arc<dyn File + Send + Sync>>, isize> {
+    let mut rights = Vec::new();
+    if control == 0 {
+        return Ok(rights);
+    }
+    let bytes = read_user(token, control as *const u8, controllen);
+    let mut offset = 0;
+    while offset + CMSG_HEADER_LEN <= bytes.len() {
+        let cmsg = &bytes[offset..];
+        let cmsg_len = usize::from_le_bytes(cmsg[0..8].try_into().unwrap());
+        let level = i32::from_le_bytes(cmsg[8..12].try_into().unwrap());
+        let cmsg_type = i32::from_le_bytes(cmsg[12..16].try_into().unwrap());
+        if cmsg_len < CMSG_HEADER_LEN || cmsg_len > cmsg.len() || level != SOL_SOCKET || cmsg_type != SCM_RIGHTS {
+            return Err(EINVAL);
+        }
+        for fd in cmsg[CMSG_HEADER_LEN..cmsg_len].chunks_exact(4) {
+            let fd = i32::from_le_bytes(fd.try_into().unwrap());
+            if fd < 0 {
+                return Err(EBADF);
+            }
+            rights.push(fd_file(fd as usize).ok_or(EBADF)?);
+        }
+        if rights.len() > SCM_MAX_FD {
+            return Err(EINVAL);
+        }
+        offset += cmsg_align(cmsg_len);
+    }
+    Ok(rights)
+}
+
+/// Put the files passed on new fds and write an `SCM_RIGHTS` message with
+/// them to the control buffer, return its length and whether files were
+/// dropped for want of room or fds
+fn write_rights(
+    token: usize,
+    control: usize,
+    controllen: usize,
+    mut rights: Vec<Arc<dyn File + Send + Sync>>,
+    cloexec: bool,
+) -> (usize, bool) {
+    if rights.is_empty() {
+        return (0, false);
+    }
+    let room = if control == 0 { 0 } else { controllen.saturating_sub(CMSG_HEADER_LEN) / 4 };
+    let passed = rights.len();
+    // the files left out are closed outside the TCB
+    let dropped = rights.split_off(room.min(passed));
+    let mut fds = Vec::new();
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    for file in rights {
+        match inner.fd_table.alloc(file, cloexec) {
+            Ok(fd) => fds.push(fd as i32),
+            Err(_) => break,
+        }
+    }
+    drop(inner);
+    drop(dropped);
+    if fds.is_empty() {
+        return (0, true);
+    }
+    let cmsg_len = CMSG_HEADER_LEN + fds.len() * 4;
+    let mut bytes = Vec::with_capacity(cmsg_len);
+    bytes.extend_from_slice(&cmsg_len.to_le_bytes());
+    bytes.extend_from_slice(&SOL_SOCKET.to_le_bytes());
+    bytes.extend_from_slice(&SCM_RIGHTS.to_le_bytes());
+    for fd in fds.iter() {
+        bytes.extend_from_slice(&fd.to_le_bytes());
+    }
+    write_user(token, control as *mut u8, &bytes);
+    (cmsg_align(cmsg_len).min(controllen), fds.len() < passed)
+}
+
+pub fn sys_sendmsg(fd: usize, msg: *const u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| {
+        let hdr = read_msghdr(token, msg)?;
+        let to = if hdr.name == 0 { None } else { Some(read_addr(token, hdr.name as *const u8, hdr.namelen)?) };
+        let rights = read_rights(token, hdr.control, hdr.controllen)?;
+        send_on(file.as_socket().unwrap(), token, &hdr.segs, rights, to.as_ref(), flags)
+    });
+    match result {
+        Ok(len) => len as isize,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_recvmsg(fd: usize, msg: *mut u8, flags: u32) -> isize {
+    let token = current_user_token();
+    let result = socket_file(fd).and_then(|file| {
+        let hdr = read_msghdr(token, msg)?;
+        let received = recv_on(file.as_socket().unwrap(), token, &hdr.segs, flags)?;
+        let mut msg_flags = 0u32;
+        if received.truncated {
+            msg_flags |= MSG_TRUNC;
+        }
+        let mut namelen = 0;
+        if hdr.name != 0 {
+            let bytes = received.from.to_bytes();
+            write_user(token, hdr.name as *mut u8, &bytes[..bytes.len().min(hdr.namelen)]);
+            namelen = bytes.len() as u32;
+        }
+        let cloexec = flags & MSG_CMSG_CLOEXEC != 0;
+        let (controllen, cut) = write_rights(token, hdr.control, hdr.controllen, received.rights, cloexec);
+        if cut {
+            msg_flags |= MSG_CTRUNC;
+        }
+        write_user(token, unsafe { msg.add(8) }, &namelen.to_le_bytes());
+        write_user(token, unsafe { msg.add(40) }, &controllen.to_le_bytes());
+        write_user(token, unsafe { msg.add(48) }, &msg_flags.to_le_bytes());
+        Ok(received.len)
+    });
+    match result {
+        Ok(len) => len as isize,
+        Err(errno) => -errno,
+    }
+}
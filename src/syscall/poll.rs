@@ -0,0 +1,256 @@
+//! I/O multiplexing syscalls
+//!
+//! The signal masks of `ppoll`, `pselect6` and `epoll_pwait` are ignored,
+//! and the timeout of `pselect6` is not updated with the time left.
+use super::errno::{EBADF, EINVAL};
+use crate::fs::{poll_wait, EpollEvent, EpollFile, File, PollEvents};
+use crate::mm::{translated_byte_buffer, translated_ref};
+use crate::task::{current_task, current_user_token};
+use crate::timer::TimeSpec;
+use alloc::sync::Arc;
+use alloc::vec;
+use alloc::vec::Vec;
+use core::mem::size_of;
+
+/// Copy `len` bytes from user space at `ptr`
+pub(super) fn read_user(token: usize, ptr: *const u8, len: usize) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(len);
+    for slice in translated_byte_buffer(token, ptr, len) {
+        bytes.extend_from_slice(slice);
+    }
+    bytes
+}
+
+/// Copy `bytes` to user space at `ptr`
+pub(super) fn write_user(token: usize, ptr: *mut u8, bytes: &[u8]) {
+    let mut copied = 0;
+    for slice in translated_byte_buffer(token, ptr, bytes.len()) {
+        slice.copy_from_slice(&bytes[copied..copied + slice.len()]);
+        copied += slice.len();
+    }
+}
+
+/// Timeout in milliseconds of a `struct timespec`, `None` if null
+fn timeout_ms(token: usize, timeout: *const TimeSpec) -> Option<usize> {
+    if timeout.is_null() {
+        None
+    } else {
+        Some(translated_ref(token, timeout).to_ms())
+    }
+}
+
+/// The open file of `fd`, or `None` if it is not open
+pub(super) fn fd_file(fd: usize) -> Option<Arc<dyn File + Send + Sync>> {
+    current_task().unwrap().inner_exclusive_access().fd_table.get(fd).ok()
+}
+
+/// `struct pollfd`
+#[repr(C)]
+#[derive(Copy, Clone)]
+struct PollFd {
+    fd: i32,
+    events: u16,
+    revents: u16,
+}
+
+pub fn sys_ppoll(fds: *mut u8, nfds: usize, timeout: *const TimeSpec, _sigmask: usize) -> isize {
+    let token = current_user_token();
+    let limit = current_task().unwrap().inner_exclusive_access().fd_table.limit().cur;
+    if nfds as u64 > limit {
+        return -EINVAL;
+    }
+    let bytes = read_user(token, fds, nfds * size_of::<PollFd>());
+    let mut poll_fds: Vec<PollFd> = bytes
+        .chunks_exact(size_of::<PollFd>())
+        .map(|chunk| PollFd {
+            fd: i32::from_le_bytes(chunk[0..4].try_into().unwrap()),
+            events: u16::from_le_bytes(chunk[4..6].try_into().unwrap()),
+            revents: 0,
+        })
+        .collect();
+    // a negative fd is skipped, a closed one reports `NVAL`
+    let files: Vec<Option<Arc<dyn File + Send + Sync>>> = poll_fds
+        .iter()
+        .map(|poll_fd| if poll_fd.fd < 0 { None } else { fd_file(poll_fd.fd as usize) })
+        .collect();
+    let waited: Vec<_> = files.iter().flatten().cloned().collect();
+    let outcome = poll_wait(&waited, timeout_ms(token, timeout), || {
+        let mut ready = 0;
+        for (poll_fd, file) in poll_fds.iter_mut().zip(files.iter()) {
+            let events = PollEvents::from_bits_truncate(poll_fd.events) | PollEvents::ERR | PollEvents::HUP;
+            poll_fd.revents = match file {
+                Some(file) => (file.poll() & events).bits(),
+                None if poll_fd.fd >= 0 => PollEvents::NVAL.bits(),
+                None => 0,
+            };
+            if poll_fd.revents != 0 {
+                ready += 1;
+            }
+        }
+        ready
+    });
+    let ready = match outcome {
+        Ok(ready) => ready,
+        Err(errno) => return -errno,
+    };
+    let mut bytes = Vec::with_capacity(nfds * size_of::<PollFd>());
+    for poll_fd in poll_fds.iter() {
+        bytes.extend_from_slice(&poll_fd.fd.to_le_bytes());
+        bytes.extend_from_slice(&poll_fd.events.to_le_bytes());
+        bytes.extend_from_slice(&poll_fd.revents.to_le_bytes());
+    }
+    write_user(token, fds, &bytes);
+    ready as isize
+}
+
+/// Bytes of an `fd_set` holding `nfds` fds, in whole `u64` words
+fn fd_set_len(nfds: usize) -> usize {
+    nfds.div_ceil(64) * 8
+}
+
+fn fd_isset(set: &[u8], fd: usize) -> bool {
+    set[fd / 8] & (1 << (fd % 8)) != 0
+}
+
+pub fn sys_pselect6(
+    nfds: usize,
+    readfds: *mut u8,
+    writefds: *mut u8,
+    exceptfds: *mut u8,
+    timeout: *const TimeSpec,
+    _sigmask: usize,
+) -> isize {
+    let token = current_user_token();
+    let limit = current_task().unwrap().inner_exclusive_access().fd_table.limit().cur;
+    if nfds as u64 > limit {
+        return -EINVAL;
+    }
+    let len = fd_set_len(nfds);
+    let read_set = |set: *mut u8| if set.is_null() { vec![0u8; len] } else { read_user(token, set, len) };
+    // the events making an fd ready in each set
+    let sets = [
+        (readfds, read_set(readfds), PollEvents::IN | PollEvents::HUP | PollEvents::ERR),
+        (writefds, read_set(writefds), PollEvents::OUT | PollEvents::ERR),
+        (exceptfds, read_set(exceptfds), PollEvents::PRI),
+    ];
+    let mut files: Vec<(usize, Arc<dyn File + Send + Sync>)> = Vec::new();
+    for fd in 0..nfds {
+        if sets.iter().any(|(_, set, _)| fd_isset(set, fd)) {
+            match fd_file(fd) {
+                Some(file) => files.push((fd, file)),
+                None => return -EBADF,
+            }
+        }
+    }
+    let waited: Vec<_> = files.iter().map(|(_, file)| file.clone()).collect();
+    let mut results = [vec![0u8; len], vec![0u8; len], vec![0u8; len]];
+    let outcome = poll_wait(&waited, timeout_ms(token, timeout), || {
+        let mut ready = 0;
+        for result in results.iter_mut() {
+            result.fill(0);
+        }
+        for (fd, file) in files.iter() {
+            let events = file.poll();
+            for ((_, set, wanted), result) in sets.iter().zip(results.iter_mut()) {
+                if fd_isset(set, *fd) && events.intersects(*wanted) {
+                    result[fd / 8] |= 1 << (fd % 8);
+                    ready += 1;
+                }
+            }
+        }
+        ready
+    });
+    let ready = match outcome {
+        Ok(ready) => ready,
+        Err(errno) => return -errno,
+    };
+    for ((ptr, _, _), result) in sets.iter().zip(results.iter()) {
+        if !ptr.is_null() {
+            write_user(token, *ptr, result);
+        }
+    }
+    ready as isize
+}
+
+/// `epoll_create1` flag closing the fd on `exec`
+const EPOLL_CLOEXEC: u32 = 0o2000000;
+
+pub fn sys_epoll_create1(flags: u32) -> isize {
+    if flags & !EPOLL_CLOEXEC != 0 {
+        return -EINVAL;
+    }
+    let epoll: Arc<dyn File + Send + Sync> = Arc::new(EpollFile::new());
+    let task = current_task().unwrap();
+    let result = task.inner_exclusive_access().fd_table.alloc(epoll, flags & EPOLL_CLOEXEC != 0);
+    match result {
+        Ok(fd) => fd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// `epoll_ctl` operations
+const EPOLL_CTL_ADD: usize = 1;
+const EPOLL_CTL_DEL: usize = 2;
+const EPOLL_CTL_MOD: usize = 3;
+
+pub fn sys_epoll_ctl(epfd: usize, op: usize, fd: usize, event: *const EpollEvent) -> isize {
+    let token = current_user_token();
+    let (Some(epoll_file), Some(file)) = (fd_file(epfd), fd_file(fd)) else {
+        return -EBADF;
+    };
+    let Some(epoll) = epoll_file.as_epoll() else {
+        return -EINVAL;
+    };
+    // also through another fd of the instance
+    if Arc::ptr_eq(&epoll_file, &file) {
+        return -EINVAL;
+    }
+    let read_event = || {
+        let bytes = read_user(token, event as *const u8, size_of::<EpollEvent>());
+        EpollEvent {
+            events: u32::from_le_bytes(bytes[0..4].try_into().unwrap()),
+            data: u64::from_le_bytes(bytes[8..16].try_into().unwrap()),
+        }
+    };
+    let result = match op {
+        EPOLL_CTL_ADD => epoll.add(fd, &file, read_event()),
+        EPOLL_CTL_MOD => epoll.modify(fd, read_event()),
+        EPOLL_CTL_DEL => epoll.delete(fd),
+        _ => Err(EINVAL),
+    };
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
+    }
+}
+
+pub fn sys_epoll_pwait(epfd: usize, events: *mut EpollEvent, maxevents: i32, timeout: i32, _sigmask: usize) -> isize {
+    let token = current_user_token();
+    if maxevents <= 0 {
+        return -EINVAL;
+    }
+    let Some(epoll_file) = fd_file(epfd) else {
+        return -EBADF;
+    };
+    let Some(epoll) = epoll_file.as_epoll() else {
+        return -EINVAL;
+    };
+    // a negative timeout waits forever
+    let timeout_ms = if timeout < 0 { None } else { Some(timeout as usize) };
+    let mut ready = Vec::new();
+    let outcome = poll_wait(&epoll.files(), timeout_ms, || {
+        ready = epoll.scan(maxevents as usize, true);
+        ready.len()
+    });
+    if let Err(errno) = outcome {
+        return -errno;
+    }
+    let mut bytes = Vec::with_capacity(ready.len() * size_of::<EpollEvent>());
+    for event in ready.iter() {
+        bytes.extend_from_slice(&event.events.to_le_bytes());
+        bytes.extend_from_slice(&[0u8; 4]);
+        bytes.extend_from_slice(&event.data.to_le_bytes());
+    }
+    write_user(token, events as *mut u8, &bytes);
+    ready.len() as isize
+}
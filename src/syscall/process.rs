@@ -3,12 +3,13 @@ use alloc::string::String;
 use alloc::sync::Arc;
 use alloc::vec::Vec;
 
-use crate::config::PAGE_SIZE;
-use crate::fs::{open_file, OpenFlags};
-use crate::task::{add_task, current_task, current_user_token, exit_current_and_run_next, pid2task, suspend_current_and_run_next, Mail, SignalAction, SignalFlags, UserTaskInfo, MAIL_MAXLEN, MAX_SIG};
-use crate::timer::get_time_ms;
+use crate::config::{PAGE_SIZE, RT_RR_TIME_SLICE};
+use crate::fs::{normalize, open_file, File, OpenFlags};
+use crate::task::{add_task, block_current_and_run_next, current_has_pending_signal, current_task, wakeup_task, current_user_token, exit_current_and_run_next, pid2task, suspend_current_and_run_next, MailFile, TASK_MANAGER, MIN_PRIORITY, MIN_NICE, MAX_NICE, SchedParam, SchedPolicy, TaskControlBlock, MAX_RT_PRIORITY, MIN_RT_PRIORITY, SignalAction, SignalFlags, UserTaskInfo, UserTaskInfoExt, MAX_SIG, RLimit};
+use super::errno::{EBADF, EFAULT, EINTR, EINVAL, EPERM, ESRCH};
+use crate::timer::{add_timer, remove_timer, cycles_to_ticks, get_time, get_time_ms, TimeSpec, TimeVal, MSEC_PER_TICK};
 use crate::{println, debug};
-use crate::mm::{translated_args_vec, translated_byte_buffer, translated_ref, translated_refmut, translated_str};
+use crate::mm::{translated_args_vec, translated_byte_buffer, translated_ref, translated_refmut, translated_str, user_range_valid};
 use core::mem::size_of;
 
 /// task exits and submit an exit code
@@ -24,30 +25,214 @@ pub fn sys_yield() -> isize {
     0
 }
 
+/// block the current task for the time in `req`. A signal ends the sleep
+/// early with `EINTR`, the time left is written to `rem` if not null.
+pub fn sys_nanosleep(req: *const TimeSpec, rem: *mut TimeSpec) -> isize {
+    let token = current_user_token();
+    if !user_range_valid(token, req as *const u8, size_of::<TimeSpec>(), false)
+        || (!rem.is_null() && !user_range_valid(token, rem as *const u8, size_of::<TimeSpec>(), true))
+    {
+        return -EFAULT;
+    }
+    let req = *translated_ref(token, req);
+    // negative fields are seen as huge unsigned ones
+    if (req.tv_sec as isize) < 0 || req.tv_nsec >= 1_000_000_000 {
+        return -EINVAL;
+    }
+    let expire_ms = get_time_ms() + req.to_ms();
+    let task = current_task().unwrap();
+    let mut left_ms = 0;
+    loop {
+        let now = get_time_ms();
+        if now >= expire_ms {
+            break;
+        }
+        if current_has_pending_signal() {
+            left_ms = expire_ms - now;
+            break;
+        }
+        add_timer(expire_ms, task.clone());
+        block_current_and_run_next();
+        remove_timer(&task);
+    }
+    if !rem.is_null() {
+        copy_to_user(token, rem, &TimeSpec::from_ms(left_ms));
+    }
+    if left_ms > 0 {
+        -EINTR
+    } else {
+        0
+    }
+}
+
 /// get time in milliseconds
 pub fn sys_get_time() -> isize {
     get_time_ms() as isize
 }
 
-// pub fn sys_task_info(id: usize, ts: *mut UserTaskInfo) -> isize {
-//     if id >= TASK_MANAGER.num_app() { -1 }
-//     else {
-//         let inner = TASK_MANAGER.inner.exclusive_access();
-//         let sz = size_of::<UserTaskInfo>();
-//         let src = inner.taskinfo[id].user();
-//         drop(inner);
-//         unsafe {
-//             let buf = translated_byte_buffer(current_user_token(), 
-//                 ts as usize as *const u8, sz);
-//             let mut srcptr = &src as *const UserTaskInfo as usize;
-//             for seg in buf {
-//                 core::ptr::copy(srcptr as *const u8, seg.as_mut_ptr(), seg.len());
-//                 srcptr += seg.len();
-//             }
-//         }
-//         0
-//     }
-// }
+/// Copy `value` to user space at `ptr`, which may cross a page boundary
+fn copy_to_user<T>(token: usize, ptr: *mut T, value: &T) {
+    let buf = translated_byte_buffer(token, ptr as *const u8, size_of::<T>());
+    let mut src = value as *const T as *const u8;
+    for seg in buf {
+        unsafe {
+            core::ptr::copy_nonoverlapping(src, seg.as_mut_ptr(), seg.len());
+            src = src.add(seg.len());
+        }
+    }
+}
+
+/// Write the syscall counters of task `pid` into `ts`. A `size` of 0
+/// asks for the original `UserTaskInfo`, the size of `UserTaskInfoExt`
+/// for the counters with the cpu accounting.
+pub fn sys_task_info(pid: usize, ts: *mut u8, size: usize) -> isize {
+    if size != 0 && size != size_of::<UserTaskInfoExt>() {
+        return -EINVAL;
+    }
+    if let Some(task) = pid2task(pid) {
+        let inner = task.inner_exclusive_access();
+        let token = current_user_token();
+        if size == 0 {
+            let info = inner.task_info.user(pid, inner.task_status);
+            drop(inner);
+            copy_to_user(token, ts as *mut UserTaskInfo, &info);
+        } else {
+            let info = inner.task_info.user_ext(pid, inner.task_status);
+            drop(inner);
+            copy_to_user(token, ts as *mut UserTaskInfoExt, &info);
+        }
+        0
+    } else {
+        -1
+    }
+}
+
+/// Process times in clock ticks
+#[repr(C)]
+pub struct Tms {
+    pub tms_utime: usize,
+    pub tms_stime: usize,
+    pub tms_cutime: usize,
+    pub tms_cstime: usize,
+}
+
+/// Fill `tms` with the times of the current task and its waited-for children,
+/// return the clock ticks since boot
+pub fn sys_times(tms: *mut Tms) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    inner.task_info.charge(false);
+    let info = &inner.task_info;
+    let times = Tms {
+        tms_utime: cycles_to_ticks(info.utime),
+        tms_stime: cycles_to_ticks(info.stime),
+        tms_cutime: cycles_to_ticks(info.cutime),
+        tms_cstime: cycles_to_ticks(info.cstime),
+    };
+    drop(inner);
+    if !tms.is_null() {
+        copy_to_user(current_user_token(), tms, &times);
+    }
+    cycles_to_ticks(get_time()) as isize
+}
+
+const RUSAGE_SELF: i32 = 0;
+const RUSAGE_CHILDREN: i32 = -1;
+
+/// Resource usage as in Linux `struct rusage`
+#[repr(C)]
+pub struct RUsage {
+    pub ru_utime: TimeVal,
+    pub ru_stime: TimeVal,
+    pub ru_maxrss: isize,
+    pub ru_ixrss: isize,
+    pub ru_idrss: isize,
+    pub ru_isrss: isize,
+    pub ru_minflt: isize,
+    pub ru_majflt: isize,
+    pub ru_nswap: isize,
+    pub ru_inblock: isize,
+    pub ru_oublock: isize,
+    pub ru_msgsnd: isize,
+    pub ru_msgrcv: isize,
+    pub ru_nsignals: isize,
+    pub ru_nvcsw: isize,
+    pub ru_nivcsw: isize,
+}
+
+pub fn sys_getrusage(who: i32, usage: *mut RUsage) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    inner.task_info.charge(false);
+    let info = &inner.task_info;
+    let (utime, stime, nvcsw, nivcsw) = match who {
+        RUSAGE_SELF => (info.utime, info.stime, info.nvcsw, info.nivcsw),
+        RUSAGE_CHILDREN => (info.cutime, info.cstime, 0, 0),
+        _ => return -1,
+    };
+    drop(inner);
+    let ru = RUsage {
+        ru_utime: TimeVal::from_cycles(utime),
+        ru_stime: TimeVal::from_cycles(stime),
+        ru_maxrss: 0,
+        ru_ixrss: 0,
+        ru_idrss: 0,
+        ru_isrss: 0,
+        ru_minflt: 0,
+        ru_majflt: 0,
+        ru_nswap: 0,
+        ru_inblock: 0,
+        ru_oublock: 0,
+        ru_msgsnd: 0,
+        ru_msgrcv: 0,
+        ru_nsignals: 0,
+        ru_nvcsw: nvcsw as isize,
+        ru_nivcsw: nivcsw as isize,
+    };
+    copy_to_user(current_user_token(), usage, &ru);
+    0
+}
+
+/// The resource of the file descriptor limit, the only one kept
+const RLIMIT_NOFILE: u32 = 7;
+
+/// Get the resource limit of task `pid` into `old_limit` and replace it by
+/// `new_limit`, either may be null
+pub fn sys_prlimit64(pid: usize, resource: u32, new_limit: *const RLimit, old_limit: *mut RLimit) -> isize {
+    if resource != RLIMIT_NOFILE {
+        return -EINVAL;
+    }
+    let Some(task) = sched_target(pid) else {
+        return -ESRCH;
+    };
+    let token = current_user_token();
+    let mut inner = task.inner_exclusive_access();
+    let old = inner.fd_table.limit();
+    if !new_limit.is_null() {
+        let new = *translated_ref(token, new_limit);
+        if new.cur > new.max {
+            return -EINVAL;
+        }
+        // nobody is privileged to raise the ceiling
+        if new.max > old.max {
+            return -EPERM;
+        }
+        inner.fd_table.set_limit(new);
+    }
+    drop(inner);
+    if !old_limit.is_null() {
+        *translated_refmut(token, old_limit) = old;
+    }
+    0
+}
+
+pub fn sys_getrlimit(resource: u32, limit: *mut RLimit) -> isize {
+    sys_prlimit64(0, resource, core::ptr::null(), limit)
+}
+
+pub fn sys_setrlimit(resource: u32, limit: *const RLimit) -> isize {
+    sys_prlimit64(0, resource, limit, core::ptr::null_mut())
+}
 
 pub fn sys_getpid() -> isize {
     current_task().unwrap().pid.0 as isize
@@ -70,12 +255,14 @@ pub fn sys_fork() -> isize {
 pub fn sys_exec(path: *const u8, args: *const usize) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
+    let path = normalize(&current_task().unwrap().inner_exclusive_access().cwd, &path);
     let args_vec: Vec<String> = translated_args_vec(token, args);
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+    if let Ok(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY, 0) {
         let all_data = app_inode.read_all();
         let task = current_task().unwrap();
         let argc = args_vec.len();
         task.exec(all_data.as_slice(), args_vec);
+        task.inner_exclusive_access().name = path;
         // return argc because cx.x[10] will be covered with it later
         argc as isize
     } else {
@@ -86,11 +273,13 @@ pub fn sys_exec(path: *const u8, args: *const usize) -> isize {
 pub fn sys_spawn(path: *const u8, args: *const usize) -> isize {
     let token = current_user_token();
     let path = translated_str(token, path);
+    let path = normalize(&current_task().unwrap().inner_exclusive_access().cwd, &path);
     let args_vec = translated_args_vec(token, args);
-    if let Some(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY) {
+    if let Ok(app_inode) = open_file(path.as_str(), OpenFlags::RDONLY, 0) {
         let data = &app_inode.read_all();
         let current_task = current_task().unwrap();
         let ntask = current_task.spawn(data, args_vec);
+        ntask.inner_exclusive_access().name = path;
         let pid = ntask.getpid();
         add_task(ntask);
         pid as isize
@@ -126,7 +315,10 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
         assert_eq!(Arc::strong_count(&child), 1);
         let found_pid = child.getpid();
         // ++++ temporarily access child TCB exclusively
-        let exit_code = child.inner_exclusive_access().exit_code;
+        let child_inner = child.inner_exclusive_access();
+        let exit_code = child_inner.exit_code;
+        inner.task_info.add_child(&child_inner.task_info);
+        drop(child_inner);
         // ++++ release child PCB
         *translated_refmut(inner.memory_set.token(), exit_code_ptr) = exit_code;
         found_pid as isize
@@ -136,14 +328,122 @@ pub fn sys_waitpid(pid: isize, exit_code_ptr: *mut i32) -> isize {
     // ---- release current PCB lock automatically
 }
 
-pub fn sys_set_priority(prio: u8) -> isize {
-    if prio < 2 {
+pub fn sys_set_priority(prio: isize) -> isize {
+    if prio < MIN_PRIORITY as isize {
         -1
     } else {
         let task = current_task().unwrap();
         let mut inner = task.inner_exclusive_access();
-        inner.priority = prio;
+        inner.priority = prio as u64;
+        drop(inner);
+        TASK_MANAGER.exclusive_access().on_priority_change(&task);
+        0
+    }
+}
+
+/// Add `inc` to the nice value of the current task, return the new nice value
+pub fn sys_nice(inc: i32) -> isize {
+    let task = current_task().unwrap();
+    let mut inner = task.inner_exclusive_access();
+    inner.nice = inner.nice.saturating_add(inc).clamp(MIN_NICE, MAX_NICE);
+    let nice = inner.nice;
+    drop(inner);
+    TASK_MANAGER.exclusive_access().on_priority_change(&task);
+    nice as isize
+}
+
+/// Task referred to by `pid`, 0 meaning the current task
+fn sched_target(pid: usize) -> Option<Arc<TaskControlBlock>> {
+    if pid == 0 {
+        current_task()
+    } else {
+        pid2task(pid)
+    }
+}
+
+pub fn sys_sched_setscheduler(pid: usize, policy: i32, param: *const SchedParam) -> isize {
+    let Some(policy) = SchedPolicy::from_raw(policy) else {
+        return -EINVAL;
+    };
+    if param.is_null() {
+        return -EINVAL;
+    }
+    let prio = translated_ref(current_user_token(), param).sched_priority;
+    if prio < 0 || !policy.valid_priority(prio as usize) {
+        return -EINVAL;
+    }
+    if let Some(task) = sched_target(pid) {
+        TASK_MANAGER.exclusive_access().set_scheduler(&task, policy, prio as usize);
+        0
+    } else {
+        -ESRCH
+    }
+}
+
+pub fn sys_sched_getscheduler(pid: usize) -> isize {
+    if let Some(task) = sched_target(pid) {
+        task.inner_exclusive_access().sched_policy as isize
+    } else {
+        -ESRCH
+    }
+}
+
+pub fn sys_sched_setparam(pid: usize, param: *const SchedParam) -> isize {
+    if let Some(task) = sched_target(pid) {
+        let policy = task.inner_exclusive_access().sched_policy;
+        sys_sched_setscheduler(task.getpid(), policy as i32, param)
+    } else {
+        -ESRCH
+    }
+}
+
+pub fn sys_sched_getparam(pid: usize, param: *mut SchedParam) -> isize {
+    if param.is_null() {
+        return -EINVAL;
+    }
+    if let Some(task) = sched_target(pid) {
+        let prio = task.inner_exclusive_access().rt_priority;
+        *translated_refmut(current_user_token(), param) = SchedParam {
+            sched_priority: prio as i32,
+        };
         0
+    } else {
+        -ESRCH
+    }
+}
+
+pub fn sys_sched_get_priority_max(policy: i32) -> isize {
+    match SchedPolicy::from_raw(policy) {
+        Some(policy) if policy.is_realtime() => MAX_RT_PRIORITY as isize,
+        Some(_) => 0,
+        None => -EINVAL,
+    }
+}
+
+pub fn sys_sched_get_priority_min(policy: i32) -> isize {
+    match SchedPolicy::from_raw(policy) {
+        Some(policy) if policy.is_realtime() => MIN_RT_PRIORITY as isize,
+        Some(_) => 0,
+        None => -EINVAL,
+    }
+}
+
+/// Write the `SCHED_RR` time slice of the task into `ts`, 0 for other policies
+pub fn sys_sched_rr_get_interval(pid: usize, ts: *mut TimeSpec) -> isize {
+    if ts.is_null() {
+        return -EFAULT;
+    }
+    if let Some(task) = sched_target(pid) {
+        let policy = task.inner_exclusive_access().sched_policy;
+        let ms = if policy == SchedPolicy::RoundRobin {
+            RT_RR_TIME_SLICE * MSEC_PER_TICK
+        } else {
+            0
+        };
+        *translated_refmut(current_user_token(), ts) = TimeSpec::from_ms(ms);
+        0
+    } else {
+        -ESRCH
     }
 }
 
@@ -156,6 +456,9 @@ pub fn sys_kill(pid: usize, signum: i32) -> isize {
                 return -1;
             }
             task_ref.signals.insert(flag);
+            drop(task_ref);
+            // a sleeping or polling task wakes to notice the signal
+            wakeup_task(task);
             0
         } else {
             debug!("sys_kill: from_bit failed");
@@ -234,53 +537,121 @@ pub fn sys_sigaction(
     }
 }
 
+/// Copy `content` to `buf` of `len` bytes, return the bytes copied
+fn copy_mail(token: usize, content: &[u8], buf: *mut u8, len: usize) -> usize {
+    let len = len.min(content.len());
+    let mut copied = 0;
+    for slice in translated_byte_buffer(token, buf, len) {
+        slice.copy_from_slice(&content[copied..copied + slice.len()]);
+        copied += slice.len();
+    }
+    copied
+}
+
+/// Take the oldest mail of the current process without waiting, -1 if
+/// there is none. A zero `len` only checks for one.
 pub fn sys_mailread(buf: *mut u8, len: usize) -> isize {
-    let task = current_task().unwrap();
-    debug!("{} readable: {}", task.getpid(), task.mailread_available());
+    let mailbox = current_task().unwrap().inner_exclusive_access().mailbox.clone();
     if len == 0 {
-        if task.mailread_available() > 0 { return 0; }
-        else { return -1; }
+        return if mailbox.has_mail(None) { 0 } else { -1 };
     }
-    let mail = task.mailread();
-    drop(task);
-    if let Some(mail) = mail {
-        let token = current_user_token();
-        let len = len.min(MAIL_MAXLEN);
-        let tr = translated_byte_buffer(token, buf, len);
-        let mut already_read = 0;
-        for b in tr {
-            let toread = mail.len.min(already_read + b.len()) - already_read;
-            b[..toread].copy_from_slice(&mail.content[already_read..already_read + toread]);
-            already_read += toread;
-            if already_read == mail.len { break; }
-        }
-        already_read as isize
-    } else {
-        -1
+    match mailbox.receive(None, true) {
+        Ok(mail) => copy_mail(current_user_token(), &mail.content, buf, len) as isize,
+        Err(_) => -1,
     }
 }
 
+/// Send a mail to process `pid` without waiting, -1 if its mailbox is
+/// full. A zero `len` only checks for room.
 pub fn sys_mailwrite(pid: usize, buf: *const u8, len: usize) -> isize {
-    let task = pid2task(pid);
-    if let Some(task) = task
-    {
-        debug!("{} writable: {}", pid, task.mailwrite_available());
-        if task.mailwrite_available() > 0 {
-            if len == 0 { return 0; }
-        } else { return -1; }
-        let mut mail = Mail::empty();
-        let token = current_user_token();
-        let len = len.min(MAIL_MAXLEN);
-        let tr = translated_byte_buffer(token, buf, len);
-        let mut already_write = 0;
-        for b in tr {
-            let towrite = b.len().min(len - already_write);
-            mail.content[already_write..already_write + towrite].copy_from_slice(b);
-            already_write += towrite;
-        }
-        mail.len = already_write;
-        task.mailwrite(&mail)
+    let Some(task) = pid2task(pid) else {
+        return -1;
+    };
+    let mailbox = task.inner_exclusive_access().mailbox.clone();
+    if len == 0 {
+        return if mailbox.has_room() { 0 } else { -1 };
+    }
+    let token = current_user_token();
+    let mut content = Vec::new();
+    for slice in translated_byte_buffer(token, buf, len.min(mailbox.max_len())) {
+        content.extend_from_slice(slice);
+    }
+    match mailbox.send(current_task().unwrap().getpid(), &content, true) {
+        Ok(len) => len as isize,
+        Err(_) => -1,
+    }
+}
+
+/// Open the mailbox of process `pid`, or of the current one if 0, as an
+/// fd with the access mode, `NONBLOCK` and `CLOEXEC` of `flags`. Only the
+/// own mailbox may be opened for reading.
+pub fn sys_mailopen(pid: usize, flags: u32) -> isize {
+    let flags = match OpenFlags::from_user(flags) {
+        Ok(flags) if (OpenFlags::WRONLY | OpenFlags::RDWR | OpenFlags::NONBLOCK | OpenFlags::CLOEXEC).contains(flags) => flags,
+        _ => return -EINVAL,
+    };
+    let task = current_task().unwrap();
+    let target = if pid == 0 || pid == task.getpid() {
+        task.clone()
     } else {
-        -1
+        match pid2task(pid) {
+            Some(target) => target,
+            None => return -ESRCH,
+        }
+    };
+    if flags.read_write().0 && !Arc::ptr_eq(&target, &task) {
+        return -EPERM;
+    }
+    let mailbox = target.inner_exclusive_access().mailbox.clone();
+    let file = MailFile::new(mailbox, flags);
+    let mut inner = task.inner_exclusive_access();
+    match inner.fd_table.alloc(file, flags.contains(OpenFlags::CLOEXEC)) {
+        Ok(fd) => fd as isize,
+        Err(errno) => -errno,
+    }
+}
+
+/// The mailbox open for reading on `fd`
+fn read_mailbox(fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+    let file = current_task().unwrap().inner_exclusive_access().fd_table.get(fd)?;
+    if file.as_mailbox().is_none() {
+        return Err(EINVAL);
+    }
+    if !file.readable() {
+        return Err(EBADF);
+    }
+    Ok(file)
+}
+
+/// Take the oldest mail from process `from`, or from anyone if 0, on the
+/// mailbox fd `fd`, waiting unless it is nonblocking. What does not fit in
+/// `buf` is dropped, the pid of the sender is written to `sender` if not
+/// null. `EPIPE` once the owner has exited and no matching mail is left.
+pub fn sys_mailrecv(fd: usize, buf: *mut u8, len: usize, from: usize, sender: *mut usize) -> isize {
+    let token = current_user_token();
+    let file = match read_mailbox(fd) {
+        Ok(file) => file,
+        Err(errno) => return -errno,
+    };
+    let mail_file = file.as_mailbox().unwrap();
+    let from = if from == 0 { None } else { Some(from) };
+    match mail_file.mailbox().receive(from, mail_file.nonblock()) {
+        Ok(mail) => {
+            if !sender.is_null() {
+                *translated_refmut(token, sender) = mail.sender;
+            }
+            copy_mail(token, &mail.content, buf, len) as isize
+        }
+        Err(errno) => -errno,
+    }
+}
+
+/// Make the mailbox on `fd` hold `capacity` mails of up to `max_len`
+/// bytes, `EBUSY` if the mails held do not fit
+pub fn sys_mailconfig(fd: usize, capacity: usize, max_len: usize) -> isize {
+    let result = read_mailbox(fd).and_then(|file| file.as_mailbox().unwrap().mailbox().configure(capacity, max_len));
+    match result {
+        Ok(()) => 0,
+        Err(errno) => -errno,
     }
 }
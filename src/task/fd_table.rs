@@ -0,0 +1,127 @@
+//! File descriptor table of a process
+//!
+//! An fd refers to an open file description, the `Arc<dyn File>` made by
+//! one `open` or `pipe`. The description holds the offset and the status
+//! flags, so they are shared by the fds `dup` makes and by the child after
+//! `fork`. The table entry only holds what belongs to the fd itself, the
+//! close-on-exec flag.
+use crate::config::MAX_FD_NUM;
+use crate::fs::File;
+use crate::syscall::errno::{EBADF, EMFILE};
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+
+/// Soft limit of `RLIMIT_NOFILE` a process starts with
+pub const DEFAULT_FD_LIMIT: usize = 1024;
+
+/// A resource limit as `struct rlimit`
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct RLimit {
+    /// soft limit, which is enforced
+    pub cur: u64,
+    /// ceiling of the soft limit
+    pub max: u64,
+}
+
+/// An open fd
+#[derive(Clone)]
+pub struct FdEntry {
+    pub file: Arc<dyn File + Send + Sync>,
+    /// close the fd on `exec`
+    pub cloexec: bool,
+}
+
+/// The fds of a process, indexed by fd
+#[derive(Clone)]
+pub struct FdTable {
+    entries: Vec<Option<FdEntry>>,
+    /// `RLIMIT_NOFILE`, fds are below `limit.cur`
+    limit: RLimit,
+}
+
+impl FdTable {
+    /// A table with `files` on fds 0, 1, 2...
+    pub fn new(files: Vec<Arc<dyn File + Send + Sync>>) -> Self {
+        Self {
+            entries: files.into_iter().map(|file| Some(FdEntry { file, cloexec: false })).collect(),
+            limit: RLimit {
+                cur: DEFAULT_FD_LIMIT as u64,
+                max: MAX_FD_NUM as u64,
+            },
+        }
+    }
+    /// Number of slots, open or not
+    pub fn slots(&self) -> usize {
+        self.entries.len()
+    }
+    /// The open file description of `fd`
+    pub fn get(&self, fd: usize) -> Result<Arc<dyn File + Send + Sync>, isize> {
+        self.entry(fd).map(|entry| entry.file.clone())
+    }
+    pub fn entry(&self, fd: usize) -> Result<&FdEntry, isize> {
+        self.entries.get(fd).and_then(|entry| entry.as_ref()).ok_or(EBADF)
+    }
+    pub fn entry_mut(&mut self, fd: usize) -> Result<&mut FdEntry, isize> {
+        self.entries.get_mut(fd).and_then(|entry| entry.as_mut()).ok_or(EBADF)
+    }
+    /// Put `file` on the lowest free fd, `EMFILE` if none is below the limit
+    pub fn alloc(&mut self, file: Arc<dyn File + Send + Sync>, cloexec: bool) -> Result<usize, isize> {
+        self.alloc_from(0, file, cloexec)
+    }
+    /// Put `file` on the lowest free fd not below `min`
+    pub fn alloc_from(&mut self, min: usize, file: Arc<dyn File + Send + Sync>, cloexec: bool) -> Result<usize, isize> {
+        let limit = self.limit.cur as usize;
+        let fd = (min..limit)
+            .find(|fd| !matches!(self.entries.get(*fd), Some(Some(_))))
+            .ok_or(EMFILE)?;
+        if self.entries.len() <= fd {
+            self.entries.resize_with(fd + 1, || None);
+        }
+        self.entries[fd] = Some(FdEntry { file, cloexec });
+        Ok(fd)
+    }
+    /// Put `file` on `fd`, return what was open there to be closed by the
+    /// caller, `EBADF` if `fd` is beyond the limit
+    pub fn install(
+        &mut self,
+        fd: usize,
+        file: Arc<dyn File + Send + Sync>,
+        cloexec: bool,
+    ) -> Result<Option<FdEntry>, isize> {
+        if fd as u64 >= self.limit.cur {
+            return Err(EBADF);
+        }
+        if self.entries.len() <= fd {
+            self.entries.resize_with(fd + 1, || None);
+        }
+        Ok(self.entries[fd].replace(FdEntry { file, cloexec }))
+    }
+    /// Remove `fd`, return its entry
+    pub fn close(&mut self, fd: usize) -> Result<FdEntry, isize> {
+        self.entries.get_mut(fd).and_then(|entry| entry.take()).ok_or(EBADF)
+    }
+    /// Remove the fds with close-on-exec
+    pub fn close_on_exec(&mut self) {
+        for entry in self.entries.iter_mut() {
+            if entry.as_ref().is_some_and(|entry| entry.cloexec) {
+                *entry = None;
+            }
+        }
+    }
+    /// Remove all fds, when the process exits
+    pub fn close_all(&mut self) {
+        self.entries.clear();
+    }
+    /// Open fds with their entries
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &FdEntry)> {
+        self.entries.iter().enumerate().filter_map(|(fd, entry)| entry.as_ref().map(|entry| (fd, entry)))
+    }
+    pub fn limit(&self) -> RLimit {
+        self.limit
+    }
+    /// Replace `RLIMIT_NOFILE`, fds open beyond the new limit stay open
+    pub fn set_limit(&mut self, limit: RLimit) {
+        self.limit = limit;
+    }
+}
@@ -1,77 +1,312 @@
-use core::iter::Empty;
+//! Mailboxes of processes
+//!
+//! Every process has a [`MailBox`] that any process can send mails to by
+//! pid. A mailbox can also be opened as a [`MailFile`], which is closed,
+//! inherited and polled like any other fd, so the children holding the
+//! fd read the same mailbox as its owner. Once the owner exits, the mails
+//! held can still be read but sending fails with `EPIPE`, and so does
+//! receiving once they are gone, which a `MailFile` reads as end of file.
+use super::{suspend_current_and_run_next, TaskControlBlock};
+use crate::config::PAGE_SIZE;
+use crate::fs::{File, OpenFlags, PollEvents, Stat, StatMode};
+use crate::mm::UserBuffer;
+use crate::sync::{UPSafeCell, WaitQueue};
+use crate::syscall::errno::{EAGAIN, EBUSY, EINVAL, EPIPE};
+use alloc::collections::VecDeque;
+use alloc::string::String;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
 
+/// Mails a new mailbox holds
 pub const MAIL_BUFFER_SIZE: usize = 16;
+/// Longest mail of a new mailbox
 pub const MAIL_MAXLEN: usize = 256;
+/// Most mails a mailbox may be configured to hold
+pub const MAIL_MAX_CAPACITY: usize = 1024;
+/// Longest mail a mailbox may be configured to take
+pub const MAIL_MAX_SIZE: usize = PAGE_SIZE;
 
-#[derive(Clone, Copy)]
-pub struct Mail{
-    pub content: [u8; MAIL_MAXLEN],
-    pub len: usize,
+pub struct Mail {
+    /// pid of the sending process
+    pub sender: usize,
+    pub content: Vec<u8>,
 }
 
-impl Mail {
-    pub fn empty() -> Self {
-        Self { content: [0; MAIL_MAXLEN], len: 0 }
+struct MailBoxInner {
+    mails: VecDeque<Mail>,
+    capacity: usize,
+    max_len: usize,
+    /// cleared when the owner exits
+    owner_alive: bool,
+    /// tasks polling for mail or for room
+    waiters: WaitQueue,
+}
+
+pub struct MailBox {
+    inner: UPSafeCell<MailBoxInner>,
+}
+
+impl MailBox {
+    /// An empty mailbox of `capacity` mails up to `max_len` bytes
+    pub fn new(capacity: usize, max_len: usize) -> Self {
+        Self {
+            inner: unsafe {
+                UPSafeCell::new(MailBoxInner {
+                    mails: VecDeque::new(),
+                    capacity,
+                    max_len,
+                    owner_alive: true,
+                    waiters: WaitQueue::new(),
+                })
+            },
+        }
+    }
+    /// An empty mailbox configured as this one
+    pub fn empty_copy(&self) -> Self {
+        let inner = self.inner.exclusive_access();
+        Self::new(inner.capacity, inner.max_len)
+    }
+    pub fn capacity(&self) -> usize {
+        self.inner.exclusive_access().capacity
+    }
+    pub fn max_len(&self) -> usize {
+        self.inner.exclusive_access().max_len
+    }
+    /// Change the number and the length of mails held, `EBUSY` if the
+    /// mails already held do not fit
+    pub fn configure(&self, capacity: usize, max_len: usize) -> Result<(), isize> {
+        if !(1..=MAIL_MAX_CAPACITY).contains(&capacity) || !(1..=MAIL_MAX_SIZE).contains(&max_len) {
+            return Err(EINVAL);
+        }
+        let mut inner = self.inner.exclusive_access();
+        if inner.mails.len() > capacity || inner.mails.iter().any(|mail| mail.content.len() > max_len) {
+            return Err(EBUSY);
+        }
+        inner.capacity = capacity;
+        inner.max_len = max_len;
+        // there may be room now
+        inner.waiters.wake_all();
+        Ok(())
+    }
+    /// Mark the owner as exited, waking the senders waiting for room
+    pub fn close(&self) {
+        let mut inner = self.inner.exclusive_access();
+        inner.owner_alive = false;
+        inner.waiters.wake_all();
+    }
+    pub fn owner_alive(&self) -> bool {
+        self.inner.exclusive_access().owner_alive
+    }
+    /// Whether a mail from `from`, or from anyone if `None`, is held
+    pub fn has_mail(&self, from: Option<usize>) -> bool {
+        let inner = self.inner.exclusive_access();
+        inner.mails.iter().any(|mail| from.map_or(true, |pid| mail.sender == pid))
+    }
+    pub fn has_room(&self) -> bool {
+        let inner = self.inner.exclusive_access();
+        inner.mails.len() < inner.capacity
+    }
+    /// Queue a mail of `content` cut to the longest mail, waiting for room
+    /// unless `nonblock`, return the bytes queued, `EPIPE` once the owner
+    /// has exited
+    pub fn send(&self, sender: usize, content: &[u8], nonblock: bool) -> Result<usize, isize> {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            if !inner.owner_alive {
+                return Err(EPIPE);
+            }
+            if inner.mails.len() < inner.capacity {
+                let len = content.len().min(inner.max_len);
+                inner.mails.push_back(Mail {
+                    sender,
+                    content: Vec::from(&content[..len]),
+                });
+                inner.waiters.wake_all();
+                return Ok(len);
+            }
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            drop(inner);
+            suspend_current_and_run_next();
+        }
+    }
+    /// Take the oldest mail from `from`, or from anyone if `None`, waiting
+    /// for one unless `nonblock`, `EPIPE` if none is held and the owner
+    /// has exited, since no more can arrive
+    pub fn receive(&self, from: Option<usize>, nonblock: bool) -> Result<Mail, isize> {
+        loop {
+            let mut inner = self.inner.exclusive_access();
+            let found = inner.mails.iter().position(|mail| from.map_or(true, |pid| mail.sender == pid));
+            if let Some(index) = found {
+                let mail = inner.mails.remove(index).unwrap();
+                // room is made for the senders
+                inner.waiters.wake_all();
+                return Ok(mail);
+            }
+            if !inner.owner_alive {
+                return Err(EPIPE);
+            }
+            if nonblock {
+                return Err(EAGAIN);
+            }
+            drop(inner);
+            suspend_current_and_run_next();
+        }
     }
 }
 
-#[derive(Debug, Copy, Clone, PartialEq)]
-pub enum RingBufferStatus {
-    Full,
-    Empty,
-    Normal,
+/// A mailbox open as a file: reading takes mails, writing sends them
+/// with the pid of the writer
+pub struct MailFile {
+    mailbox: Arc<MailBox>,
+    readable: bool,
+    writable: bool,
+    /// `NONBLOCK` if set
+    status: UPSafeCell<OpenFlags>,
 }
 
-#[derive(Clone)]
-pub struct MailRingBuffer {
-    mails: [Mail; MAIL_BUFFER_SIZE],
-    head: usize,
-    tail: usize,
-    status: RingBufferStatus,
+impl MailFile {
+    /// Open `mailbox` with the access mode and `NONBLOCK` of `flags`
+    pub fn new(mailbox: Arc<MailBox>, flags: OpenFlags) -> Arc<Self> {
+        let (readable, writable) = flags.read_write();
+        Arc::new(Self {
+            mailbox,
+            readable,
+            writable,
+            status: unsafe { UPSafeCell::new(flags & OpenFlags::NONBLOCK) },
+        })
+    }
+    pub fn mailbox(&self) -> &Arc<MailBox> {
+        &self.mailbox
+    }
+    pub fn nonblock(&self) -> bool {
+        self.status.exclusive_access().contains(OpenFlags::NONBLOCK)
+    }
 }
 
-impl MailRingBuffer {
-    pub fn new() -> Self {
-        Self {
-            mails: core::array::from_fn(|_| Mail::empty()),
-            head: 0,
-            tail: 0,
-            status: RingBufferStatus::Empty,
-        }
+/// pid of the current process
+fn current_pid() -> usize {
+    super::current_task().unwrap().getpid()
+}
+
+impl File for MailFile {
+    fn readable(&self) -> bool {
+        self.readable
+    }
+    fn writable(&self) -> bool {
+        self.writable
     }
-    pub fn available_read(&self) -> usize {
-        if self.status == RingBufferStatus::Empty {
-            0
-        } else if self.tail > self.head {
-            self.tail - self.head
-        } else {
-            self.tail + MAIL_BUFFER_SIZE - self.head
+    /// Take the oldest mail, what does not fit in `buf` is dropped. Nothing
+    /// is read at the end of file, when the owner has exited and no mail
+    /// is left.
+    fn read(&self, buf: UserBuffer) -> usize {
+        let Ok(mail) = self.mailbox.receive(None, self.nonblock()) else {
+            return 0;
+        };
+        let mut copied = 0;
+        for slice in buf.buffers {
+            let len = slice.len().min(mail.content.len() - copied);
+            slice[..len].copy_from_slice(&mail.content[copied..copied + len]);
+            copied += len;
         }
+        copied
     }
-    pub fn available_write(&self) -> usize {
-        if self.status == RingBufferStatus::Full {
-            0
-        } else {
-            MAIL_BUFFER_SIZE - self.available_read()
+    /// Send `buf` as one mail, cut to the longest mail
+    fn write(&self, buf: UserBuffer) -> usize {
+        let mut content = Vec::with_capacity(buf.len());
+        for slice in buf.buffers.iter() {
+            content.extend_from_slice(slice);
         }
+        self.mailbox.send(current_pid(), &content, self.nonblock()).unwrap_or(0)
+    }
+    fn stat(&self) -> Stat {
+        let mut st = Stat::empty();
+        st.mode = StatMode::new(StatMode::NULL, 0o600);
+        st.nlink = 1;
+        st
+    }
+    fn path(&self) -> Option<String> {
+        None
     }
-    pub fn read(&mut self) -> Mail {
-        self.status = RingBufferStatus::Normal;
-        let m = self.mails[self.head];
-        self.head = (self.head + 1) % MAIL_BUFFER_SIZE;
-        if self.head == self.tail {
-            self.status = RingBufferStatus::Empty;
+    fn seekable(&self) -> bool {
+        false
+    }
+    fn status_flags(&self) -> OpenFlags {
+        *self.status.exclusive_access()
+    }
+    fn set_status_flags(&self, flags: OpenFlags) {
+        *self.status.exclusive_access() = flags & OpenFlags::NONBLOCK;
+    }
+    fn read_ready(&self) -> bool {
+        self.mailbox.has_mail(None) || !self.mailbox.owner_alive()
+    }
+    fn write_ready(&self) -> bool {
+        self.mailbox.has_room() || !self.mailbox.owner_alive()
+    }
+    fn poll(&self) -> PollEvents {
+        let mut events = PollEvents::empty();
+        if self.readable {
+            if self.mailbox.has_mail(None) {
+                events |= PollEvents::IN;
+            }
+            if !self.mailbox.owner_alive() {
+                events |= PollEvents::HUP;
+            }
         }
-        m
-    }
-    pub fn write(&mut self, mail: &Mail){
-        self.status = RingBufferStatus::Normal;
-        self.mails[self.tail] = *mail;
-        self.tail = (self.tail + 1) % MAIL_BUFFER_SIZE;
-        if self.tail == self.head {
-            self.status = RingBufferStatus::Full;
+        if self.writable {
+            if self.mailbox.has_room() {
+                events |= PollEvents::OUT;
+            }
+            if !self.mailbox.owner_alive() {
+                events |= PollEvents::ERR;
+            }
         }
+        events
+    }
+    fn add_waiter(&self, task: &Arc<TaskControlBlock>) -> bool {
+        self.mailbox.inner.exclusive_access().waiters.add(task);
+        true
+    }
+    fn remove_waiter(&self, task: &Arc<TaskControlBlock>) {
+        self.mailbox.inner.exclusive_access().waiters.remove(task);
+    }
+    fn as_mailbox(&self) -> Option<&MailFile> {
+        Some(self)
     }
 }
 
-pub type MailBox = MailRingBuffer;
\ No newline at end of file
+#[allow(unused)]
+/// Check selective receive, the capacity and a reconfiguration, all
+/// without waiting
+pub fn mailbox_test() {
+    use crate::println;
+    let mailbox = MailBox::new(2, 4);
+    assert_eq!(mailbox.receive(None, true).err(), Some(EAGAIN));
+    assert_eq!(mailbox.send(1, b"first", true), Ok(4));
+    assert_eq!(mailbox.send(2, b"two", true), Ok(3));
+    assert_eq!(mailbox.send(3, b"x", true).err(), Some(EAGAIN));
+    assert!(!mailbox.has_mail(Some(3)));
+    // a mail from 2 is taken past the older one from 1
+    let mail = mailbox.receive(Some(2), true).unwrap();
+    assert_eq!((mail.sender, mail.content.as_slice()), (2, &b"two"[..]));
+    assert_eq!(mailbox.configure(4, 2).err(), Some(EBUSY));
+    assert_eq!(mailbox.configure(0, 8).err(), Some(EINVAL));
+    mailbox.configure(4, 8).unwrap();
+    assert_eq!(mailbox.send(3, b"three", true), Ok(5));
+    let mail = mailbox.receive(None, true).unwrap();
+    assert_eq!((mail.sender, mail.content.as_slice()), (1, &b"firs"[..]));
+    let copy = mailbox.empty_copy();
+    assert_eq!((copy.capacity(), copy.max_len(), copy.has_mail(None)), (4, 8, false));
+    // the held mail outlives the owner, new ones are refused
+    mailbox.close();
+    assert_eq!(mailbox.send(1, b"late", false).err(), Some(EPIPE));
+    assert!(mailbox.receive(Some(3), true).is_ok());
+    // and once it is taken a receive ends instead of waiting
+    assert_eq!(mailbox.receive(None, false).err(), Some(EPIPE));
+    let file = MailFile::new(Arc::new(copy), OpenFlags::RDONLY);
+    file.mailbox().close();
+    assert!(file.read_ready());
+    assert_eq!(file.poll(), PollEvents::HUP);
+    println!("mailbox_test passed!");
+}
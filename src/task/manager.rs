@@ -1,38 +1,96 @@
 //!Implementation of [`TaskManager`]
+use super::scheduler::{RtScheduler, SchedPolicy, Scheduler, SchedulerImpl};
 use super::TaskControlBlock;
+use crate::config::{RT_PERIOD, RT_RUNTIME};
 use crate::sync::UPSafeCell;
 use alloc::collections::btree_map::BTreeMap;
-use alloc::collections::VecDeque;
-use alloc::collections::BinaryHeap;
 use alloc::sync::Arc;
+use alloc::vec::Vec;
 use lazy_static::*;
-///A array of `TaskControlBlock` that is thread-safe
+///A ready queue of `TaskControlBlock` that is thread-safe
 pub struct TaskManager {
-    ready_queue: BinaryHeap<Arc<TaskControlBlock>>,
+    /// real-time tasks, always run before normal ones
+    rt: RtScheduler,
+    /// normal tasks
+    scheduler: SchedulerImpl,
+    /// ticks elapsed in the current throttling period
+    rt_period_ticks: usize,
+    /// ticks used by real-time tasks in the current throttling period
+    rt_used_ticks: usize,
 }
 
-/// A simple FIFO scheduler.
+/// Real-time tasks are picked first, normal tasks are scheduled by the policy
+/// chosen by [`SchedulerImpl`].
+///
+/// Real-time tasks may use at most `RT_RUNTIME` out of every `RT_PERIOD` ticks
+/// while normal tasks are waiting, so that a runaway real-time task cannot
+/// starve `initproc` and the shell forever.
 impl TaskManager {
     ///Creat an empty TaskManager
     pub fn new() -> Self {
         Self {
-            ready_queue: BinaryHeap::new(),
+            rt: RtScheduler::new(),
+            scheduler: SchedulerImpl::new(),
+            rt_period_ticks: 0,
+            rt_used_ticks: 0,
         }
     }
+    fn rt_throttled(&self) -> bool {
+        self.rt_used_ticks >= RT_RUNTIME
+    }
     ///Add a task to `TaskManager`
     pub fn add(&mut self, task: Arc<TaskControlBlock>) {
-        self.ready_queue.push(task);
+        let policy = task.inner_exclusive_access().sched_policy;
+        if policy.is_realtime() {
+            self.rt.add(task);
+        } else {
+            self.scheduler.add(task);
+        }
     }
-    ///Remove the first task and return it,or `None` if `TaskManager` is empty
+    ///Remove the next task to run and return it,or `None` if `TaskManager` is empty
     pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
-        let top = self.ready_queue.pop();
-        if let Some(task) = top {
-            let mut inner = task.inner_exclusive_access();
-            inner.stride += (256u16 / (inner.priority as u16)) as u8;
-            drop(inner);
-            Some(task)
-        }else {
-            None
+        if self.rt_throttled() {
+            self.scheduler.fetch().or_else(|| self.rt.fetch())
+        } else {
+            self.rt.fetch().or_else(|| self.scheduler.fetch())
+        }
+    }
+    ///Account a timer tick to the running task, return whether it should be preempted
+    pub fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.rt_period_ticks += 1;
+        if self.rt_period_ticks >= RT_PERIOD {
+            self.rt_period_ticks = 0;
+            self.rt_used_ticks = 0;
+        }
+        let policy = task.inner_exclusive_access().sched_policy;
+        if policy.is_realtime() {
+            self.rt_used_ticks += 1;
+            self.rt_throttled() || self.rt.tick(task)
+        } else if !self.rt_throttled() && self.rt.has_ready() {
+            true
+        } else {
+            self.scheduler.tick(task)
+        }
+    }
+    ///Remove a task from the ready queue, return whether it was queued
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.rt.remove(task) || self.scheduler.remove(task)
+    }
+    ///Notify the scheduler that the priority of a task has been changed
+    pub fn on_priority_change(&mut self, task: &Arc<TaskControlBlock>) {
+        self.scheduler.on_priority_change(task);
+    }
+    ///Change the scheduling policy and real-time priority of a task,
+    ///moving it to the matching run queue
+    pub fn set_scheduler(&mut self, task: &Arc<TaskControlBlock>, policy: SchedPolicy, rt_priority: usize) {
+        let queued = self.remove(task);
+        let mut inner = task.inner_exclusive_access();
+        inner.sched_policy = policy;
+        inner.rt_priority = rt_priority;
+        inner.sched_ticks = 0;
+        drop(inner);
+        if queued {
+            self.add(task.clone());
         }
     }
 }
@@ -58,10 +116,14 @@ pub fn pid2task(pid: usize) -> Option<Arc<TaskControlBlock>> {
     map.get(&pid).map(Arc::clone)
 }
 
+///All tasks which have not exited, ordered by pid
+pub fn all_tasks() -> Vec<Arc<TaskControlBlock>> {
+    PID2TCB.exclusive_access().values().cloned().collect()
+}
+
 pub fn remove_from_pid2task(pid: usize) {
     let mut map = PID2TCB.exclusive_access();
     if map.remove(&pid).is_none() {
         panic!("cannot find pid {} in pid2task!", pid);
     }
 }
-
@@ -17,31 +17,45 @@ mod task;
 
 mod pid;
 mod manager;
+mod scheduler;
 mod processor;
 mod signal;
 mod action;
 mod mail;
+mod fd_table;
 
 use crate::{fs::{open_file, OpenFlags}, mm::MapPermission};
+use alloc::string::String;
 use alloc::sync::Arc;
 use lazy_static::*;
 use switch::__switch;
-use task::TaskControlBlock;
 use crate::{println, debug};
 
 pub use context::TaskContext;
-pub use task::{TaskInfo, SyscallInfo, UserTaskInfo, TaskStatus};
+pub use task::{TaskControlBlock, TaskInfo, SyscallInfo, UserTaskInfo, UserTaskInfoExt, TaskStatus};
 pub use pid::{pid_alloc, KernelStack, PidAllocator, PidHandle};
-pub use manager::{fetch_task, TaskManager, add_task, pid2task, remove_from_pid2task};
+pub use manager::{fetch_task, TaskManager, add_task, all_tasks, pid2task, remove_from_pid2task, TASK_MANAGER};
+pub use scheduler::{SchedParam, SchedPolicy, MAX_NICE, MAX_RT_PRIORITY, MIN_NICE, MIN_PRIORITY, MIN_RT_PRIORITY};
 pub use processor::{
     current_task, current_trap_cx, current_user_token, run_tasks, schedule, take_current_task,
-    Processor,
+    cpu_time_stats, Processor,
 };
 pub use signal::{MAX_SIG, SignalFlags};
 pub use action::{SignalAction, SignalActions};
-pub use mail::{Mail, MailBox, MAIL_BUFFER_SIZE, MAIL_MAXLEN};
+pub use mail::{MailBox, MailFile};
+pub use fd_table::{FdEntry, FdTable, RLimit};
 
 pub fn suspend_current_and_run_next() {
+    switch_out_current(false);
+}
+
+/// Preempt the current 'Running' task and run the next task in task list,
+/// counted as an involuntary context switch.
+pub fn preempt_current_and_run_next() {
+    switch_out_current(true);
+}
+
+fn switch_out_current(preempted: bool) {
     // There must be an application running.
     let task = take_current_task().unwrap();
 
@@ -50,6 +64,12 @@ pub fn suspend_current_and_run_next() {
     let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
     // Change status to Ready
     task_inner.task_status = TaskStatus::Ready;
+    task_inner.task_info.charge(false);
+    if preempted {
+        task_inner.task_info.nivcsw += 1;
+    } else {
+        task_inner.task_info.nvcsw += 1;
+    }
     drop(task_inner);
     // ---- release current PCB
 
@@ -59,6 +79,51 @@ pub fn suspend_current_and_run_next() {
     schedule(task_cx_ptr);
 }
 
+/// Block the current 'Running' task until [`wakeup_task`] is called on it,
+/// and run the next task in task list.
+pub fn block_current_and_run_next() {
+    let task = take_current_task().unwrap();
+    let mut task_inner = task.inner_exclusive_access();
+    let task_cx_ptr = &mut task_inner.task_cx as *mut TaskContext;
+    task_inner.task_status = TaskStatus::Blocked;
+    task_inner.task_info.charge(false);
+    task_inner.task_info.nvcsw += 1;
+    drop(task_inner);
+    schedule(task_cx_ptr);
+}
+
+/// Make a blocked task ready again
+pub fn wakeup_task(task: Arc<TaskControlBlock>) {
+    let mut task_inner = task.inner_exclusive_access();
+    // a task waiting for several events is woken by the first one only
+    if task_inner.task_status != TaskStatus::Blocked {
+        return;
+    }
+    task_inner.task_status = TaskStatus::Ready;
+    drop(task_inner);
+    add_task(task);
+}
+
+/// Charge the time since the last accounting of the current task
+/// to its user time or system time
+pub fn account_current(user: bool) {
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().task_info.charge(user);
+}
+
+/// Count an invocation of `syscall_id` by the current task
+pub fn count_current_syscall(syscall_id: usize) {
+    let task = current_task().unwrap();
+    task.inner_exclusive_access().task_info.count_syscall(syscall_id);
+}
+
+/// Account a timer tick to the current task,
+/// return whether the scheduler wants to preempt it
+pub fn scheduler_tick() -> bool {
+    let task = current_task().unwrap();
+    TASK_MANAGER.exclusive_access().tick(&task)
+}
+
 // pid of usertests app in make run TEST=1
 pub const IDLE_PID: usize = 0;
 
@@ -84,12 +149,16 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     }
 
     remove_from_pid2task(task.getpid());
+    // senders to the mailbox stop waiting for room
+    let mailbox = task.inner_exclusive_access().mailbox.clone();
+    mailbox.close();
     // **** access current TCB exclusively
     let mut inner = task.inner_exclusive_access();
     // Change status to Zombie
     inner.task_status = TaskStatus::Zombie;
     // Record exit code
     inner.exit_code = exit_code;
+    inner.task_info.charge(false);
     // do not move to its parent but under initproc
 
     // ++++++ access initproc TCB exclusively
@@ -105,6 +174,8 @@ pub fn exit_current_and_run_next(exit_code: i32) {
     inner.children.clear();
     // deallocate user space
     inner.memory_set.recycle_data_pages();
+    // close the fds now, the parent may wait for the end of a pipe
+    inner.fd_table.close_all();
     drop(inner);
     // **** release current PCB
     // drop task manually to maintain rc correctly
@@ -140,9 +211,11 @@ pub fn user_unmap_area(start_va: crate::mm::VirtAddr) -> isize {
 lazy_static! {
     ///Globle process that init user shell
     pub static ref INITPROC: Arc<TaskControlBlock> = Arc::new({
-        let inode = open_file("initproc", OpenFlags::RDONLY).unwrap();
+        let inode = open_file("/initproc", OpenFlags::RDONLY, 0).unwrap();
         let v = inode.read_all();
-        TaskControlBlock::new(v.as_slice())
+        let tcb = TaskControlBlock::new(v.as_slice());
+        tcb.inner_exclusive_access().name = String::from("initproc");
+        tcb
     });
 }
 ///Add init process to the manager
@@ -157,7 +230,28 @@ pub fn check_signals_error_of_current() -> Option<(i32, &'static str)> {
     //     "[K] check_signals_error_of_current {:?}",
     //     task_inner.signals
     // );
-    task_inner.signals.check_error()
+    let mut signals = task_inner.signals;
+    // SIGPIPE only kills by its default action, masked or caught it stays
+    // pending and the write just fails with EPIPE
+    let sigpipe = SignalFlags::SIGPIPE;
+    let handling_mask = match task_inner.handling_sig {
+        -1 => SignalFlags::empty(),
+        sig => task_inner.signal_actions.table[sig as usize].mask,
+    };
+    let handler = task_inner.signal_actions.table[sigpipe.bits().trailing_zeros() as usize].handler;
+    if task_inner.signal_mask.contains(sigpipe) || handling_mask.contains(sigpipe) || handler != 0 {
+        signals.remove(sigpipe);
+    }
+    signals.check_error()
+}
+
+/// Whether the current task has a signal pending which is not masked,
+/// one to interrupt a wait for
+pub fn current_has_pending_signal() -> bool {
+    let task = current_task().unwrap();
+    let task_inner = task.inner_exclusive_access();
+    let pending = task_inner.signals - task_inner.signal_mask;
+    !pending.is_empty() || task_inner.signals.contains(SignalFlags::SIGKILL)
 }
 
 pub fn current_add_signal(signal: SignalFlags) {
@@ -3,8 +3,10 @@ use super::__switch;
 use super::{fetch_task, TaskStatus};
 use super::{TaskContext, TaskControlBlock};
 use crate::sync::UPSafeCell;
+use crate::timer::{check_timer, get_time, set_next_deadline, set_next_trigger};
 use crate::trap::TrapContext;
 use alloc::sync::Arc;
+use core::arch::asm;
 use lazy_static::*;
 ///Processor management structure
 pub struct Processor {
@@ -12,6 +14,10 @@ pub struct Processor {
     current: Option<Arc<TaskControlBlock>>,
     ///The basic control flow of each core, helping to select and switch process
     idle_task_cx: TaskContext,
+    ///Timer cycles this core spent running tasks
+    busy_time: usize,
+    ///Timer cycles this core spent waiting for interrupts with nothing to run
+    idle_time: usize,
 }
 
 impl Processor {
@@ -20,6 +26,8 @@ impl Processor {
         Self {
             current: None,
             idle_task_cx: TaskContext::zero_init(),
+            busy_time: 0,
+            idle_time: 0,
         }
     }
     ///Get mutable reference to `idle_task_cx`
@@ -50,17 +58,46 @@ pub fn run_tasks() {
             let mut task_inner = task.inner_exclusive_access();
             let next_task_cx_ptr = &task_inner.task_cx as *const TaskContext;
             task_inner.task_status = TaskStatus::Running;
+            task_inner.task_info.restart();
             drop(task_inner);
             // release coming task TCB manually
             processor.current = Some(task);
             // release processor manually
             drop(processor);
+            let start = get_time();
             unsafe {
                 __switch(idle_task_cx_ptr, next_task_cx_ptr);
             }
+            PROCESSOR.exclusive_access().busy_time += get_time() - start;
+        } else {
+            drop(processor);
+            idle();
         }
     }
 }
+///Wait for an interrupt when there is nothing to run
+///
+///The timer is programmed for the next expiring timer only, so an idle core
+///is not woken up by the periodic tick. `wfi` resumes as soon as an interrupt
+///enabled in `sie` is pending, even with `sstatus.SIE` cleared, so the kernel
+///trap entry never runs: the pending timer interrupt is consumed by
+///reprogramming the timer.
+fn idle() {
+    let start = get_time();
+    set_next_deadline();
+    unsafe {
+        asm!("wfi");
+    }
+    check_timer();
+    // some task may be ready now, go back to the periodic tick
+    set_next_trigger();
+    PROCESSOR.exclusive_access().idle_time += get_time() - start;
+}
+///Return (busy, idle) time of the processor in timer cycles
+pub fn cpu_time_stats() -> (usize, usize) {
+    let processor = PROCESSOR.exclusive_access();
+    (processor.busy_time, processor.idle_time)
+}
 ///Take the current task,leaving a None in its place
 pub fn take_current_task() -> Option<Arc<TaskControlBlock>> {
     PROCESSOR.exclusive_access().take_current()
@@ -0,0 +1,130 @@
+//! Completely Fair Scheduler style virtual-runtime scheduling
+//!
+//! Each task accumulates virtual runtime, its measured run time scaled by
+//! `NICE_0_WEIGHT / weight`, and the task with the smallest vruntime runs
+//! next. A running task is preempted once it has used its share of
+//! `CFS_SCHED_LATENCY`, but never before `CFS_MIN_GRANULARITY`.
+use super::Scheduler;
+use crate::config::{CFS_MIN_GRANULARITY, CFS_SCHED_LATENCY};
+use crate::task::TaskControlBlock;
+use crate::timer::get_time;
+use alloc::collections::BTreeMap;
+use alloc::sync::Arc;
+
+/// Lowest nice value (highest priority)
+pub const MIN_NICE: i32 = -20;
+/// Highest nice value (lowest priority)
+pub const MAX_NICE: i32 = 19;
+/// Weight of a task with nice 0
+const NICE_0_WEIGHT: u64 = 1024;
+
+/// Nice -20..=19 to load weight, each step is roughly 10% of cpu time
+const PRIO_TO_WEIGHT: [u64; 40] = [
+    /* -20 */ 88761, 71755, 56483, 46273, 36291,
+    /* -15 */ 29154, 23254, 18705, 14949, 11916,
+    /* -10 */ 9548, 7620, 6100, 4904, 3906,
+    /*  -5 */ 3121, 2501, 1991, 1586, 1277,
+    /*   0 */ 1024, 820, 655, 526, 423,
+    /*   5 */ 335, 272, 215, 172, 137,
+    /*  10 */ 110, 87, 70, 56, 45,
+    /*  15 */ 36, 29, 23, 18, 15,
+];
+
+/// Load weight of a task with `nice`
+pub fn nice_to_weight(nice: i32) -> u64 {
+    PRIO_TO_WEIGHT[(nice.clamp(MIN_NICE, MAX_NICE) - MIN_NICE) as usize]
+}
+
+pub struct CfsScheduler {
+    /// run queue ordered by (vruntime, insertion order),
+    /// holding the weight each task was queued with
+    ready_queue: BTreeMap<(u64, u64), (u64, Arc<TaskControlBlock>)>,
+    seq: u64,
+    /// monotonic lower bound of the vruntime of runnable tasks
+    min_vruntime: u64,
+    /// sum of the weights of queued tasks
+    load: u64,
+}
+
+impl CfsScheduler {
+    /// Charge the time `task` ran since it was last accounted
+    fn update_curr(&mut self, task: &Arc<TaskControlBlock>) {
+        let mut inner = task.inner_exclusive_access();
+        if let Some(start) = inner.exec_start {
+            let now = get_time();
+            let delta = now - start;
+            inner.exec_start = Some(now);
+            inner.slice_runtime += delta;
+            inner.vruntime += delta as u64 * NICE_0_WEIGHT / nice_to_weight(inner.nice);
+        }
+    }
+}
+
+impl Scheduler for CfsScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: BTreeMap::new(),
+            seq: 0,
+            min_vruntime: 0,
+            load: 0,
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.update_curr(&task);
+        let mut inner = task.inner_exclusive_access();
+        inner.exec_start = None;
+        // new and woken tasks start slightly ahead of the queue,
+        // they must not catch up on all the time they did not run
+        let floor = self.min_vruntime.saturating_sub(CFS_SCHED_LATENCY as u64 / 2);
+        inner.vruntime = inner.vruntime.max(floor);
+        let key = (inner.vruntime, self.seq);
+        let weight = nice_to_weight(inner.nice);
+        drop(inner);
+        self.seq += 1;
+        self.load += weight;
+        self.ready_queue.insert(key, (weight, task));
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let ((vruntime, _), (weight, task)) = self.ready_queue.pop_first()?;
+        self.min_vruntime = self.min_vruntime.max(vruntime);
+        self.load -= weight;
+        let mut inner = task.inner_exclusive_access();
+        inner.exec_start = Some(get_time());
+        inner.slice_runtime = 0;
+        drop(inner);
+        Some(task)
+    }
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.update_curr(task);
+        let inner = task.inner_exclusive_access();
+        let weight = nice_to_weight(inner.nice);
+        let slice = (CFS_SCHED_LATENCY as u64 * weight / (self.load + weight)) as usize;
+        if inner.slice_runtime < slice.max(CFS_MIN_GRANULARITY) {
+            return false;
+        }
+        // preempt only if someone is behind the current task
+        self.ready_queue
+            .first_key_value()
+            .is_some_and(|(&(vruntime, _), _)| vruntime < inner.vruntime)
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let key = self
+            .ready_queue
+            .iter()
+            .find(|(_, (_, t))| Arc::ptr_eq(t, task))
+            .map(|(key, _)| *key);
+        if let Some(key) = key {
+            let (weight, _) = self.ready_queue.remove(&key).unwrap();
+            self.load -= weight;
+            true
+        } else {
+            false
+        }
+    }
+    fn on_priority_change(&mut self, task: &Arc<TaskControlBlock>) {
+        // the queued weight is stale, re-insert with the new one
+        if self.remove(task) {
+            self.add(task.clone());
+        }
+    }
+}
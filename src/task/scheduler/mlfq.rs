@@ -0,0 +1,80 @@
+//! Multi-level feedback queue scheduling
+//!
+//! New tasks enter the highest level. A task using up the quantum of its
+//! level is demoted, and every `MLFQ_BOOST_PERIOD` ticks all tasks are
+//! moved back to the highest level so that none of them starves.
+use super::Scheduler;
+use crate::config::{MLFQ_BOOST_PERIOD, MLFQ_LEVELS};
+use crate::task::TaskControlBlock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Quantum in ticks of a level, doubling at each lower level
+fn quantum(level: usize) -> usize {
+    1 << level
+}
+
+pub struct MlfqScheduler {
+    queues: [VecDeque<Arc<TaskControlBlock>>; MLFQ_LEVELS],
+    ticks_since_boost: usize,
+}
+
+impl MlfqScheduler {
+    fn boost(&mut self, current: &Arc<TaskControlBlock>) {
+        for level in 1..MLFQ_LEVELS {
+            while let Some(task) = self.queues[level].pop_front() {
+                let mut inner = task.inner_exclusive_access();
+                inner.sched_level = 0;
+                inner.sched_ticks = 0;
+                drop(inner);
+                self.queues[0].push_back(task);
+            }
+        }
+        let mut inner = current.inner_exclusive_access();
+        inner.sched_level = 0;
+        inner.sched_ticks = 0;
+    }
+}
+
+impl Scheduler for MlfqScheduler {
+    fn new() -> Self {
+        Self {
+            queues: core::array::from_fn(|_| VecDeque::new()),
+            ticks_since_boost: 0,
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let level = task.inner_exclusive_access().sched_level;
+        self.queues[level].push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.queues.iter_mut().find_map(|queue| queue.pop_front())
+    }
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.ticks_since_boost += 1;
+        if self.ticks_since_boost >= MLFQ_BOOST_PERIOD {
+            self.ticks_since_boost = 0;
+            self.boost(task);
+            return true;
+        }
+        let mut inner = task.inner_exclusive_access();
+        inner.sched_ticks += 1;
+        if inner.sched_ticks >= quantum(inner.sched_level) {
+            // used up its allotment, demote it
+            inner.sched_ticks = 0;
+            inner.sched_level = (inner.sched_level + 1).min(MLFQ_LEVELS - 1);
+            return true;
+        }
+        // preempt if a task of a higher level is waiting
+        let level = inner.sched_level;
+        self.queues[..level].iter().any(|queue| !queue.is_empty())
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let level = task.inner_exclusive_access().sched_level;
+        let queue = &mut self.queues[level];
+        let len = queue.len();
+        queue.retain(|t| !Arc::ptr_eq(t, task));
+        queue.len() != len
+    }
+    fn on_priority_change(&mut self, _task: &Arc<TaskControlBlock>) {}
+}
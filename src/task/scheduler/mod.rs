@@ -0,0 +1,44 @@
+//! Pluggable scheduling policies used by [`TaskManager`](super::TaskManager)
+//!
+//! The policy is chosen at build time with one of the `sched-*` cargo
+//! features (`make run SCHED=rr`), falling back to stride scheduling.
+mod cfs;
+mod mlfq;
+mod rr;
+mod rt;
+mod stride;
+
+use super::TaskControlBlock;
+use alloc::sync::Arc;
+
+pub use cfs::{CfsScheduler, MAX_NICE, MIN_NICE};
+pub use mlfq::MlfqScheduler;
+pub use rr::RoundRobinScheduler;
+pub use rt::{RtScheduler, SchedParam, SchedPolicy, MAX_RT_PRIORITY, MIN_RT_PRIORITY};
+pub use stride::{StrideScheduler, MIN_PRIORITY};
+
+/// A scheduling policy managing the ready queue
+pub trait Scheduler {
+    /// Create an empty scheduler
+    fn new() -> Self;
+    /// Put a ready task into the run queue
+    fn add(&mut self, task: Arc<TaskControlBlock>);
+    /// Pick the next task to run, or `None` if the run queue is empty
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>>;
+    /// Account a timer tick to the running `task`,
+    /// return whether it should be preempted
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+    /// Remove `task` from the run queue, return whether it was queued
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool;
+    /// Called after the priority of `task` has been changed
+    fn on_priority_change(&mut self, task: &Arc<TaskControlBlock>);
+}
+
+#[cfg(feature = "sched-rr")]
+pub type SchedulerImpl = RoundRobinScheduler;
+#[cfg(all(feature = "sched-mlfq", not(feature = "sched-rr")))]
+pub type SchedulerImpl = MlfqScheduler;
+#[cfg(all(feature = "sched-cfs", not(any(feature = "sched-rr", feature = "sched-mlfq"))))]
+pub type SchedulerImpl = CfsScheduler;
+#[cfg(not(any(feature = "sched-rr", feature = "sched-mlfq", feature = "sched-cfs")))]
+pub type SchedulerImpl = StrideScheduler;
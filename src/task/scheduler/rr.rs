@@ -0,0 +1,41 @@
+//! Round-robin scheduling with a fixed time slice
+use super::Scheduler;
+use crate::config::RR_TIME_SLICE;
+use crate::task::TaskControlBlock;
+use alloc::collections::VecDeque;
+use alloc::sync::Arc;
+
+/// Run tasks in FIFO order, each for at most `RR_TIME_SLICE` ticks
+pub struct RoundRobinScheduler {
+    ready_queue: VecDeque<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for RoundRobinScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: VecDeque::new(),
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        self.ready_queue.push_back(task);
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        self.ready_queue.pop_front()
+    }
+    fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = task.inner_exclusive_access();
+        inner.sched_ticks += 1;
+        if inner.sched_ticks >= RR_TIME_SLICE {
+            inner.sched_ticks = 0;
+            true
+        } else {
+            false
+        }
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let len = self.ready_queue.len();
+        self.ready_queue.retain(|t| !Arc::ptr_eq(t, task));
+        self.ready_queue.len() != len
+    }
+    fn on_priority_change(&mut self, _task: &Arc<TaskControlBlock>) {}
+}
@@ -0,0 +1,117 @@
+//! Real-time scheduling classes `SCHED_FIFO` and `SCHED_RR`
+//!
+//! Real-time tasks always run before normal tasks. Among them the highest
+//! `rt_priority` wins, `SCHED_FIFO` tasks run until they yield and
+//! `SCHED_RR` tasks of the same priority take turns every `RT_RR_TIME_SLICE`
+//! ticks.
+use crate::config::RT_RR_TIME_SLICE;
+use crate::task::TaskControlBlock;
+use alloc::collections::{BTreeMap, VecDeque};
+use alloc::sync::Arc;
+
+/// Lowest real-time priority
+pub const MIN_RT_PRIORITY: usize = 1;
+/// Highest real-time priority
+pub const MAX_RT_PRIORITY: usize = 99;
+
+/// Scheduling policy of a task, numbered as in Linux
+#[derive(Copy, Clone, PartialEq, Debug)]
+pub enum SchedPolicy {
+    /// Scheduled by the fair policy [`SchedulerImpl`](super::SchedulerImpl)
+    Normal = 0,
+    Fifo = 1,
+    RoundRobin = 2,
+}
+
+impl SchedPolicy {
+    pub fn from_raw(policy: i32) -> Option<Self> {
+        match policy {
+            0 => Some(Self::Normal),
+            1 => Some(Self::Fifo),
+            2 => Some(Self::RoundRobin),
+            _ => None,
+        }
+    }
+    pub fn is_realtime(&self) -> bool {
+        *self != Self::Normal
+    }
+    /// Whether `priority` is allowed for this policy
+    pub fn valid_priority(&self, priority: usize) -> bool {
+        if self.is_realtime() {
+            (MIN_RT_PRIORITY..=MAX_RT_PRIORITY).contains(&priority)
+        } else {
+            priority == 0
+        }
+    }
+}
+
+/// Scheduling parameters exchanged with user space
+#[repr(C)]
+#[derive(Copy, Clone)]
+pub struct SchedParam {
+    pub sched_priority: i32,
+}
+
+/// Run queues of real-time tasks, one FIFO per priority
+pub struct RtScheduler {
+    queues: BTreeMap<usize, VecDeque<Arc<TaskControlBlock>>>,
+}
+
+impl RtScheduler {
+    pub fn new() -> Self {
+        Self {
+            queues: BTreeMap::new(),
+        }
+    }
+    pub fn has_ready(&self) -> bool {
+        !self.queues.is_empty()
+    }
+    /// Highest priority among the queued tasks
+    fn top_priority(&self) -> Option<usize> {
+        self.queues.last_key_value().map(|(prio, _)| *prio)
+    }
+    pub fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let prio = task.inner_exclusive_access().rt_priority;
+        self.queues.entry(prio).or_default().push_back(task);
+    }
+    pub fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let mut entry = self.queues.last_entry()?;
+        let task = entry.get_mut().pop_front();
+        if entry.get().is_empty() {
+            entry.remove();
+        }
+        task
+    }
+    /// Account a timer tick to the running real-time `task`,
+    /// return whether it should be preempted
+    pub fn tick(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let mut inner = task.inner_exclusive_access();
+        if self.top_priority().is_some_and(|prio| prio > inner.rt_priority) {
+            return true;
+        }
+        if inner.sched_policy != SchedPolicy::RoundRobin {
+            return false;
+        }
+        inner.sched_ticks += 1;
+        if inner.sched_ticks < RT_RR_TIME_SLICE {
+            return false;
+        }
+        inner.sched_ticks = 0;
+        // only give up the cpu to a task of the same priority
+        self.top_priority() == Some(inner.rt_priority)
+    }
+    pub fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        let prio = task.inner_exclusive_access().rt_priority;
+        if let Some(queue) = self.queues.get_mut(&prio) {
+            let len = queue.len();
+            queue.retain(|t| !Arc::ptr_eq(t, task));
+            let removed = queue.len() != len;
+            if queue.is_empty() {
+                self.queues.remove(&prio);
+            }
+            removed
+        } else {
+            false
+        }
+    }
+}
@@ -0,0 +1,177 @@
+//! Stride scheduling
+//!
+//! Every task owns a 64-bit pass value which is advanced by
+//! `BIG_STRIDE / priority` each time the task is picked, and the task with
+//! the smallest pass runs next. Pass values are compared with wrapping
+//! arithmetic, which is correct as long as any two of them are less than
+//! `2^63` apart. Since the priority is at least 2 and a task joining the
+//! queue is never behind the last picked pass, passes in the queue are
+//! always within `BIG_STRIDE` of each other.
+use super::Scheduler;
+use crate::println;
+use crate::task::TaskControlBlock;
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use alloc::vec::Vec;
+use core::cmp::Ordering;
+
+/// Pass advance of a task with priority 1
+pub const BIG_STRIDE: u64 = 1 << 32;
+/// Smallest priority accepted by `sys_set_priority`
+pub const MIN_PRIORITY: u64 = 2;
+
+/// Stride of a task with `priority`
+pub fn stride_of(priority: u64) -> u64 {
+    BIG_STRIDE / priority
+}
+
+/// Compare two pass values which may have wrapped around
+fn pass_cmp(a: u64, b: u64) -> Ordering {
+    (a.wrapping_sub(b) as i64).cmp(&0)
+}
+
+/// A queued item, keyed by the pass it had when it was pushed
+struct StrideEntry<T> {
+    pass: u64,
+    seq: u64,
+    item: T,
+}
+
+impl<T> PartialEq for StrideEntry<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.cmp(other) == Ordering::Equal
+    }
+}
+
+impl<T> Eq for StrideEntry<T> {}
+
+impl<T> PartialOrd for StrideEntry<T> {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl<T> Ord for StrideEntry<T> {
+    /// `BinaryHeap` is a max-heap, so the smallest pass compares greatest,
+    /// ties are broken in FIFO order
+    fn cmp(&self, other: &Self) -> Ordering {
+        pass_cmp(other.pass, self.pass).then(other.seq.cmp(&self.seq))
+    }
+}
+
+/// A priority queue ordered by pass values
+pub struct StrideQueue<T> {
+    heap: BinaryHeap<StrideEntry<T>>,
+    seq: u64,
+    /// pass of the item popped last
+    min_pass: u64,
+}
+
+impl<T> StrideQueue<T> {
+    pub fn new() -> Self {
+        Self {
+            heap: BinaryHeap::new(),
+            seq: 0,
+            min_pass: 0,
+        }
+    }
+    /// Queue `item` with `pass`, return the pass it is actually queued with.
+    /// An item lagging behind the queue (new or woken up) is moved forward
+    /// so it cannot monopolize the cpu.
+    pub fn push(&mut self, pass: u64, item: T) -> u64 {
+        let pass = if pass_cmp(pass, self.min_pass) == Ordering::Less {
+            self.min_pass
+        } else {
+            pass
+        };
+        self.seq += 1;
+        self.heap.push(StrideEntry {
+            pass,
+            seq: self.seq,
+            item,
+        });
+        pass
+    }
+    /// Pop the item with the smallest pass
+    pub fn pop(&mut self) -> Option<(u64, T)> {
+        let entry = self.heap.pop()?;
+        self.min_pass = entry.pass;
+        Some((entry.pass, entry.item))
+    }
+    /// Remove all items for which `f` returns false, return whether any was removed
+    pub fn retain(&mut self, mut f: impl FnMut(&T) -> bool) -> bool {
+        let len = self.heap.len();
+        self.heap.retain(|entry| f(&entry.item));
+        self.heap.len() != len
+    }
+}
+
+/// Always run the task with the smallest pass, preempting on every tick
+pub struct StrideScheduler {
+    ready_queue: StrideQueue<Arc<TaskControlBlock>>,
+}
+
+impl Scheduler for StrideScheduler {
+    fn new() -> Self {
+        Self {
+            ready_queue: StrideQueue::new(),
+        }
+    }
+    fn add(&mut self, task: Arc<TaskControlBlock>) {
+        let pass = task.inner_exclusive_access().pass;
+        let pass = self.ready_queue.push(pass, task.clone());
+        task.inner_exclusive_access().pass = pass;
+    }
+    fn fetch(&mut self) -> Option<Arc<TaskControlBlock>> {
+        let (_, task) = self.ready_queue.pop()?;
+        let mut inner = task.inner_exclusive_access();
+        inner.pass = inner.pass.wrapping_add(stride_of(inner.priority));
+        drop(inner);
+        Some(task)
+    }
+    fn tick(&mut self, _task: &Arc<TaskControlBlock>) -> bool {
+        true
+    }
+    fn remove(&mut self, task: &Arc<TaskControlBlock>) -> bool {
+        self.ready_queue.retain(|t| !Arc::ptr_eq(t, task))
+    }
+    fn on_priority_change(&mut self, task: &Arc<TaskControlBlock>) {
+        // the queued key is a snapshot, re-insert so the heap stays ordered
+        if self.remove(task) {
+            self.add(task.clone());
+        }
+    }
+}
+
+#[allow(unused)]
+/// Check that the cpu share of each task is proportional to its priority,
+/// starting close to the wrap-around point of the pass values
+pub fn stride_test() {
+    const ROUNDS: usize = 10000;
+    const TOLERANCE_PERMILLE: usize = 10;
+    let priorities: [u64; 4] = [2, 3, 5, 10];
+    let mut queue = StrideQueue::new();
+    queue.min_pass = u64::MAX - BIG_STRIDE;
+    for (id, _) in priorities.iter().enumerate() {
+        queue.push(u64::MAX - BIG_STRIDE, id);
+    }
+    let mut runs: Vec<usize> = priorities.iter().map(|_| 0).collect();
+    for _ in 0..ROUNDS {
+        let (pass, id) = queue.pop().unwrap();
+        runs[id] += 1;
+        queue.push(pass.wrapping_add(stride_of(priorities[id])), id);
+    }
+    let total: u64 = priorities.iter().sum();
+    for (id, prio) in priorities.iter().enumerate() {
+        let expected = ROUNDS * (*prio as usize) / (total as usize);
+        let diff = runs[id].abs_diff(expected);
+        assert!(
+            diff * 1000 <= ROUNDS * TOLERANCE_PERMILLE,
+            "priority {} ran {} times, expected {}",
+            prio,
+            runs[id],
+            expected
+        );
+    }
+    println!("stride_test passed!");
+}
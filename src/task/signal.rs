@@ -54,6 +54,8 @@ impl SignalFlags {
             Some((-9, "Killed, SIGKILL=9"))
         } else if self.contains(Self::SIGSEGV) {
             Some((-11, "Segmentation Fault, SIGSEGV=11"))
+        } else if self.contains(Self::SIGPIPE) {
+            Some((-13, "Broken Pipe, SIGPIPE=13"))
         } else {
             //println!("[K] signalflags check_error  {:?}", self);
             None
@@ -2,12 +2,14 @@
 use super::{PidAllocator, TaskContext};
 use super::{pid_alloc, KernelStack, PidHandle};
 use crate::config::TRAP_CONTEXT;
-use crate::fs::{File, Stdin, Stdout};
+use super::fd_table::FdTable;
+use crate::fs::{Stdin, Stdout};
 use crate::mm::{translated_refmut, MemorySet, PhysPageNum, VirtAddr, KERNEL_SPACE};
 use crate::sync::UPSafeCell;
-use crate::syscall::MAX_SYSCALL_NUM;
-use crate::task::mail::{Mail, MailBox};
-use crate::task::{SignalActions, SignalFlags};
+use crate::syscall::{MAX_SYSCALL_NUM, MAX_SYSCALL_NUM_EXT};
+use crate::task::mail::{MailBox, MAIL_BUFFER_SIZE, MAIL_MAXLEN};
+use crate::task::{SchedPolicy, SignalActions, SignalFlags};
+use crate::timer::{cycles_to_ms, get_time};
 use crate::trap::{self, trap_handler, TrapContext};
 use alloc::string::String;
 use alloc::sync::{Arc, Weak};
@@ -25,6 +27,10 @@ pub struct TaskControlBlock {
 }
 
 pub struct TaskControlBlockInner {
+    // name of the executed program
+    pub name: String,
+    // normalized absolute path of the working directory
+    pub cwd: String,
     pub trap_cx_ppn: PhysPageNum,
     pub base_size: usize,
     pub task_cx: TaskContext,
@@ -33,9 +39,28 @@ pub struct TaskControlBlockInner {
     pub parent: Option<Weak<TaskControlBlock>>,
     pub children: Vec<Arc<TaskControlBlock>>,
     pub exit_code: i32,
-    pub stride: u8,
-    pub priority: u8,
-    pub fd_table: Vec<Option<Arc<dyn File + Send + Sync>>>,
+    // pass value of stride scheduling
+    pub pass: u64,
+    pub priority: u64,
+    // level of the task in the multi-level feedback queue
+    pub sched_level: usize,
+    // ticks consumed in the current time slice
+    pub sched_ticks: usize,
+    // virtual runtime of CFS scheduling
+    pub vruntime: u64,
+    // nice value, -20 (highest priority) to 19
+    pub nice: i32,
+    // time the task started running or was last accounted, `None` if not running
+    pub exec_start: Option<usize>,
+    // timer cycles run since the task was picked
+    pub slice_runtime: usize,
+    // scheduling class of the task
+    pub sched_policy: SchedPolicy,
+    // priority among real-time tasks, 0 for normal tasks
+    pub rt_priority: usize,
+    // cpu time and syscall accounting
+    pub task_info: TaskInfo,
+    pub fd_table: FdTable,
     pub signals: SignalFlags,
     pub signal_mask: SignalFlags,
     // the signal which is being handling
@@ -47,7 +72,8 @@ pub struct TaskControlBlockInner {
     // if the task is frozen by a signal
     pub frozen: bool,
     pub trap_ctx_backup: Option<TrapContext>,
-    pub mailbox: MailBox,
+    /// shared with the mailbox fds opened on it
+    pub mailbox: Arc<MailBox>,
 }
 
 impl TaskControlBlockInner {
@@ -68,14 +94,6 @@ impl TaskControlBlockInner {
     pub fn is_zombie(&self) -> bool {
         self.get_status() == TaskStatus::Zombie
     }
-    pub fn alloc_fd(&mut self) -> usize {
-        if let Some(fd) = (0..self.fd_table.len()).find(|fd| self.fd_table[*fd].is_none()) {
-            fd
-        } else {
-            self.fd_table.push(None);
-            self.fd_table.len() - 1
-        }
-    }
 }
 
 impl TaskControlBlock {
@@ -99,6 +117,8 @@ impl TaskControlBlock {
             kernel_stack,
             inner: unsafe {
                 UPSafeCell::new(TaskControlBlockInner {
+                    name: String::new(),
+                    cwd: String::from("/"),
                     trap_cx_ppn,
                     base_size: user_sp,
                     task_cx: TaskContext::goto_trap_return(kernel_stack_top),
@@ -107,13 +127,22 @@ impl TaskControlBlock {
                     parent: None,
                     children: Vec::new(),
                     exit_code: 0,
-                    stride: 0,
+                    pass: 0,
                     priority: 16,
-                    fd_table: vec![
-                        Some(Arc::new(Stdin)),
-                        Some(Arc::new(Stdout)),
-                        Some(Arc::new(Stdout)),
-                    ],
+                    sched_level: 0,
+                    sched_ticks: 0,
+                    vruntime: 0,
+                    nice: 0,
+                    exec_start: None,
+                    slice_runtime: 0,
+                    sched_policy: SchedPolicy::Normal,
+                    rt_priority: 0,
+                    task_info: TaskInfo::init(),
+                    fd_table: FdTable::new(vec![
+                        Arc::new(Stdin::new()),
+                        Arc::new(Stdout),
+                        Arc::new(Stdout),
+                    ]),
                     signals: SignalFlags::empty(),
                     signal_mask: SignalFlags::empty(),
                     handling_sig: -1,
@@ -121,7 +150,7 @@ impl TaskControlBlock {
                     killed: false,
                     frozen: false,
                     trap_ctx_backup: None,
-                    mailbox: MailBox::new(),
+                    mailbox: Arc::new(MailBox::new(MAIL_BUFFER_SIZE, MAIL_MAXLEN)),
                 })
             },
         };
@@ -181,6 +210,8 @@ impl TaskControlBlock {
         inner.memory_set = memory_set;
         // update trap_cx ppn
         inner.trap_cx_ppn = trap_cx_ppn;
+        // close the fds opened with close-on-exec
+        inner.fd_table.close_on_exec();
         // initialize trap_cx
         let mut trap_cx = TrapContext::app_init_context(
             entry_point,
@@ -207,20 +238,13 @@ impl TaskControlBlock {
         let pid_handle = pid_alloc();
         let kernel_stack = KernelStack::new(&pid_handle);
         let kernel_stack_top = kernel_stack.get_top();
-        // copy fd table
-        let mut new_fd_table: Vec<Option<Arc<dyn File + Send + Sync>>> = Vec::new();
-        for fd in parent_inner.fd_table.iter() {
-            if let Some(file) = fd {
-                new_fd_table.push(Some(file.clone()));
-            } else {
-                new_fd_table.push(None);
-            }
-        }
         let task_control_block = Arc::new(TaskControlBlock {
             pid: pid_handle,
             kernel_stack,
             inner: unsafe {
                 UPSafeCell::new(TaskControlBlockInner {
+                    name: parent_inner.name.clone(),
+                    cwd: parent_inner.cwd.clone(),
                     trap_cx_ppn,
                     base_size: parent_inner.base_size,
                     task_cx: TaskContext::goto_trap_return(kernel_stack_top),
@@ -229,9 +253,19 @@ impl TaskControlBlock {
                     parent: Some(Arc::downgrade(self)),
                     children: Vec::new(),
                     exit_code: 0,
-                    stride: 0,
+                    pass: 0,
                     priority: 16,
-                    fd_table: new_fd_table,
+                    sched_level: 0,
+                    sched_ticks: 0,
+                    vruntime: 0,
+                    nice: parent_inner.nice,
+                    exec_start: None,
+                    slice_runtime: 0,
+                    sched_policy: parent_inner.sched_policy,
+                    rt_priority: parent_inner.rt_priority,
+                    task_info: TaskInfo::init(),
+                    // the child shares the open file descriptions
+                    fd_table: parent_inner.fd_table.clone(),
                     signals: SignalFlags::empty(),
                     // inherit the signal_mask and signal_action
                     signal_mask: parent_inner.signal_mask,
@@ -240,7 +274,8 @@ impl TaskControlBlock {
                     killed: false,
                     frozen: false,
                     trap_ctx_backup: None,
-                    mailbox: parent_inner.mailbox.clone(),
+                    // the mails were sent to the parent, only the configuration is kept
+                    mailbox: Arc::new(parent_inner.mailbox.empty_copy()),
                 })
             },
         });
@@ -267,10 +302,13 @@ impl TaskControlBlock {
         let pid_handle = pid_alloc();
         let kernel_stack = KernelStack::new(&pid_handle);
         let kernel_stack_top = kernel_stack.get_top();
+        let cwd = self.inner_exclusive_access().cwd.clone();
         let tcb = Arc::new(TaskControlBlock {
             pid: pid_handle,
             kernel_stack,
             inner: unsafe { UPSafeCell::new(TaskControlBlockInner {
+                name: String::new(),
+                cwd,
                 trap_cx_ppn,
                 base_size: user_sp,
                 task_cx: TaskContext::goto_trap_return(kernel_stack_top),
@@ -279,13 +317,22 @@ impl TaskControlBlock {
                 parent: Some(Arc::downgrade(self)),
                 children: Vec::new(),
                 exit_code: 0,
-                stride: 0,
+                pass: 0,
                 priority: 16,
-                fd_table: vec![
-                    Some(Arc::new(Stdin)),
-                    Some(Arc::new(Stdout)),
-                    Some(Arc::new(Stdout)),
-                ],
+                sched_level: 0,
+                sched_ticks: 0,
+                vruntime: 0,
+                nice: 0,
+                exec_start: None,
+                slice_runtime: 0,
+                sched_policy: SchedPolicy::Normal,
+                rt_priority: 0,
+                task_info: TaskInfo::init(),
+                fd_table: FdTable::new(vec![
+                    Arc::new(Stdin::new()),
+                    Arc::new(Stdout),
+                    Arc::new(Stdout),
+                ]),
                 signals: SignalFlags::empty(),
                 signal_mask: SignalFlags::empty(),
                 handling_sig: -1,
@@ -293,7 +340,7 @@ impl TaskControlBlock {
                 killed: false,
                 frozen: false,
                 trap_ctx_backup: None,
-                mailbox: MailBox::new(),
+                mailbox: Arc::new(MailBox::new(MAIL_BUFFER_SIZE, MAIL_MAXLEN)),
             })}
         });
         let trap_cx = tcb.inner_exclusive_access().get_trap_cx();
@@ -313,84 +360,35 @@ impl TaskControlBlock {
     pub fn getpid(&self) -> usize {
         self.pid.0
     }
-    pub fn mailread(&self) -> Option<Mail> {
-        let mut inner = self.inner_exclusive_access();
-        if inner.mailbox.available_read() > 0 {
-            Some(inner.mailbox.read())
-        }else {
-            None
-        }
-    }
-    pub fn mailwrite(&self, mail: &Mail) -> isize {
-        let mut inner = self.inner_exclusive_access();
-        if inner.mailbox.available_write() > 0 {
-            inner.mailbox.write(mail);
-            mail.len as isize
-        } else {
-            -1
-        }
-    }
-    pub fn mailread_available(&self) -> usize {
-        self.inner_exclusive_access().mailbox.available_read()
-    }
-    pub fn mailwrite_available(&self) -> usize {
-        self.inner_exclusive_access().mailbox.available_write()
-    }
-}
-
-fn tcb_cmp(this: &TaskControlBlock, other: &TaskControlBlock) -> core::cmp::Ordering {
-    let this = this.inner_exclusive_access();
-    let another = other.inner_exclusive_access();
-    let delta = this.stride - another.stride;
-    if delta == 0 {
-        core::cmp::Ordering::Equal
-    }
-    else if delta <= 128 {
-        core::cmp::Ordering::Less
-    }
-    else {
-        core::cmp::Ordering::Greater
-    }
-}
-
-impl PartialEq for TaskControlBlock {
-    fn eq(&self, other: &Self) -> bool {
-        let this = self.inner_exclusive_access();
-        let other = other.inner_exclusive_access();
-        this.stride == other.stride
-    }
-}
-
-impl Eq for TaskControlBlock {}
-
-impl PartialOrd for TaskControlBlock {
-    fn partial_cmp(&self, other: &Self) -> Option<core::cmp::Ordering> {
-        Some(tcb_cmp(self, other))
-    }
 }
 
-impl Ord for TaskControlBlock {
-    fn cmp(&self, other: &Self) -> core::cmp::Ordering {
-        tcb_cmp(self, other)
-    }
-}
-
-#[derive(Copy, Clone, PartialEq)]
+#[repr(usize)]
+#[derive(Copy, Clone, PartialEq, Debug)]
 pub enum TaskStatus {
     Ready,
     Running,
     Zombie,
+    // after `Zombie` to keep the values seen by `task_info`
+    Blocked,
 }
 
-
+/// Cpu accounting of a task, times are in timer cycles
 pub struct TaskInfo {
-    pub id: usize,
-    pub status: TaskStatus,
     pub call: Vec<SyscallInfo>,
-    pub total_time: usize,
+    pub utime: usize,
+    pub stime: usize,
+    // user and system time of waited-for children
+    pub cutime: usize,
+    pub cstime: usize,
+    // voluntary and involuntary context switches
+    pub nvcsw: usize,
+    pub nivcsw: usize,
+    pub start_time: usize,
+    // start of the period not yet charged to utime or stime
     pub last_start: usize,
 }
 
+#[repr(C)]
 #[derive(Clone, Copy)]
 pub struct SyscallInfo {
     pub id: usize,
@@ -398,32 +396,99 @@ pub struct SyscallInfo {
 }
 
 impl TaskInfo {
-    pub fn init(id: usize) -> Self {
+    pub fn init() -> Self {
+        let now = get_time();
         Self {
-            id: id,
-            status: TaskStatus::Ready,
             call: Vec::new(),
-            total_time: 0,
-            last_start: 0,
+            utime: 0,
+            stime: 0,
+            cutime: 0,
+            cstime: 0,
+            nvcsw: 0,
+            nivcsw: 0,
+            start_time: now,
+            last_start: now,
+        }
+    }
+    /// Charge the time since the last accounting to user or system time
+    pub fn charge(&mut self, user: bool) {
+        let now = get_time();
+        if user {
+            self.utime += now - self.last_start;
+        } else {
+            self.stime += now - self.last_start;
+        }
+        self.last_start = now;
+    }
+    /// Start a new accounting period without charging the time before,
+    /// used when the task is switched in
+    pub fn restart(&mut self) {
+        self.last_start = get_time();
+    }
+    pub fn count_syscall(&mut self, syscall_id: usize) {
+        if let Some(info) = self.call.iter_mut().find(|info| info.id == syscall_id) {
+            info.times += 1;
+        } else {
+            self.call.push(SyscallInfo { id: syscall_id, times: 1 });
         }
     }
-    pub fn user(&self) -> UserTaskInfo {
+    /// Add the times of a reaped child, including its own children
+    pub fn add_child(&mut self, child: &TaskInfo) {
+        self.cutime += child.utime + child.cutime;
+        self.cstime += child.stime + child.cstime;
+    }
+    /// The counters in the original `task_info` layout
+    pub fn user(&self, id: usize, status: TaskStatus) -> UserTaskInfo {
         let mut ret = UserTaskInfo {
-            id: self.id,
-            status: self.status,
+            id,
+            status,
             call: [SyscallInfo{id: 0, times: 0}; MAX_SYSCALL_NUM],
-            total_time: self.total_time
+            total_time: cycles_to_ms(get_time() - self.start_time),
+        };
+        for (ind, info) in self.call.iter().take(MAX_SYSCALL_NUM).enumerate() {
+            ret.call[ind] = *info;
+        }
+        ret
+    }
+    /// The counters and the cpu accounting
+    pub fn user_ext(&self, id: usize, status: TaskStatus) -> UserTaskInfoExt {
+        let mut ret = UserTaskInfoExt {
+            id,
+            status,
+            call: [SyscallInfo{id: 0, times: 0}; MAX_SYSCALL_NUM_EXT],
+            total_time: cycles_to_ms(get_time() - self.start_time),
+            utime: cycles_to_ms(self.utime),
+            stime: cycles_to_ms(self.stime),
+            nvcsw: self.nvcsw,
+            nivcsw: self.nivcsw,
         };
-        for (ind, info) in self.call.iter().enumerate() {
+        for (ind, info) in self.call.iter().take(MAX_SYSCALL_NUM_EXT).enumerate() {
             ret.call[ind] = *info;
         }
         ret
     }
 }
 
+/// Task information in the original layout, copied to user space by
+/// `task_info` without a size, times are in milliseconds
+#[repr(C)]
 pub struct UserTaskInfo {
     pub id: usize,
     pub status: TaskStatus,
     pub call: [SyscallInfo; MAX_SYSCALL_NUM],
     pub total_time: usize,
-}
\ No newline at end of file
+}
+
+/// Task information with the cpu accounting, copied to user space by
+/// `task_info` given its size, times are in milliseconds
+#[repr(C)]
+pub struct UserTaskInfoExt {
+    pub id: usize,
+    pub status: TaskStatus,
+    pub call: [SyscallInfo; MAX_SYSCALL_NUM_EXT],
+    pub total_time: usize,
+    pub utime: usize,
+    pub stime: usize,
+    pub nvcsw: usize,
+    pub nivcsw: usize,
+}
@@ -2,10 +2,68 @@
 
 use crate::config::CLOCK_FREQ;
 use crate::sbi::set_timer;
+use crate::sync::UPSafeCell;
+use crate::task::{wakeup_task, TaskControlBlock};
+use alloc::collections::BinaryHeap;
+use alloc::sync::Arc;
+use core::cmp::Ordering;
+use lazy_static::*;
 use riscv::register::time;
 
 const TICKS_PER_SEC: usize = 100;
 const MSEC_PER_SEC: usize = 1000;
+const USEC_PER_SEC: usize = 1_000_000;
+/// Length of a scheduling tick in milliseconds
+pub const MSEC_PER_TICK: usize = MSEC_PER_SEC / TICKS_PER_SEC;
+
+/// Time value in microseconds exchanged with user space
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TimeVal {
+    pub sec: usize,
+    pub usec: usize,
+}
+
+impl TimeVal {
+    pub fn from_cycles(cycles: usize) -> Self {
+        let us = cycles_to_us(cycles);
+        Self {
+            sec: us / USEC_PER_SEC,
+            usec: us % USEC_PER_SEC,
+        }
+    }
+}
+
+/// Time value exchanged with user space
+#[repr(C)]
+#[derive(Copy, Clone, Debug)]
+pub struct TimeSpec {
+    pub tv_sec: usize,
+    pub tv_nsec: usize,
+}
+
+impl TimeSpec {
+    pub fn zero() -> Self {
+        Self { tv_sec: 0, tv_nsec: 0 }
+    }
+    /// Time since boot
+    pub fn now() -> Self {
+        let us = cycles_to_us(get_time());
+        Self {
+            tv_sec: us / USEC_PER_SEC,
+            tv_nsec: us % USEC_PER_SEC * 1000,
+        }
+    }
+    pub fn from_ms(ms: usize) -> Self {
+        Self {
+            tv_sec: ms / MSEC_PER_SEC,
+            tv_nsec: ms % MSEC_PER_SEC * 1_000_000,
+        }
+    }
+    pub fn to_ms(&self) -> usize {
+        self.tv_sec * MSEC_PER_SEC + self.tv_nsec / 1_000_000
+    }
+}
 
 /// read the `mtime` register
 pub fn get_time() -> usize {
@@ -17,7 +75,92 @@ pub fn get_time_ms() -> usize {
     time::read() / (CLOCK_FREQ / MSEC_PER_SEC)
 }
 
+/// convert timer cycles to milliseconds
+pub fn cycles_to_ms(cycles: usize) -> usize {
+    cycles / (CLOCK_FREQ / MSEC_PER_SEC)
+}
+
+/// convert timer cycles to microseconds
+pub fn cycles_to_us(cycles: usize) -> usize {
+    cycles / (CLOCK_FREQ / USEC_PER_SEC)
+}
+
+/// convert timer cycles to clock ticks, the unit of `times()`
+pub fn cycles_to_ticks(cycles: usize) -> usize {
+    cycles / (CLOCK_FREQ / TICKS_PER_SEC)
+}
+
 /// set the next timer interrupt
 pub fn set_next_trigger() {
     set_timer(get_time() + CLOCK_FREQ / TICKS_PER_SEC);
 }
+
+/// set the timer interrupt to the next expiring timer only,
+/// or turn it off if there is none
+pub fn set_next_deadline() {
+    match next_deadline_ms() {
+        Some(expire_ms) => set_timer(expire_ms * (CLOCK_FREQ / MSEC_PER_SEC)),
+        None => set_timer(usize::MAX),
+    }
+}
+
+/// A task waiting until `expire_ms`
+pub struct TimerCondVar {
+    pub expire_ms: usize,
+    pub task: Arc<TaskControlBlock>,
+}
+
+impl PartialEq for TimerCondVar {
+    fn eq(&self, other: &Self) -> bool {
+        self.expire_ms == other.expire_ms
+    }
+}
+impl Eq for TimerCondVar {}
+impl PartialOrd for TimerCondVar {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for TimerCondVar {
+    /// `BinaryHeap` is a max-heap, the earliest timer compares greatest
+    fn cmp(&self, other: &Self) -> Ordering {
+        other.expire_ms.cmp(&self.expire_ms)
+    }
+}
+
+lazy_static! {
+    static ref TIMERS: UPSafeCell<BinaryHeap<TimerCondVar>> =
+        unsafe { UPSafeCell::new(BinaryHeap::<TimerCondVar>::new()) };
+}
+
+/// Wake up `task` once `expire_ms` has passed
+pub fn add_timer(expire_ms: usize, task: Arc<TaskControlBlock>) {
+    let mut timers = TIMERS.exclusive_access();
+    timers.push(TimerCondVar { expire_ms, task });
+}
+
+/// Cancel the timers of `task`
+pub fn remove_timer(task: &Arc<TaskControlBlock>) {
+    let mut timers = TIMERS.exclusive_access();
+    timers.retain(|condvar| !Arc::ptr_eq(&condvar.task, task));
+}
+
+/// Expiry time of the earliest timer
+pub fn next_deadline_ms() -> Option<usize> {
+    TIMERS.exclusive_access().peek().map(|condvar| condvar.expire_ms)
+}
+
+/// Wake up the tasks whose timers have expired
+pub fn check_timer() {
+    let current_ms = get_time_ms();
+    let mut timers = TIMERS.exclusive_access();
+    while let Some(timer) = timers.peek() {
+        if timer.expire_ms <= current_ms {
+            let timer = timers.pop().unwrap();
+            wakeup_task(timer.task);
+        } else {
+            break;
+        }
+    }
+}
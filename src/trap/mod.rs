@@ -17,9 +17,9 @@ use crate::config::{TRAMPOLINE, TRAP_CONTEXT};
 use crate::{println, debug};
 use crate::syscall::syscall;
 use crate::task::{
-    check_signals_error_of_current, current_add_signal, current_trap_cx, current_user_token, exit_current_and_run_next, handle_signals, suspend_current_and_run_next, SignalFlags
+    account_current, check_signals_error_of_current, current_add_signal, current_trap_cx, current_user_token, exit_current_and_run_next, handle_signals, preempt_current_and_run_next, scheduler_tick, SignalFlags
 };
-use crate::timer::set_next_trigger;
+use crate::timer::{check_timer, set_next_trigger};
 use core::arch::{asm, global_asm};
 use riscv::register::stvec::Stvec;
 use riscv::register::{
@@ -65,6 +65,7 @@ pub fn enable_timer_interrupt() {
 /// handle an interrupt, exception, or system call from user space
 pub fn trap_handler() -> ! {
     set_kernel_trap_entry();
+    account_current(true);
     let scause = scause::read(); // get trap cause
     let stval = stval::read(); // get extra value
     match scause.cause() {
@@ -102,7 +103,10 @@ pub fn trap_handler() -> ! {
             match unsafe {core::mem::transmute(intnum)}{
                 Interrupt::SupervisorTimer => {
                     set_next_trigger();
-                    suspend_current_and_run_next();
+                    check_timer();
+                    if scheduler_tick() {
+                        preempt_current_and_run_next();
+                    }
                 }
                 _ => {
                     panic!(
@@ -132,6 +136,7 @@ pub fn trap_handler() -> ! {
 /// finally, jump to new addr of __restore asm function
 pub fn trap_return() -> ! {
     set_user_trap_entry();
+    account_current(false);
     let trap_cx_ptr = TRAP_CONTEXT;
     let user_satp = current_user_token();
     extern "C" {